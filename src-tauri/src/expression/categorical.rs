@@ -0,0 +1,237 @@
+use std::{cmp::Ordering, collections::HashMap, sync::Arc};
+
+use super::{ExpressionError, ExpressionResult};
+
+/// The declared set of labels for a categorical type.
+///
+/// Labels are stored once here and values refer to them by code, their index
+/// in declaration order. If the categories are ordered, that is also the order
+/// used by `<`, `>` and sorting.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Categories {
+    labels: Vec<String>,
+    codes: HashMap<String, u32>,
+    ordered: bool,
+}
+
+impl Categories {
+    pub fn new(labels: Vec<String>, ordered: bool) -> ExpressionResult<Arc<Self>> {
+        let mut codes = HashMap::with_capacity(labels.len());
+        for (code, label) in labels.iter().enumerate() {
+            if codes.insert(label.clone(), code as u32).is_some() {
+                return Err(ExpressionError::new(
+                    &format!("Duplicate category {:?}", label),
+                    None,
+                ));
+            }
+        }
+        Ok(Arc::new(Categories {
+            labels,
+            codes,
+            ordered,
+        }))
+    }
+
+    pub fn code(&self, label: &str) -> Option<u32> {
+        self.codes.get(label).copied()
+    }
+
+    pub fn label(&self, code: u32) -> Option<&str> {
+        self.labels.get(code as usize).map(String::as_str)
+    }
+
+    pub fn labels(&self) -> &[String] {
+        &self.labels
+    }
+
+    pub fn len(&self) -> usize {
+        self.labels.len()
+    }
+
+    pub fn is_ordered(&self) -> bool {
+        self.ordered
+    }
+}
+
+impl std::fmt::Display for Categories {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{{")?;
+        for (i, label) in self.labels.iter().enumerate() {
+            if i > 0 {
+                write!(f, "{}", if self.ordered { " < " } else { ", " })?;
+            }
+            write!(f, "{:?}", label)?;
+        }
+        write!(f, "}}")
+    }
+}
+
+/// A single categorical value
+#[derive(Debug, Clone)]
+pub struct Category {
+    code: u32,
+    categories: Arc<Categories>,
+}
+
+impl Category {
+    pub fn new(label: &str, categories: &Arc<Categories>) -> Option<Self> {
+        Some(Category {
+            code: categories.code(label)?,
+            categories: categories.clone(),
+        })
+    }
+
+    pub fn from_code(code: u32, categories: &Arc<Categories>) -> Option<Self> {
+        categories.label(code)?;
+        Some(Category {
+            code,
+            categories: categories.clone(),
+        })
+    }
+
+    pub fn code(&self) -> u32 {
+        self.code
+    }
+
+    pub fn label(&self) -> &str {
+        // Codes are checked on construction
+        self.categories.label(self.code).unwrap()
+    }
+
+    pub fn categories(&self) -> &Arc<Categories> {
+        &self.categories
+    }
+
+    fn same_categories(&self, other: &Category) -> bool {
+        Arc::ptr_eq(&self.categories, &other.categories) || self.categories == other.categories
+    }
+
+    /// Orders two values of the same ordered categories
+    pub fn compare(&self, other: &Category) -> Option<Ordering> {
+        if self.categories.ordered && self.same_categories(other) {
+            Some(self.code.cmp(&other.code))
+        } else {
+            None
+        }
+    }
+}
+
+impl PartialEq for Category {
+    fn eq(&self, other: &Self) -> bool {
+        self.code == other.code && self.same_categories(other)
+    }
+}
+
+/// A dictionary-encoded list of categorical values, each stored as its code.
+/// Nulls are not represented here, the owner of the list tracks them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CategoricalVec {
+    categories: Arc<Categories>,
+    codes: Vec<u32>,
+}
+
+impl CategoricalVec {
+    pub fn new(categories: Arc<Categories>) -> Self {
+        CategoricalVec {
+            categories,
+            codes: Vec::new(),
+        }
+    }
+
+    /// Encodes a list of labels, failing on the first unknown label
+    pub fn encode<'a>(
+        categories: Arc<Categories>,
+        labels: impl IntoIterator<Item = &'a str>,
+    ) -> ExpressionResult<Self> {
+        let mut encoded = CategoricalVec::new(categories);
+        for label in labels {
+            let code = encoded.code(label)?;
+            encoded.codes.push(code);
+        }
+        Ok(encoded)
+    }
+
+    fn code(&self, label: &str) -> ExpressionResult<u32> {
+        self.categories.code(label).ok_or_else(|| {
+            ExpressionError::new(
+                &format!("{:?} is not one of {}", label, self.categories),
+                None,
+            )
+        })
+    }
+
+    fn check(&self, category: &Category) -> ExpressionResult<u32> {
+        if *category.categories() != self.categories {
+            return Err(ExpressionError::new(
+                &format!("{:?} is not one of {}", category.label(), self.categories),
+                None,
+            ));
+        }
+        Ok(category.code)
+    }
+
+    pub fn insert(&mut self, index: usize, category: &Category) -> ExpressionResult<()> {
+        let code = self.check(category)?;
+        self.codes.insert(index, code);
+        Ok(())
+    }
+
+    /// Inserts a placeholder for a null, which the owner tracks
+    pub fn insert_null(&mut self, index: usize) {
+        self.codes.insert(index, 0);
+    }
+
+    pub fn set(&mut self, index: usize, category: &Category) -> ExpressionResult<()> {
+        let code = self.check(category)?;
+        self.codes[index] = code;
+        Ok(())
+    }
+
+    /// Overwrites a value with the placeholder for a null
+    pub fn set_null(&mut self, index: usize) {
+        self.codes[index] = 0;
+    }
+
+    /// Puts the codes in a new order, code `i` taking code `order[i]`
+    pub fn permute(&mut self, order: &[usize]) {
+        self.codes = order.iter().map(|index| self.codes[*index]).collect();
+    }
+
+    pub fn remove(&mut self, index: usize) -> u32 {
+        self.codes.remove(index)
+    }
+
+    pub fn get(&self, index: usize) -> Option<Category> {
+        self.codes.get(index).map(|code| Category {
+            code: *code,
+            categories: self.categories.clone(),
+        })
+    }
+
+    pub fn codes(&self) -> &[u32] {
+        &self.codes
+    }
+
+    pub fn len(&self) -> usize {
+        self.codes.len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn encoding() {
+        let regions = Categories::new(
+            vec!["EU".to_string(), "UK".to_string(), "US".to_string()],
+            false,
+        )
+        .unwrap();
+        let encoded = CategoricalVec::encode(regions.clone(), ["US", "EU", "US"]).unwrap();
+        assert_eq!(encoded.codes(), &[2, 0, 2]);
+        assert_eq!(encoded.get(1).unwrap().label(), "EU");
+        assert!(CategoricalVec::encode(regions, ["EU", "APAC"]).is_err());
+        assert!(Categories::new(vec!["a".to_string(), "a".to_string()], false).is_err());
+    }
+}
@@ -0,0 +1,79 @@
+use std::{ops::Range, sync::Arc};
+
+use super::{ColumnRef, Value, ValueType};
+
+/// What an expression reads of a column. The model's columns provide it, so
+/// expressions never depend on how columns are stored.
+pub trait ColumnView: std::fmt::Debug + Send + Sync {
+    fn name(&self) -> &str;
+
+    fn value_type(&self) -> &ValueType;
+
+    fn len(&self) -> usize;
+
+    /// The value at `row`, or `None` past the last row
+    fn get(&self, row: usize) -> Option<Value>;
+
+    fn iter(&self) -> Box<dyn Iterator<Item = Value> + '_>;
+
+    /// The rows in `rows`, as a column of their own
+    fn slice(&self, rows: Range<usize>) -> Arc<dyn ColumnView>;
+
+    /// The error in the earliest row holding one
+    fn first_error(&self) -> Option<Value>;
+
+    /// The number of rows holding a value, neither null nor an error
+    fn count(&self) -> usize;
+
+    /// The exact sum of an Integer or Decimal column, in units of its scale,
+    /// if it is kept
+    fn units(&self) -> Option<i128>;
+}
+
+/// Columns are equal when they have the same name, type and values
+impl PartialEq for dyn ColumnView {
+    fn eq(&self, other: &Self) -> bool {
+        self.name() == other.name()
+            && self.value_type() == other.value_type()
+            && self.iter().eq(other.iter())
+    }
+}
+
+/// Resolves the names used in an expression.
+///
+/// The type checker asks for types, evaluation asks for values. Both return
+/// `None` for names the context does not know about.
+pub trait Context {
+    fn variable(&self, name: &str) -> Option<Value>;
+
+    fn variable_type(&self, name: &str) -> Option<ValueType>;
+
+    fn column(&self, _reference: &ColumnRef) -> Option<Arc<dyn ColumnView>> {
+        None
+    }
+
+    /// The type of the values in a column
+    fn column_type(&self, reference: &ColumnRef) -> Option<ValueType> {
+        self.column(reference)
+            .map(|column| column.value_type().clone())
+    }
+
+    /// The row being evaluated, if any. Columns used outside of aggregations
+    /// read their value at this row.
+    fn row(&self) -> Option<usize> {
+        None
+    }
+}
+
+/// A context with nothing in it, for expressions made only of literals
+pub struct EmptyContext;
+
+impl Context for EmptyContext {
+    fn variable(&self, _name: &str) -> Option<Value> {
+        None
+    }
+
+    fn variable_type(&self, _name: &str) -> Option<ValueType> {
+        None
+    }
+}
@@ -0,0 +1,284 @@
+use std::cmp::Ordering;
+
+/// The largest scale a decimal can be declared with
+pub const MAX_SCALE: u32 = 18;
+
+/// A fixed-point decimal number, stored as an integer number of
+/// `10^-scale` units.
+///
+/// All arithmetic is exact apart from division, which rounds half away from
+/// zero. Operations return `None` on overflow rather than losing precision.
+#[derive(Debug, Clone, Copy)]
+pub struct Decimal {
+    units: i128,
+    scale: u32,
+}
+
+/// `10^exponent`, if it fits
+fn pow10(exponent: u32) -> Option<i128> {
+    10i128.checked_pow(exponent)
+}
+
+/// Divides, rounding half away from zero
+fn div_round(numerator: i128, denominator: i128) -> Option<i128> {
+    let quotient = numerator.checked_div(denominator)?;
+    let remainder = numerator.checked_rem(denominator)?;
+    if remainder.checked_abs()?.checked_mul(2)? >= denominator.checked_abs()? {
+        if (numerator < 0) == (denominator < 0) {
+            quotient.checked_add(1)
+        } else {
+            quotient.checked_sub(1)
+        }
+    } else {
+        Some(quotient)
+    }
+}
+
+impl Decimal {
+    pub fn new(units: i128, scale: u32) -> Self {
+        Decimal { units, scale }
+    }
+
+    pub fn from_integer(value: i64, scale: u32) -> Option<Self> {
+        Some(Decimal {
+            units: (value as i128).checked_mul(pow10(scale)?)?,
+            scale,
+        })
+    }
+
+    /// The nearest decimal with the given scale, if `value` is finite and in
+    /// range
+    pub fn from_f64(value: f64, scale: u32) -> Option<Self> {
+        let units = (value * pow10(scale)? as f64).round();
+        if units.is_finite() && units.abs() < i128::MAX as f64 {
+            Some(Decimal {
+                units: units as i128,
+                scale,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Parses a plain decimal string such as `-12.50`, rounding to `scale`.
+    /// Exponents are not accepted. Digits past the first one rounded off
+    /// cannot change the result, so they are ignored.
+    pub fn parse(source: &str, scale: u32) -> Option<Self> {
+        let (negative, digits) = match source.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, source.strip_prefix('+').unwrap_or(source)),
+        };
+        let (whole, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+        if whole.is_empty() && fraction.is_empty() {
+            return None;
+        }
+        if !whole
+            .chars()
+            .chain(fraction.chars())
+            .all(|c| c.is_ascii_digit())
+        {
+            return None;
+        }
+        let fraction = &fraction[..fraction.len().min(scale as usize + 1)];
+        let mut units: i128 = 0;
+        for c in whole.chars().chain(fraction.chars()) {
+            units = units
+                .checked_mul(10)?
+                .checked_add(c.to_digit(10)? as i128)?;
+        }
+        let parsed = Decimal {
+            units: if negative { -units } else { units },
+            scale: fraction.len() as u32,
+        };
+        parsed.rescale(scale)
+    }
+
+    pub fn scale(&self) -> u32 {
+        self.scale
+    }
+
+    pub fn units(&self) -> i128 {
+        self.units
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.units == 0
+    }
+
+    /// The same value with a different scale, rounding if the scale shrinks
+    pub fn rescale(&self, scale: u32) -> Option<Self> {
+        let units = match scale.cmp(&self.scale) {
+            Ordering::Equal => self.units,
+            Ordering::Greater => self.units.checked_mul(pow10(scale - self.scale)?)?,
+            Ordering::Less => div_round(self.units, pow10(self.scale - scale)?)?,
+        };
+        Some(Decimal { units, scale })
+    }
+
+    pub fn to_f64(self) -> f64 {
+        self.units as f64 / 10f64.powi(self.scale as i32)
+    }
+
+    /// The value as an integer, if it has no fractional part
+    pub fn to_integer(self) -> Option<i64> {
+        let divisor = match pow10(self.scale) {
+            Some(divisor) => divisor,
+            // Only zero is a whole number at a scale this large
+            None if self.units == 0 => return Some(0),
+            None => return None,
+        };
+        if self.units % divisor != 0 {
+            return None;
+        }
+        i64::try_from(self.units / divisor).ok()
+    }
+
+    fn aligned(&self, other: &Decimal) -> Option<(i128, i128, u32)> {
+        let scale = self.scale.max(other.scale);
+        Some((
+            self.rescale(scale)?.units,
+            other.rescale(scale)?.units,
+            scale,
+        ))
+    }
+
+    pub fn checked_add(&self, other: &Decimal) -> Option<Self> {
+        let (a, b, scale) = self.aligned(other)?;
+        Some(Decimal::new(a.checked_add(b)?, scale))
+    }
+
+    pub fn checked_sub(&self, other: &Decimal) -> Option<Self> {
+        let (a, b, scale) = self.aligned(other)?;
+        Some(Decimal::new(a.checked_sub(b)?, scale))
+    }
+
+    /// Multiplies exactly, so the result's scale is the sum of both scales.
+    /// Scales past `MAX_SCALE` are rounded off.
+    pub fn checked_mul(&self, other: &Decimal) -> Option<Self> {
+        let product = Decimal::new(
+            self.units.checked_mul(other.units)?,
+            self.scale + other.scale,
+        );
+        product.rescale(product.scale.min(MAX_SCALE))
+    }
+
+    /// Divides, rounding to the larger of the two scales
+    pub fn checked_div(&self, other: &Decimal) -> Option<Self> {
+        let (a, b, scale) = self.aligned(other)?;
+        Some(Decimal::new(
+            div_round(a.checked_mul(pow10(scale)?)?, b)?,
+            scale,
+        ))
+    }
+
+    pub fn checked_rem(&self, other: &Decimal) -> Option<Self> {
+        let (a, b, scale) = self.aligned(other)?;
+        Some(Decimal::new(a.checked_rem(b)?, scale))
+    }
+
+    pub fn checked_neg(&self) -> Option<Self> {
+        Some(Decimal::new(self.units.checked_neg()?, self.scale))
+    }
+
+    /// Divides by a count, rounding to this decimal's scale
+    pub fn checked_div_count(&self, count: usize) -> Option<Self> {
+        Some(Decimal::new(
+            div_round(self.units, count as i128)?,
+            self.scale,
+        ))
+    }
+}
+
+impl PartialEq for Decimal {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Decimal {}
+
+impl PartialOrd for Decimal {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Decimal {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match self.aligned(other) {
+            Some((a, b, _)) => a.cmp(&b),
+            // Only reachable when one side cannot be widened, which means it
+            // is the larger in magnitude
+            None => self.to_f64().total_cmp(&other.to_f64()),
+        }
+    }
+}
+
+impl std::fmt::Display for Decimal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let magnitude = self.units.unsigned_abs();
+        let (whole, fraction) = match 10u128.checked_pow(self.scale) {
+            Some(divisor) => (magnitude / divisor, magnitude % divisor),
+            None => (0, magnitude),
+        };
+        let sign = if self.units < 0 { "-" } else { "" };
+        if self.scale == 0 {
+            write!(f, "{}{}", sign, magnitude)
+        } else {
+            write!(
+                f,
+                "{}{}.{:0>width$}",
+                sign,
+                whole,
+                fraction,
+                width = self.scale as usize
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn exact_arithmetic() {
+        let a = Decimal::parse("0.1", 2).unwrap();
+        let b = Decimal::parse("0.2", 2).unwrap();
+        assert_eq!(
+            a.checked_add(&b).unwrap(),
+            Decimal::parse("0.3", 2).unwrap()
+        );
+        assert_eq!(a.checked_add(&b).unwrap().to_string(), "0.30");
+
+        let price = Decimal::parse("19.99", 2).unwrap();
+        let qty = Decimal::from_integer(3, 0).unwrap();
+        assert_eq!(price.checked_mul(&qty).unwrap().to_string(), "59.97");
+
+        let third = Decimal::from_integer(1, 2)
+            .unwrap()
+            .checked_div(&Decimal::from_integer(3, 2).unwrap())
+            .unwrap();
+        assert_eq!(third.to_string(), "0.33");
+        assert_eq!(Decimal::parse("-2.345", 2).unwrap().to_string(), "-2.35");
+        assert_eq!(Decimal::from_f64(0.1, 3).unwrap().to_string(), "0.100");
+    }
+
+    #[test]
+    fn overflow() {
+        let big = Decimal::new(i128::MAX, 0);
+        assert!(big.checked_add(&Decimal::new(1, 0)).is_none());
+        assert!(big.rescale(2).is_none());
+        assert!(Decimal::parse("abc", 2).is_none());
+
+        // Scales whose units do not fit
+        assert!(Decimal::from_integer(1, 40).is_none());
+        assert!(Decimal::new(1, 0).rescale(40).is_none());
+        assert!(Decimal::new(1, 45).rescale(2).is_none());
+        let long = format!("0.{}", "4".repeat(2) + &"9".repeat(40));
+        assert_eq!(Decimal::parse(&long, 2).unwrap().to_string(), "0.45");
+        assert!(Decimal::parse(&long, 40).is_none());
+        assert_eq!(Decimal::new(5, 40).to_string(), format!("0.{:0>40}", 5));
+        assert_eq!(Decimal::new(0, 40).to_integer(), Some(0));
+    }
+}
@@ -1,13 +1,45 @@
+use std::collections::BTreeMap;
+
 use super::{
     parser::{ParseError, Position},
-    ValueType,
+    Categories, ColumnRef, Value, ValueType,
 };
 
-#[derive(Debug)]
+/// The kinds of error a single evaluation can produce.
+///
+/// These are carried by `Value::Error`, so one bad row does not stop the rest
+/// of a column from evaluating.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ErrorKind {
+    DivByZero,
+    Domain,
+    NotFound,
+    Type,
+    Overflow,
+    /// A value kept out of a column for breaking its validation rules
+    Invalid,
+}
+
+impl std::fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ErrorKind::DivByZero => write!(f, "#DIV/0"),
+            ErrorKind::Domain => write!(f, "#DOMAIN"),
+            ErrorKind::NotFound => write!(f, "#NOT_FOUND"),
+            ErrorKind::Type => write!(f, "#TYPE"),
+            ErrorKind::Overflow => write!(f, "#OVERFLOW"),
+            ErrorKind::Invalid => write!(f, "#INVALID"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct ExpressionError {
     message: String,
     position: Option<Position>,
-    source: Option<ParseError>,
+    kind: Option<ErrorKind>,
+    // Boxed so that results carrying this error stay small
+    source: Option<Box<ParseError>>,
 }
 
 pub type ExpressionResult<T> = Result<T, ExpressionError>;
@@ -21,8 +53,12 @@ impl std::fmt::Display for ExpressionError {
                 f,
                 " [{:0>3}:{:0>3}] {}",
                 position.line, position.column, self.message
-            ),
-            None => write!(f, "{}", self.message),
+            )?,
+            None => write!(f, "{}", self.message)?,
+        }
+        match &self.source {
+            Some(source) => write!(f, ":{}", source),
+            None => Ok(()),
         }
     }
 }
@@ -32,6 +68,7 @@ impl ExpressionError {
         ExpressionError {
             message: message.to_string(),
             position: position.cloned(),
+            kind: None,
             source: None,
         }
     }
@@ -40,15 +77,208 @@ impl ExpressionError {
         ExpressionError {
             message: "Expected at least one argument".to_string(),
             position: None,
+            kind: Some(ErrorKind::Type),
             source: None,
         }
     }
 
     pub fn type_error(expected: &ValueType, found: &ValueType, position: Position) -> Self {
         ExpressionError {
-            message: format!("Type {:?} not valid, require {:?}", expected, found),
+            message: format!("Type {} not valid, require {}", found, expected),
+            position: Some(position),
+            kind: Some(ErrorKind::Type),
+            source: None,
+        }
+    }
+
+    pub fn type_error_message(message: &str, position: Position) -> Self {
+        ExpressionError {
+            message: message.to_string(),
+            position: Some(position),
+            kind: Some(ErrorKind::Type),
+            source: None,
+        }
+    }
+
+    pub fn operand_error(
+        op: &str,
+        left: &ValueType,
+        right: &ValueType,
+        position: Position,
+    ) -> Self {
+        ExpressionError {
+            message: format!("Cannot apply {} to {} and {}", op, left, right),
+            position: Some(position),
+            kind: Some(ErrorKind::Type),
+            source: None,
+        }
+    }
+
+    pub fn unknown_variable(name: &str, position: Position) -> Self {
+        ExpressionError {
+            message: format!("Unknown variable {}", name),
+            position: Some(position),
+            kind: Some(ErrorKind::NotFound),
+            source: None,
+        }
+    }
+
+    pub fn unknown_column(reference: &ColumnRef, position: Position) -> Self {
+        ExpressionError {
+            message: format!("Unknown column {}", reference),
+            position: Some(position),
+            kind: Some(ErrorKind::NotFound),
+            source: None,
+        }
+    }
+
+    /// A column was used as a single value where there is no current row
+    pub fn no_current_row(reference: &ColumnRef, position: Position) -> Self {
+        ExpressionError {
+            message: format!(
+                "Column {} has no current row here, use an aggregate such as sum",
+                reference
+            ),
+            position: Some(position),
+            kind: Some(ErrorKind::Type),
+            source: None,
+        }
+    }
+
+    pub fn unknown_function(name: &str, position: Position) -> Self {
+        ExpressionError {
+            message: format!("Unknown function {}", name),
+            position: Some(position),
+            kind: Some(ErrorKind::NotFound),
+            source: None,
+        }
+    }
+
+    pub fn unknown_category(label: &str, categories: &Categories, position: Position) -> Self {
+        ExpressionError {
+            message: format!("{:?} is not one of {}", label, categories),
+            position: Some(position),
+            kind: Some(ErrorKind::NotFound),
+            source: None,
+        }
+    }
+
+    pub fn argument_count(name: &str, expected: &str, found: usize, position: Position) -> Self {
+        ExpressionError {
+            message: format!(
+                "Function {} takes {} argument(s), found {}",
+                name, expected, found
+            ),
+            position: Some(position),
+            kind: Some(ErrorKind::Type),
+            source: None,
+        }
+    }
+
+    pub fn div_by_zero(position: Position) -> Self {
+        ExpressionError {
+            message: "Division by zero".to_string(),
+            position: Some(position),
+            kind: Some(ErrorKind::DivByZero),
+            source: None,
+        }
+    }
+
+    pub fn domain(message: &str, position: Position) -> Self {
+        ExpressionError {
+            message: message.to_string(),
+            position: Some(position),
+            kind: Some(ErrorKind::Domain),
+            source: None,
+        }
+    }
+
+    /// A value quarantined by a column's validation rules, with `message`
+    /// saying what the value was and which rule it broke
+    pub fn invalid(message: &str) -> Self {
+        ExpressionError {
+            message: message.to_string(),
+            position: None,
+            kind: Some(ErrorKind::Invalid),
+            source: None,
+        }
+    }
+
+    /// An error of a kind, as read back from a file
+    pub fn with_kind(kind: ErrorKind, message: &str) -> Self {
+        ExpressionError {
+            message: message.to_string(),
+            position: None,
+            kind: Some(kind),
+            source: None,
+        }
+    }
+
+    pub fn overflow(position: Position) -> Self {
+        ExpressionError {
+            message: "Result is too large to represent".to_string(),
             position: Some(position),
+            kind: Some(ErrorKind::Overflow),
             source: None,
         }
     }
+
+    /// The per-row error kind, if this error can be caught as a value
+    pub fn kind(&self) -> Option<ErrorKind> {
+        self.kind
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn position(&self) -> Option<&Position> {
+        self.position.as_ref()
+    }
+}
+
+impl From<ParseError> for ExpressionError {
+    fn from(error: ParseError) -> Self {
+        ExpressionError {
+            message: "Parse error".to_string(),
+            position: error.position,
+            kind: None,
+            source: Some(Box::new(error)),
+        }
+    }
+}
+
+/// A summary of the error values in a column.
+///
+/// Errors are counted per kind, and the first error of each kind is kept with
+/// its row so the UI can point at the problem.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ErrorReport {
+    pub total: usize,
+    pub counts: BTreeMap<ErrorKind, usize>,
+    pub first: BTreeMap<ErrorKind, (usize, ExpressionError)>,
+}
+
+impl ErrorReport {
+    pub fn from_values<'a>(values: impl IntoIterator<Item = &'a Value>) -> Self {
+        let mut report = ErrorReport::default();
+        for (row, value) in values.into_iter().enumerate() {
+            report.record(row, value);
+        }
+        report
+    }
+
+    pub fn record(&mut self, row: usize, value: &Value) {
+        if let Value::Error(kind, error) = value {
+            self.total += 1;
+            *self.counts.entry(*kind).or_insert(0) += 1;
+            self.first
+                .entry(*kind)
+                .or_insert_with(|| (row, error.as_ref().clone()));
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.total == 0
+    }
 }
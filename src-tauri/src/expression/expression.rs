@@ -1,9 +1,17 @@
-use super::{error::ExpressionResult, parser::Node, Function};
+use std::sync::Arc;
 
+use super::{
+    error::{ExpressionError, ExpressionResult},
+    parser::{ast::quote, Assembler, ColumnRef, Node, Position, RowBound, RowSelector},
+    Category, ColumnView, Context, EmptyContext, Function, Value, ValueType, MAX_SCALE,
+};
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Expression {
     root: ExprNode,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
 enum BinaryOpType {
     Add,
     Sub,
@@ -19,6 +27,7 @@ enum BinaryOpType {
     Ge,
     And,
     Or,
+    In,
 }
 
 impl BinaryOpType {
@@ -38,10 +47,449 @@ impl BinaryOpType {
             super::parser::ast::BinaryOpType::Ge => Self::Ge,
             super::parser::ast::BinaryOpType::And => Self::And,
             super::parser::ast::BinaryOpType::Or => Self::Or,
+            super::parser::ast::BinaryOpType::In => Self::In,
+        }
+    }
+
+    fn symbol(&self) -> &'static str {
+        match self {
+            Self::Add => "+",
+            Self::Sub => "-",
+            Self::Mul => "*",
+            Self::Div => "/",
+            Self::Mod => "%",
+            Self::Pow => "^",
+            Self::Eq => "==",
+            Self::Ne => "!=",
+            Self::Lt => "<",
+            Self::Le => "<=",
+            Self::Gt => ">",
+            Self::Ge => ">=",
+            Self::And => "&",
+            Self::Or => "|",
+            Self::In => "in",
+        }
+    }
+
+    /// How tightly the operator binds, matching the grammar
+    fn precedence(&self) -> u8 {
+        match self {
+            Self::Or => 1,
+            Self::And => 2,
+            Self::Eq | Self::Ne | Self::Lt | Self::Le | Self::Gt | Self::Ge | Self::In => 4,
+            Self::Add | Self::Sub => 5,
+            Self::Mul | Self::Div | Self::Mod => 6,
+            Self::Pow => 7,
+        }
+    }
+
+    fn is_arithmetic(&self) -> bool {
+        matches!(
+            self,
+            Self::Add | Self::Sub | Self::Mul | Self::Div | Self::Mod | Self::Pow
+        )
+    }
+
+    fn is_comparison(&self) -> bool {
+        matches!(
+            self,
+            Self::Eq | Self::Ne | Self::Lt | Self::Le | Self::Gt | Self::Ge
+        )
+    }
+
+    /// The type of `left op right`. Any nullable operand makes the result
+    /// nullable, as nulls propagate through every operator.
+    ///
+    /// `constants` holds the value of each operand that does not depend on
+    /// the context, so labels compared against categorical values can be
+    /// checked against the declared categories.
+    fn result_type(
+        &self,
+        left: &ValueType,
+        right: &ValueType,
+        constants: [Option<Value>; 2],
+        position: Position,
+    ) -> ExpressionResult<ValueType> {
+        let error = || ExpressionError::operand_error(self.symbol(), left, right, position);
+        let is = |t: &ValueType, base: &ValueType| t.base() == base || *t == ValueType::Null;
+        let nullable = |base: ValueType| {
+            if left.is_nullable() || right.is_nullable() {
+                base.nullable()
+            } else {
+                base
+            }
+        };
+        // Arithmetic on arrays applies to each element
+        if self.is_arithmetic() {
+            let element = match (left.base(), right.base()) {
+                (ValueType::Array(a), ValueType::Array(b)) => {
+                    Some(self.result_type(a, b, [None, None], position))
+                }
+                (ValueType::Array(a), _) => {
+                    Some(self.result_type(a, right, [None, None], position))
+                }
+                (_, ValueType::Array(b)) => Some(self.result_type(left, b, [None, None], position)),
+                _ => None,
+            };
+            if let Some(element) = element {
+                let element = element.map_err(|_| error())?;
+                return Ok(nullable(ValueType::Array(Box::new(element))));
+            }
+        }
+        if *self == Self::In {
+            return self.membership_type(left, right, constants, position);
+        }
+        let base = match self {
+            Self::Add | Self::Sub | Self::Mul | Self::Div | Self::Mod | Self::Pow => {
+                let unified = left.unify(right).ok_or_else(error)?;
+                match (self, unified.base()) {
+                    (_, ValueType::Number | ValueType::Null) => ValueType::Number,
+                    (_, ValueType::Integer) => ValueType::Integer,
+                    (Self::Pow, ValueType::Decimal(_)) => return Err(error()),
+                    // Products are exact, so they need the digits of both sides
+                    (Self::Mul, ValueType::Decimal(_)) => {
+                        let scale = |t: &ValueType| match t.base() {
+                            ValueType::Decimal(scale) => *scale,
+                            _ => 0,
+                        };
+                        ValueType::Decimal((scale(left) + scale(right)).min(MAX_SCALE))
+                    }
+                    (_, ValueType::Decimal(scale)) => ValueType::Decimal(*scale),
+                    _ => return Err(error()),
+                }
+            }
+            _ if self.is_comparison()
+                && [left, right]
+                    .iter()
+                    .any(|t| matches!(t.base(), ValueType::Categorical(_))) =>
+            {
+                self.check_categories([left, right], constants, position)?;
+                ValueType::Boolean
+            }
+            Self::Eq | Self::Ne => {
+                left.unify(right).ok_or_else(error)?;
+                ValueType::Boolean
+            }
+            Self::Lt | Self::Le | Self::Gt | Self::Ge => {
+                let unified = left.unify(right).ok_or_else(error)?;
+                if !(unified.is_numeric()
+                    || matches!(unified.base(), ValueType::String | ValueType::Null))
+                {
+                    return Err(error());
+                }
+                ValueType::Boolean
+            }
+            Self::And | Self::Or => {
+                if !is(left, &ValueType::Boolean) || !is(right, &ValueType::Boolean) {
+                    return Err(error());
+                }
+                ValueType::Boolean
+            }
+            Self::In => unreachable!(),
+        };
+        Ok(nullable(base))
+    }
+
+    /// The type of `left in right`, where `right` is an array of values
+    /// comparable to `left`. Labels in a constant array are checked against
+    /// the categories of a categorical `left`.
+    fn membership_type(
+        &self,
+        left: &ValueType,
+        right: &ValueType,
+        constants: [Option<Value>; 2],
+        position: Position,
+    ) -> ExpressionResult<ValueType> {
+        let error = || ExpressionError::operand_error(self.symbol(), left, right, position);
+        let element = match right.base() {
+            ValueType::Array(element) => element.as_ref(),
+            ValueType::Null => &ValueType::Null,
+            _ => return Err(error()),
+        };
+        match (left.base(), element.base()) {
+            (ValueType::Categorical(categories), ValueType::String) => {
+                if let Some(Value::Array(labels)) = &constants[1] {
+                    for label in labels {
+                        if let Value::String(label) = label {
+                            if categories.code(label).is_none() {
+                                return Err(ExpressionError::unknown_category(
+                                    label, categories, position,
+                                ));
+                            }
+                        }
+                    }
+                }
+            }
+            _ => {
+                left.unify(element).ok_or_else(error)?;
+            }
+        }
+        // Missing values are unknown if the array holds a null
+        Ok(
+            if left.is_nullable() || right.is_nullable() || element.is_nullable() {
+                ValueType::Boolean.nullable()
+            } else {
+                ValueType::Boolean
+            },
+        )
+    }
+
+    /// Checks a comparison involving a categorical operand. The other operand
+    /// must be of the same categories, or a constant label from them, and
+    /// ordering comparisons need ordered categories.
+    fn check_categories(
+        &self,
+        types: [&ValueType; 2],
+        constants: [Option<Value>; 2],
+        position: Position,
+    ) -> ExpressionResult<()> {
+        let error = || ExpressionError::operand_error(self.symbol(), types[0], types[1], position);
+        let categories = types
+            .iter()
+            .find_map(|t| match t.base() {
+                ValueType::Categorical(categories) => Some(categories.clone()),
+                _ => None,
+            })
+            .unwrap();
+        if !matches!(self, Self::Eq | Self::Ne) && !categories.is_ordered() {
+            return Err(ExpressionError::new(
+                &format!("Cannot order unordered categories {}", categories),
+                Some(&position),
+            ));
+        }
+        for (t, constant) in types.into_iter().zip(constants) {
+            match (t.base(), constant) {
+                (ValueType::Categorical(other), _) if *other == categories => {}
+                (ValueType::Null, _) => {}
+                (ValueType::String, Some(Value::String(label))) => {
+                    if categories.code(&label).is_none() {
+                        return Err(ExpressionError::unknown_category(
+                            &label,
+                            &categories,
+                            position,
+                        ));
+                    }
+                }
+                _ => return Err(error()),
+            }
+        }
+        Ok(())
+    }
+
+    /// Applies the operator. `&` and `|` use three-valued logic, so a known
+    /// operand can decide the result even when the other is null. Every other
+    /// operator returns null if either operand is null.
+    fn apply(&self, left: Value, right: Value, position: Position) -> ExpressionResult<Value> {
+        let error = |left: &Value, right: &Value| {
+            ExpressionError::operand_error(
+                self.symbol(),
+                &left.value_type(),
+                &right.value_type(),
+                position,
+            )
+        };
+        match self {
+            Self::And => {
+                return match (&left, &right) {
+                    (Value::Boolean(false), Value::Boolean(_) | Value::Null)
+                    | (Value::Boolean(_) | Value::Null, Value::Boolean(false)) => {
+                        Ok(Value::Boolean(false))
+                    }
+                    (Value::Boolean(true), Value::Boolean(true)) => Ok(Value::Boolean(true)),
+                    (Value::Boolean(_) | Value::Null, Value::Boolean(_) | Value::Null) => {
+                        Ok(Value::Null)
+                    }
+                    _ => Err(error(&left, &right)),
+                }
+            }
+            Self::Or => {
+                return match (&left, &right) {
+                    (Value::Boolean(true), Value::Boolean(_) | Value::Null)
+                    | (Value::Boolean(_) | Value::Null, Value::Boolean(true)) => {
+                        Ok(Value::Boolean(true))
+                    }
+                    (Value::Boolean(false), Value::Boolean(false)) => Ok(Value::Boolean(false)),
+                    (Value::Boolean(_) | Value::Null, Value::Boolean(_) | Value::Null) => {
+                        Ok(Value::Null)
+                    }
+                    _ => Err(error(&left, &right)),
+                }
+            }
+            Self::In => return Self::contains(left, right, position),
+            _ => {}
+        }
+        if left.is_null() || right.is_null() {
+            return Ok(Value::Null);
+        }
+        let (left, right) = match (left, right) {
+            (Value::Array(a), Value::Array(b)) if self.is_arithmetic() => {
+                if a.len() != b.len() {
+                    return Err(ExpressionError::domain(
+                        &format!("Arrays of {} and {} elements", a.len(), b.len()),
+                        position,
+                    ));
+                }
+                return a
+                    .into_iter()
+                    .zip(b)
+                    .map(|(a, b)| self.apply(a, b, position))
+                    .collect::<ExpressionResult<_>>()
+                    .map(Value::Array);
+            }
+            (Value::Array(a), b) if self.is_arithmetic() => {
+                return a
+                    .into_iter()
+                    .map(|a| self.apply(a, b.clone(), position))
+                    .collect::<ExpressionResult<_>>()
+                    .map(Value::Array);
+            }
+            (a, Value::Array(b)) if self.is_arithmetic() => {
+                return b
+                    .into_iter()
+                    .map(|b| self.apply(a.clone(), b, position))
+                    .collect::<ExpressionResult<_>>()
+                    .map(Value::Array);
+            }
+            operands => operands,
+        };
+        // Labels compared against a categorical value are looked up in its
+        // categories, so both sides compare by code
+        let label = |label: &str, category: &Category| {
+            Category::new(label, category.categories())
+                .map(Value::Categorical)
+                .ok_or_else(|| {
+                    ExpressionError::unknown_category(label, category.categories(), position)
+                })
+        };
+        let (left, right) = match (left, right) {
+            (Value::Categorical(a), Value::String(b)) if self.is_comparison() => {
+                let b = label(&b, &a)?;
+                (Value::Categorical(a), b)
+            }
+            (Value::String(a), Value::Categorical(b)) if self.is_comparison() => {
+                (label(&a, &b)?, Value::Categorical(b))
+            }
+            operands => operands,
+        };
+        let zero = match &right {
+            Value::Number(b) => *b == 0.0,
+            Value::Integer(b) => *b == 0,
+            Value::Decimal(b) => b.is_zero(),
+            _ => false,
+        };
+        if zero && matches!(self, Self::Div | Self::Mod) {
+            return Err(ExpressionError::div_by_zero(position));
+        }
+        let overflow = || ExpressionError::overflow(position);
+        let result = match (self, &left, &right) {
+            (Self::Add, Value::Number(a), Value::Number(b)) => Value::Number(a + b),
+            (Self::Sub, Value::Number(a), Value::Number(b)) => Value::Number(a - b),
+            (Self::Mul, Value::Number(a), Value::Number(b)) => Value::Number(a * b),
+            (Self::Div, Value::Number(a), Value::Number(b)) => Value::Number(a / b),
+            (Self::Mod, Value::Number(a), Value::Number(b)) => Value::Number(a % b),
+            (Self::Pow, Value::Number(a), Value::Number(b)) => Value::Number(a.powf(*b)),
+            (Self::Pow, Value::Integer(_), Value::Integer(b)) if *b < 0 => {
+                return Err(ExpressionError::domain(
+                    &format!("Negative integer exponent {}", b),
+                    position,
+                ))
+            }
+            (_, Value::Integer(a), Value::Integer(b))
+                if !matches!(
+                    self,
+                    Self::Eq | Self::Ne | Self::Lt | Self::Le | Self::Gt | Self::Ge
+                ) =>
+            {
+                Value::Integer(
+                    match self {
+                        Self::Add => a.checked_add(*b),
+                        Self::Sub => a.checked_sub(*b),
+                        Self::Mul => a.checked_mul(*b),
+                        // Integer division truncates towards zero
+                        Self::Div => a.checked_div(*b),
+                        Self::Mod => a.checked_rem(*b),
+                        _ => u32::try_from(*b).ok().and_then(|b| a.checked_pow(b)),
+                    }
+                    .ok_or_else(overflow)?,
+                )
+            }
+            (
+                Self::Add | Self::Sub | Self::Mul | Self::Div | Self::Mod,
+                Value::Decimal(a),
+                Value::Decimal(b),
+            ) => Value::Decimal(
+                match self {
+                    Self::Add => a.checked_add(b),
+                    Self::Sub => a.checked_sub(b),
+                    Self::Mul => a.checked_mul(b),
+                    Self::Div => a.checked_div(b),
+                    _ => a.checked_rem(b),
+                }
+                .ok_or_else(overflow)?,
+            ),
+            (Self::Eq | Self::Ne, a, b) if a.value_type().unify(&b.value_type()).is_some() => {
+                Value::Boolean((a == b) == (*self == Self::Eq))
+            }
+            (Self::Lt | Self::Le | Self::Gt | Self::Ge, a, b) => {
+                let ordering = match (a, b) {
+                    (Value::Boolean(_), Value::Boolean(_)) => return Err(error(&left, &right)),
+                    (a, b) => a.compare(b).ok_or_else(|| error(&left, &right))?,
+                };
+                // NaN compares false against everything
+                Value::Boolean(ordering.map_or(false, |ordering| match self {
+                    Self::Lt => ordering.is_lt(),
+                    Self::Le => ordering.is_le(),
+                    Self::Gt => ordering.is_gt(),
+                    _ => ordering.is_ge(),
+                }))
+            }
+            _ => return Err(error(&left, &right)),
+        };
+        match result {
+            Value::Number(n) if n.is_nan() => Err(ExpressionError::domain(
+                &format!("{} {} {} is not a number", left, self.symbol(), right),
+                position,
+            )),
+            Value::Number(n) if n.is_infinite() => Err(ExpressionError::overflow(position)),
+            result => Ok(result),
         }
     }
 }
 
+impl BinaryOpType {
+    /// Whether `value` is in the array `array`. Categorical values match
+    /// their labels. A value not found is unknown if the array holds a null.
+    fn contains(value: Value, array: Value, position: Position) -> ExpressionResult<Value> {
+        let values = match array {
+            Value::Array(values) => values,
+            Value::Null => return Ok(Value::Null),
+            other => {
+                return Err(ExpressionError::operand_error(
+                    Self::In.symbol(),
+                    &value.value_type(),
+                    &other.value_type(),
+                    position,
+                ))
+            }
+        };
+        if value.is_null() {
+            return Ok(Value::Null);
+        }
+        let found = values.iter().any(|element| match (&value, element) {
+            (Value::Categorical(category), Value::String(label)) => category.label() == label,
+            (value, element) => value == element,
+        });
+        Ok(if found {
+            Value::Boolean(true)
+        } else if values.iter().any(Value::is_null) {
+            Value::Null
+        } else {
+            Value::Boolean(false)
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 enum UnaryOpType {
     Neg,
     Not,
@@ -54,50 +502,154 @@ impl UnaryOpType {
             super::parser::ast::UnaryOpType::Not => Self::Not,
         }
     }
+
+    fn symbol(&self) -> &'static str {
+        match self {
+            Self::Neg => "-",
+            Self::Not => "!",
+        }
+    }
+
+    fn result_type(&self, right: &ValueType, position: Position) -> ExpressionResult<ValueType> {
+        let expected = match self {
+            Self::Neg => ValueType::Number,
+            Self::Not => ValueType::Boolean,
+        };
+        match right {
+            ValueType::Null => Ok(expected.nullable()),
+            ValueType::Array(element) if *self == Self::Neg => Ok(ValueType::Array(Box::new(
+                self.result_type(element, position)?,
+            ))),
+            t if *t.base() == expected => Ok(t.clone()),
+            t if *self == Self::Neg && t.is_numeric() => Ok(t.clone()),
+            t => Err(ExpressionError::type_error(&expected, t, position)),
+        }
+    }
+
+    fn apply(&self, right: Value, position: Position) -> ExpressionResult<Value> {
+        match (self, right) {
+            (_, Value::Null) => Ok(Value::Null),
+            (Self::Neg, Value::Array(values)) => values
+                .into_iter()
+                .map(|value| self.apply(value, position))
+                .collect::<ExpressionResult<_>>()
+                .map(Value::Array),
+            (Self::Neg, Value::Number(n)) => Ok(Value::Number(-n)),
+            (Self::Neg, Value::Integer(n)) => n
+                .checked_neg()
+                .map(Value::Integer)
+                .ok_or_else(|| ExpressionError::overflow(position)),
+            (Self::Neg, Value::Decimal(n)) => n
+                .checked_neg()
+                .map(Value::Decimal)
+                .ok_or_else(|| ExpressionError::overflow(position)),
+            (Self::Not, Value::Boolean(b)) => Ok(Value::Boolean(!b)),
+            (Self::Neg, other) => Err(ExpressionError::type_error(
+                &ValueType::Number,
+                &other.value_type(),
+                position,
+            )),
+            (Self::Not, other) => Err(ExpressionError::type_error(
+                &ValueType::Boolean,
+                &other.value_type(),
+                position,
+            )),
+        }
+    }
 }
 
+#[derive(Debug, Clone, PartialEq)]
 enum LiteralValue {
     String(String),
     Number(f64),
+    Integer(i64),
     Boolean(bool),
+    Null,
 }
 
 impl LiteralValue {
     fn from_literal(node: Node) -> Self {
         match node {
             Node::Number(value) => Self::Number(value),
+            Node::Integer(value) => Self::Integer(value),
             Node::Boolean(value) => Self::Boolean(value),
             Node::String(value) => Self::String(value),
+            Node::Null => Self::Null,
             _ => unreachable!(),
         }
     }
+
+    fn to_value(&self) -> Value {
+        match self {
+            Self::String(value) => Value::String(value.clone()),
+            Self::Number(value) => Value::Number(*value),
+            Self::Integer(value) => Value::Integer(*value),
+            Self::Boolean(value) => Value::Boolean(*value),
+            Self::Null => Value::Null,
+        }
+    }
+}
+
+impl std::fmt::Display for LiteralValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::String(value) => write!(f, "{}", quote(value)),
+            Self::Number(value) => write!(f, "{:?}", value),
+            Self::Integer(value) => write!(f, "{}", value),
+            Self::Boolean(value) => write!(f, "{}", value),
+            Self::Null => write!(f, "null"),
+        }
+    }
 }
 
+#[derive(Debug, Clone, PartialEq)]
 enum ExprNode {
     BinaryOp {
         left: Box<ExprNode>,
         op: BinaryOpType,
         right: Box<ExprNode>,
+        position: Position,
     },
     UnaryOp {
         op: UnaryOpType,
         right: Box<ExprNode>,
+        position: Position,
     },
     Literal {
         value: LiteralValue,
     },
     Variable {
         name: String,
+        position: Position,
+    },
+    Column {
+        reference: ColumnRef,
+        position: Position,
+    },
+    Rows {
+        reference: ColumnRef,
+        rows: RowSelector,
+        position: Position,
     },
     FunctionCall {
         target: Function,
         args: Vec<ExprNode>,
+        position: Position,
+    },
+    Array {
+        items: Vec<ExprNode>,
+        position: Position,
     },
 }
 
 impl ExprNode {
-    fn static_eval() -> Option<LiteralValue> {
-        todo!();
+    /// The value of this node, if it does not depend on the context
+    fn static_eval(&self) -> Option<Value> {
+        if self.is_static() {
+            self.eval(&EmptyContext).ok()
+        } else {
+            None
+        }
     }
 
     fn is_static(&self) -> bool {
@@ -105,13 +657,355 @@ impl ExprNode {
             Self::BinaryOp { left, right, .. } => left.is_static() && right.is_static(),
             Self::UnaryOp { right, .. } => right.is_static(),
             Self::Literal { .. } => true,
-            Self::Variable { .. } => false,
+            Self::Variable { .. } | Self::Column { .. } | Self::Rows { .. } => false,
             Self::FunctionCall { args, .. } => args.iter().all(Self::is_static),
+            Self::Array { items, .. } => items.iter().all(Self::is_static),
+        }
+    }
+
+    fn type_check(&self, context: &dyn Context) -> ExpressionResult<ValueType> {
+        match self {
+            Self::BinaryOp {
+                left,
+                op,
+                right,
+                position,
+            } => op.result_type(
+                &left.type_check(context)?,
+                &right.type_check(context)?,
+                [left.static_eval(), right.static_eval()],
+                *position,
+            ),
+            Self::UnaryOp {
+                op,
+                right,
+                position,
+            } => op.result_type(&right.type_check(context)?, *position),
+            Self::Literal { value } => Ok(value.to_value().value_type()),
+            Self::Variable { name, position } => context
+                .variable_type(name)
+                .ok_or_else(|| ExpressionError::unknown_variable(name, *position)),
+            Self::Column {
+                reference,
+                position,
+            } => {
+                let value_type = context
+                    .column_type(reference)
+                    .ok_or_else(|| ExpressionError::unknown_column(reference, *position))?;
+                // Other sheets may have fewer rows, and rows past the end
+                // read as null
+                Ok(if reference.sheet.is_some() {
+                    value_type.nullable()
+                } else {
+                    value_type
+                })
+            }
+            // Rows out of range read as null, and slices are columns
+            Self::Rows {
+                reference,
+                rows,
+                position,
+            } => {
+                let value_type = context
+                    .column_type(reference)
+                    .ok_or_else(|| ExpressionError::unknown_column(reference, *position))?;
+                Ok(match rows {
+                    RowSelector::Row(_) => value_type.nullable(),
+                    RowSelector::Slice(..) => ValueType::Column(Box::new(value_type)),
+                })
+            }
+            Self::FunctionCall {
+                target,
+                args,
+                position,
+            } => {
+                let types = args
+                    .iter()
+                    .map(|arg| match arg {
+                        Self::Column {
+                            reference,
+                            position,
+                        } if target.is_aggregate() => context
+                            .column_type(reference)
+                            .map(|value_type| ValueType::Column(Box::new(value_type)))
+                            .ok_or_else(|| ExpressionError::unknown_column(reference, *position)),
+                        arg => arg.type_check(context),
+                    })
+                    .collect::<ExpressionResult<Vec<ValueType>>>()?;
+                let constants = args.iter().map(Self::static_eval).collect::<Vec<_>>();
+                target.return_type(&types, &constants, *position)
+            }
+            // Elements have to share a type, and empty arrays can hold any
+            Self::Array { items, position } => {
+                let mut element: Option<ValueType> = None;
+                for item in items {
+                    let item = item.type_check(context)?;
+                    element = Some(match element {
+                        None => item,
+                        Some(element) => element.unify(&item).ok_or_else(|| {
+                            ExpressionError::type_error_message(
+                                &format!(
+                                    "Array elements must share a type, found {} and {}",
+                                    element, item
+                                ),
+                                *position,
+                            )
+                        })?,
+                    });
+                }
+                Ok(ValueType::Array(Box::new(
+                    element.unwrap_or(ValueType::Null),
+                )))
+            }
+        }
+    }
+
+    /// Evaluates the node. Failures that only affect this evaluation, such as
+    /// a division by zero, are returned as `Value::Error` and propagate up
+    /// through the operators that use them.
+    fn eval(&self, context: &dyn Context) -> ExpressionResult<Value> {
+        let result = match self {
+            Self::BinaryOp {
+                left,
+                op,
+                right,
+                position,
+            } => {
+                let left = left.eval(context)?;
+                if left.is_error() {
+                    return Ok(left);
+                }
+                let right = right.eval(context)?;
+                if right.is_error() {
+                    return Ok(right);
+                }
+                op.apply(left, right, *position)
+            }
+            Self::UnaryOp {
+                op,
+                right,
+                position,
+            } => match right.eval(context)? {
+                error @ Value::Error(..) => return Ok(error),
+                right => op.apply(right, *position),
+            },
+            Self::Literal { value } => Ok(value.to_value()),
+            Self::Variable { name, position } => context
+                .variable(name)
+                .ok_or_else(|| ExpressionError::unknown_variable(name, *position)),
+            Self::Column {
+                reference,
+                position,
+            } => Self::column(reference, *position, context).and_then(|column| {
+                let row = context
+                    .row()
+                    .ok_or_else(|| ExpressionError::no_current_row(reference, *position))?;
+                Ok(column.get(row).unwrap_or(Value::Null))
+            }),
+            Self::Rows {
+                reference,
+                rows,
+                position,
+            } => Self::column(reference, *position, context).and_then(|column| {
+                let resolve = |bound: &RowBound| {
+                    bound
+                        .resolve(context.row())
+                        .ok_or_else(|| ExpressionError::no_current_row(reference, *position))
+                };
+                match rows {
+                    RowSelector::Row(bound) => Ok(usize::try_from(resolve(bound)?)
+                        .ok()
+                        .and_then(|row| column.get(row))
+                        .unwrap_or(Value::Null)),
+                    RowSelector::Slice(start, end) => {
+                        let clamp = |row: i64| row.clamp(0, column.len() as i64) as usize;
+                        let start = start.as_ref().map(resolve).transpose()?.map_or(0, clamp);
+                        let end = end
+                            .as_ref()
+                            .map(resolve)
+                            .transpose()?
+                            .map_or(column.len(), clamp);
+                        Ok(Value::Column(column.slice(start..end)))
+                    }
+                }
+            }),
+            Self::FunctionCall {
+                target,
+                args,
+                position,
+            } => {
+                let args = args
+                    .iter()
+                    .map(|arg| match arg {
+                        Self::Column {
+                            reference,
+                            position,
+                        } if target.is_aggregate() => {
+                            Ok(match Self::column(reference, *position, context) {
+                                Ok(column) => Value::Column(column),
+                                Err(error) => Value::error(error),
+                            })
+                        }
+                        arg => arg.eval(context),
+                    })
+                    .collect::<ExpressionResult<Vec<Value>>>()?;
+                target.call(args, *position)
+            }
+            Self::Array { items, .. } => {
+                let mut values = Vec::with_capacity(items.len());
+                for item in items {
+                    match item.eval(context)? {
+                        error @ Value::Error(..) => return Ok(error),
+                        value => values.push(value),
+                    }
+                }
+                Ok(Value::Array(values))
+            }
+        };
+        Ok(result.unwrap_or_else(Value::error))
+    }
+
+    fn column(
+        reference: &ColumnRef,
+        position: Position,
+        context: &dyn Context,
+    ) -> ExpressionResult<Arc<dyn ColumnView>> {
+        context
+            .column(reference)
+            .ok_or_else(|| ExpressionError::unknown_column(reference, position))
+    }
+
+    fn precedence(&self) -> u8 {
+        match self {
+            Self::BinaryOp { op, .. } => op.precedence(),
+            Self::UnaryOp {
+                op: UnaryOpType::Not,
+                ..
+            } => 3,
+            Self::UnaryOp {
+                op: UnaryOpType::Neg,
+                ..
+            } => 8,
+            Self::Literal {
+                value: LiteralValue::Number(n),
+            } if n.is_sign_negative() => 8,
+            Self::Literal {
+                value: LiteralValue::Integer(n),
+            } if *n < 0 => 8,
+            _ => u8::MAX,
+        }
+    }
+
+    /// Visits this node and every node below it
+    fn walk<'a>(&'a self, visit: &mut dyn FnMut(&'a ExprNode)) {
+        visit(self);
+        match self {
+            Self::BinaryOp { left, right, .. } => {
+                left.walk(visit);
+                right.walk(visit);
+            }
+            Self::UnaryOp { right, .. } => right.walk(visit),
+            Self::FunctionCall { args, .. } | Self::Array { items: args, .. } => {
+                for arg in args {
+                    arg.walk(visit);
+                }
+            }
+            Self::Literal { .. }
+            | Self::Variable { .. }
+            | Self::Column { .. }
+            | Self::Rows { .. } => {}
+        }
+    }
+
+    fn walk_mut(&mut self, visit: &mut dyn FnMut(&mut ExprNode)) {
+        visit(self);
+        match self {
+            Self::BinaryOp { left, right, .. } => {
+                left.walk_mut(visit);
+                right.walk_mut(visit);
+            }
+            Self::UnaryOp { right, .. } => right.walk_mut(visit),
+            Self::FunctionCall { args, .. } | Self::Array { items: args, .. } => {
+                for arg in args {
+                    arg.walk_mut(visit);
+                }
+            }
+            Self::Literal { .. }
+            | Self::Variable { .. }
+            | Self::Column { .. }
+            | Self::Rows { .. } => {}
+        }
+    }
+}
+
+/// Writes the node back as source, with only the parentheses it needs.
+/// Operators are left associative, so a right operand of the same
+/// precedence needs them too.
+impl std::fmt::Display for ExprNode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::BinaryOp {
+                left, op, right, ..
+            } => {
+                if left.precedence() < op.precedence() {
+                    write!(f, "({})", left)?;
+                } else {
+                    write!(f, "{}", left)?;
+                }
+                write!(f, " {} ", op.symbol())?;
+                if right.precedence() <= op.precedence() {
+                    write!(f, "({})", right)
+                } else {
+                    write!(f, "{}", right)
+                }
+            }
+            // Negation only applies to a primary expression
+            Self::UnaryOp {
+                op: op @ UnaryOpType::Neg,
+                right,
+                ..
+            } if right.precedence() != u8::MAX => write!(f, "{}({})", op.symbol(), right),
+            Self::UnaryOp { op, right, .. } if right.precedence() < self.precedence() => {
+                write!(f, "{}({})", op.symbol(), right)
+            }
+            Self::UnaryOp { op, right, .. } => write!(f, "{}{}", op.symbol(), right),
+            Self::Literal { value } if self.precedence() != u8::MAX => write!(f, "({})", value),
+            Self::Literal { value } => write!(f, "{}", value),
+            Self::Variable { name, .. } => write!(f, "{}", name),
+            Self::Column { reference, .. } => write!(f, "{}", reference),
+            Self::Rows {
+                reference, rows, ..
+            } => write!(f, "{}{}", reference, rows),
+            Self::FunctionCall { target, args, .. } => {
+                write!(f, "{}(", target.name())?;
+                for (i, arg) in args.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", arg)?;
+                }
+                write!(f, ")")
+            }
+            Self::Array { items, .. } => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            }
         }
     }
 }
 
 impl Expression {
+    pub fn parse(source: &str) -> ExpressionResult<Self> {
+        let ast = Assembler::from_string(source)?.parse()?;
+        Self::from_ast(ast)
+    }
+
     pub fn from_ast(ast: Node) -> ExpressionResult<Self> {
         let ast = ast.reduce();
         Ok(Expression {
@@ -124,30 +1018,64 @@ impl Expression {
             Node::BinaryOp {
                 left,
                 right: Some((op, right)),
+                position,
             } => ExprNode::BinaryOp {
                 left: Box::new(Self::from_ast_node(*left)?),
                 op: BinaryOpType::from_binary_op(op),
                 right: Box::new(Self::from_ast_node(*right)?),
+                position,
             },
             Node::UnaryOp {
                 op: Some(op),
                 right,
+                position,
             } => ExprNode::UnaryOp {
                 op: UnaryOpType::from_unary_op(op),
                 right: Box::new(Self::from_ast_node(*right)?),
+                position,
             },
-            Node::Number(_) | Node::Boolean(_) | Node::String(_) => ExprNode::Literal {
+            Node::Number(_)
+            | Node::Integer(_)
+            | Node::Boolean(_)
+            | Node::String(_)
+            | Node::Null => ExprNode::Literal {
                 value: LiteralValue::from_literal(node),
             },
-            Node::Identifier(name) => ExprNode::Variable { name: name.0 },
+            Node::Identifier(name) => ExprNode::Variable {
+                name: name.0,
+                position: name.1,
+            },
+            Node::Column {
+                reference,
+                position,
+            } => ExprNode::Column {
+                reference,
+                position,
+            },
+            Node::Rows {
+                reference,
+                rows,
+                position,
+            } => ExprNode::Rows {
+                reference,
+                rows,
+                position,
+            },
             Node::Function { name, args } => ExprNode::FunctionCall {
                 target: Function::from_str(&name.0)
-                    .ok_or("Unknown function")
-                    .unwrap(),
+                    .ok_or_else(|| ExpressionError::unknown_function(&name.0, name.1))?,
                 args: args
                     .into_iter()
                     .map(Self::from_ast_node)
                     .collect::<ExpressionResult<Vec<ExprNode>>>()?,
+                position: name.1,
+            },
+            Node::Array { items, position } => ExprNode::Array {
+                items: items
+                    .into_iter()
+                    .map(Self::from_ast_node)
+                    .collect::<ExpressionResult<Vec<ExprNode>>>()?,
+                position,
             },
             _ => unreachable!(),
         })
@@ -156,10 +1084,91 @@ impl Expression {
     fn eval_static(&self) -> Self {
         unimplemented!()
     }
+
+    /// The type this expression evaluates to, with names resolved through
+    /// `context`. Type errors are reported here, before anything is evaluated.
+    pub fn type_check(&self, context: &dyn Context) -> ExpressionResult<ValueType> {
+        self.root.type_check(context)
+    }
+
+    pub fn eval(&self, context: &dyn Context) -> ExpressionResult<Value> {
+        self.root.eval(context)
+    }
+
+    /// The names of the variables used, in order of first use
+    pub fn variables(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = Vec::new();
+        self.root.walk(&mut |node| {
+            if let ExprNode::Variable { name, .. } = node {
+                if !names.contains(&name.as_str()) {
+                    names.push(name);
+                }
+            }
+        });
+        names
+    }
+
+    /// The columns referenced, in order of first use
+    pub fn columns(&self) -> Vec<&ColumnRef> {
+        let mut references: Vec<&ColumnRef> = Vec::new();
+        self.root.walk(&mut |node| {
+            if let ExprNode::Column { reference, .. } | ExprNode::Rows { reference, .. } = node {
+                if !references.contains(&reference) {
+                    references.push(reference);
+                }
+            }
+        });
+        references
+    }
+
+    /// The columns referenced, in order of first use, each with whether it
+    /// is ever used whole rather than read by row. Columns passed to an
+    /// aggregation are used whole, and so are columns with rows picked in
+    /// brackets, since those rows need not be the row being evaluated.
+    pub fn column_uses(&self) -> Vec<(&ColumnRef, bool)> {
+        let mut whole: Vec<&ColumnRef> = Vec::new();
+        self.root.walk(&mut |node| match node {
+            ExprNode::FunctionCall { target, args, .. } if target.is_aggregate() => {
+                for arg in args {
+                    if let ExprNode::Column { reference, .. } = arg {
+                        whole.push(reference);
+                    }
+                }
+            }
+            ExprNode::Rows { reference, .. } => whole.push(reference),
+            _ => {}
+        });
+        self.columns()
+            .into_iter()
+            .map(|reference| (reference, whole.contains(&reference)))
+            .collect()
+    }
+
+    /// Points every reference to sheet `from` at sheet `to` instead,
+    /// returning whether anything changed
+    pub fn rename_sheet(&mut self, from: &str, to: &str) -> bool {
+        let mut changed = false;
+        self.root.walk_mut(&mut |node| {
+            if let ExprNode::Column { reference, .. } | ExprNode::Rows { reference, .. } = node {
+                if reference.sheet.as_deref() == Some(from) {
+                    reference.sheet = Some(to.to_string());
+                    changed = true;
+                }
+            }
+        });
+        changed
+    }
+}
+
+impl std::fmt::Display for Expression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "={}", self.root)
+    }
 }
 
 #[cfg(test)]
 mod test {
+    use super::super::{Categories, ErrorKind, ErrorReport};
     use super::*;
 
     static LOGGER: once_cell::sync::Lazy<flexi_logger::LoggerHandle> =
@@ -179,7 +1188,311 @@ mod test {
                 super::super::parser::ast::BinaryOpType::Add,
                 Box::new(Node::Number(2.0)),
             )),
+            position: Position::default(),
         };
         let expr = Expression::from_ast(ast).unwrap();
     }
+
+    fn eval(source: &str) -> Value {
+        Expression::parse(source)
+            .unwrap()
+            .eval(&super::super::EmptyContext)
+            .unwrap()
+    }
+
+    fn type_of(source: &str) -> ExpressionResult<ValueType> {
+        Expression::parse(source)
+            .unwrap()
+            .type_check(&super::super::EmptyContext)
+    }
+
+    #[test]
+    fn display() {
+        for source in [
+            "=(1 + 2) * 3 - 4 / (5 - 6)",
+            "=-x ^ 2 + -(a + b)",
+            "=!(a | b) & !c == d",
+            "=sum(:'amount', :'Orders'!2) > 'it\\'s'",
+            "=a == (b == null)",
+            "=sum(:'price'[10..20]) - :'price'[-1] + :'price'[0] * sum(:'Orders'!'price'[..+1])",
+            "=x in ['a', 'b'] & len([[1], []] * 2) == 2",
+        ] {
+            let expression = Expression::parse(source).unwrap();
+            assert_eq!(expression.to_string(), source);
+        }
+    }
+
+    #[test]
+    fn null_propagation() {
+        assert_eq!(eval("=1 + null"), Value::Null);
+        assert_eq!(eval("=-null"), Value::Null);
+        assert_eq!(eval("=null == null"), Value::Null);
+        assert_eq!(eval("=2 * (3 + 4)"), Value::Integer(14));
+        assert_eq!(
+            type_of("=1.5 + null").unwrap(),
+            ValueType::Number.nullable()
+        );
+        assert!(type_of("=1 + true").is_err());
+    }
+
+    #[test]
+    fn three_valued_logic() {
+        assert_eq!(eval("=false & null"), Value::Boolean(false));
+        assert_eq!(eval("=true & null"), Value::Null);
+        assert_eq!(eval("=true | null"), Value::Boolean(true));
+        assert_eq!(eval("=false | null"), Value::Null);
+        assert_eq!(eval("=true & false"), Value::Boolean(false));
+        assert_eq!(eval("=false | true"), Value::Boolean(true));
+        assert_eq!(eval("=!null"), Value::Null);
+        assert_eq!(
+            type_of("=true | null").unwrap(),
+            ValueType::Boolean.nullable()
+        );
+    }
+
+    #[test]
+    fn null_functions() {
+        assert_eq!(eval("=sum(1, null, 2)"), Value::Integer(3));
+        assert_eq!(eval("=mean(null, 2.0, 4.0)"), Value::Number(3.0));
+        assert_eq!(eval("=sum(null)"), Value::Null);
+        assert_eq!(eval("=count(null, 'a', 2.0)"), Value::Integer(2));
+        assert_eq!(eval("=trim('  a b ')"), Value::String("a b".to_string()));
+        assert_eq!(eval("=trim(null)"), Value::Null);
+        assert!(type_of("=trim(1)").is_err());
+        assert_eq!(type_of("=count(null)").unwrap(), ValueType::Integer);
+        assert_eq!(eval("=coalesce(null, null, 3)"), Value::Integer(3));
+        assert_eq!(eval("=if_null(null, 'x')"), Value::String("x".to_string()));
+        assert_eq!(eval("=null_if(5, 5)"), Value::Null);
+        assert_eq!(eval("=is_null(null_if(5, 4))"), Value::Boolean(false));
+        assert_eq!(type_of("=coalesce(null, 1.0)").unwrap(), ValueType::Number);
+        assert_eq!(
+            type_of("=null_if(1, 2)").unwrap(),
+            ValueType::Integer.nullable()
+        );
+        assert!(type_of("=coalesce(1, 'a')").is_err());
+    }
+
+    #[test]
+    fn error_values() {
+        let error = eval("=1 + 2 / 0");
+        match &error {
+            Value::Error(ErrorKind::DivByZero, error) => {
+                assert_eq!(error.position(), Some(&Position::new(1, 8)))
+            }
+            other => panic!("Expected a division error, found {}", other),
+        }
+        assert!(eval("=sum(1, 1 % 0)").is_error());
+        assert!(eval("=missing * 2").is_error());
+        assert_eq!(eval("=if_error(1 / 0, -1)"), Value::Integer(-1));
+        assert_eq!(eval("=is_error(1 / 0)"), Value::Boolean(true));
+        assert_eq!(eval("=is_error(1 / 2)"), Value::Boolean(false));
+
+        let report = ErrorReport::from_values(&[
+            Value::Number(1.0),
+            eval("=1 / 0"),
+            eval("=missing"),
+            eval("=2 / 0"),
+        ]);
+        assert_eq!(report.total, 3);
+        assert_eq!(report.counts[&ErrorKind::DivByZero], 2);
+        assert_eq!(report.first[&ErrorKind::NotFound].0, 2);
+    }
+
+    #[test]
+    fn exact_numbers() {
+        assert_eq!(eval("=7 / 2"), Value::Integer(3));
+        assert!(matches!(
+            eval("=9223372036854775807 + 1"),
+            Value::Error(ErrorKind::Overflow, _)
+        ));
+        assert_eq!(
+            eval("=to_decimal('0.1', 2) + to_decimal('0.2', 2) == to_decimal('0.3', 2)"),
+            Value::Boolean(true)
+        );
+        assert_eq!(eval("=0.1 + 0.2 == 0.3"), Value::Boolean(false));
+        assert_eq!(
+            eval("=sum(to_decimal(1.005, 3), to_decimal(2, 1))").to_string(),
+            "3.005"
+        );
+        assert_eq!(eval("=to_number(3) * 1.5"), Value::Number(4.5));
+        assert_eq!(eval("=to_integer('42')"), Value::Integer(42));
+        assert!(eval("=to_integer(1.5)").is_error());
+
+        assert!(type_of("=1 + 1.5").is_err());
+        assert!(type_of("=mean(1, 2)").is_err());
+        assert_eq!(
+            type_of("=to_decimal(1, 2) * to_decimal(1, 3)").unwrap(),
+            ValueType::Decimal(5)
+        );
+        assert_eq!(
+            type_of("=sum(to_decimal(1, 2), null)").unwrap(),
+            ValueType::Decimal(2)
+        );
+        assert!(type_of("=to_decimal(1, 1 + 30)").is_err());
+
+        // Long fractions and scales too large for the units
+        let long = format!("0.{}", "1".repeat(45));
+        assert_eq!(
+            eval(&format!("=to_decimal('{}', 2)", long)).to_string(),
+            "0.11"
+        );
+        assert!(type_of("=to_decimal('1', 40)").is_err());
+        assert!(eval("=to_decimal('1', 40)").is_error());
+    }
+
+    struct Row {
+        status: Value,
+        size: Value,
+    }
+
+    impl Context for Row {
+        fn variable(&self, name: &str) -> Option<Value> {
+            match name {
+                "status" => Some(self.status.clone()),
+                "size" => Some(self.size.clone()),
+                _ => None,
+            }
+        }
+
+        fn variable_type(&self, name: &str) -> Option<ValueType> {
+            self.variable(name).map(|value| value.value_type())
+        }
+    }
+
+    #[test]
+    fn categorical() {
+        let statuses =
+            Categories::new(vec!["pending".to_string(), "shipped".to_string()], false).unwrap();
+        let sizes = Categories::new(
+            vec!["S".to_string(), "M".to_string(), "L".to_string()],
+            true,
+        )
+        .unwrap();
+        let row = Row {
+            status: Value::Categorical(Category::new("shipped", &statuses).unwrap()),
+            size: Value::Categorical(Category::new("M", &sizes).unwrap()),
+        };
+        let check = |source: &str| Expression::parse(source).unwrap().type_check(&row);
+        let eval = |source: &str| Expression::parse(source).unwrap().eval(&row).unwrap();
+
+        assert_eq!(check("=status == 'shipped'").unwrap(), ValueType::Boolean);
+        assert!(check("=status == 'shiped'").is_err());
+        assert!(check("=status < 'shipped'").is_err());
+        assert!(check("=status == size").is_err());
+        assert!(check("=size > 'S'").is_ok());
+
+        assert_eq!(eval("=status == 'shipped'"), Value::Boolean(true));
+        assert_eq!(eval("='pending' != status"), Value::Boolean(true));
+        assert_eq!(eval("=size > 'S'"), Value::Boolean(true));
+        assert_eq!(eval("=size >= 'L'"), Value::Boolean(false));
+
+        assert_eq!(
+            check("=status in ['pending', 'shipped']").unwrap(),
+            ValueType::Boolean
+        );
+        assert!(check("=status in ['pending', 'shiped']").is_err());
+        assert_eq!(eval("=status in ['shipped']"), Value::Boolean(true));
+        assert_eq!(eval("=size in ['S', 'L']"), Value::Boolean(false));
+    }
+
+    #[test]
+    fn arrays() {
+        let integers =
+            |values: &[i64]| Value::Array(values.iter().copied().map(Value::Integer).collect());
+
+        assert_eq!(
+            type_of("=[1.5, null, 2.0]").unwrap(),
+            ValueType::Array(Box::new(ValueType::Number.nullable()))
+        );
+        assert_eq!(
+            type_of("=[[1], []]").unwrap(),
+            // Empty arrays have null elements
+            ValueType::Array(Box::new(ValueType::Array(Box::new(
+                ValueType::Integer.nullable()
+            ))))
+        );
+        assert!(type_of("=[1, 'a']").is_err());
+
+        assert_eq!(eval("=2 in [1, 2, 3]"), Value::Boolean(true));
+        assert_eq!(eval("=4 in [1, 2, 3]"), Value::Boolean(false));
+        assert_eq!(eval("=4 in [1, null]"), Value::Null);
+        assert_eq!(eval("=1 in [1, null]"), Value::Boolean(true));
+        assert_eq!(eval("=null in [1]"), Value::Null);
+        assert_eq!(eval("='b' in []"), Value::Boolean(false));
+        assert!(type_of("=1 in ['a']").is_err());
+        assert!(type_of("=1 in 1").is_err());
+
+        assert_eq!(eval("=[1, 2] + [10, 20]"), integers(&[11, 22]));
+        assert_eq!(eval("=[1, 2] * 3"), integers(&[3, 6]));
+        assert_eq!(eval("=10 - [1, 2]"), integers(&[9, 8]));
+        assert_eq!(eval("=-[1, -2]"), integers(&[-1, 2]));
+        assert!(eval("=[1, 2] + [1]").is_error());
+        assert!(type_of("=[1, 2] + 1.5").is_err());
+
+        assert_eq!(eval("=len([1, 2, 3])"), Value::Integer(3));
+        assert_eq!(eval("=concat([1], [], [2, 3])"), integers(&[1, 2, 3]));
+        assert_eq!(eval("=unique([3, 1, 3, 2, 1])"), integers(&[3, 1, 2]));
+        assert_eq!(eval("=flatten([[1], [], [2, 3]])"), integers(&[1, 2, 3]));
+        assert_eq!(eval("=at([1, 2, 3], 0)"), Value::Integer(1));
+        assert_eq!(eval("=at([1, 2, 3], -1)"), Value::Integer(3));
+        assert_eq!(eval("=at([1, 2, 3], 3)"), Value::Null);
+        assert_eq!(eval("=len(null)"), Value::Null);
+        assert!(type_of("=concat([1], ['a'])").is_err());
+        assert!(type_of("=len(1)").is_err());
+        assert_eq!(
+            type_of("=at([1], 0)").unwrap(),
+            ValueType::Integer.nullable()
+        );
+
+        assert_eq!(eval("=sum([1, 2], 3)"), Value::Integer(6));
+        assert_eq!(eval("=mean([1.0, null, 3.0])"), Value::Number(2.0));
+        assert_eq!(eval("=count([1, null, 3])"), Value::Integer(2));
+        assert_eq!(
+            type_of("=sum([1, 2])").unwrap(),
+            ValueType::Integer.nullable()
+        );
+    }
+
+    #[test]
+    fn json() {
+        let doc = r#"json_parse('{"a": {"b": [1.5, "x", null]}, "n": 2}')"#;
+        let with_doc = |expr: &str| eval(&format!("={}", expr.replace("doc", doc)));
+
+        assert_eq!(
+            with_doc("json_number(json_get(doc, '$.a.b[0]'))"),
+            Value::Number(1.5)
+        );
+        assert_eq!(
+            with_doc("json_string(json_get(doc, '$.a.b[1]'))"),
+            Value::String("x".to_string())
+        );
+        assert_eq!(with_doc("json_get(doc, '$.a.c')"), Value::Null);
+        assert_eq!(
+            with_doc("json_number(json_get(doc, '$.a.b[2]'))"),
+            Value::Null
+        );
+        assert!(with_doc("json_number(json_get(doc, '$.a.b[1]'))").is_error());
+        assert_eq!(
+            with_doc("json_array_length(json_get(doc, '$.a.b'))"),
+            Value::Integer(3)
+        );
+        assert_eq!(
+            with_doc("json_type(json_get(doc, '$.a'))"),
+            Value::String("object".to_string())
+        );
+        assert_eq!(
+            with_doc("json_keys(doc)"),
+            Value::Array(vec![
+                Value::String("a".to_string()),
+                Value::String("n".to_string())
+            ])
+        );
+        assert_eq!(
+            with_doc("json_parse(json_format(doc)) == doc"),
+            Value::Boolean(true)
+        );
+        assert!(eval("=json_parse('{')").is_error());
+        assert!(type_of("=json_get(json_parse('1'), 'a.b')").is_err());
+        assert!(type_of("=json_number('1')").is_err());
+    }
 }
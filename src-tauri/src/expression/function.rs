@@ -1,19 +1,61 @@
+use std::{cmp::Ordering, sync::Arc};
+
+use serde_json::Value as JsonValue;
+
 use super::{
+    json::{json_type, JsonPath},
     parser::Position,
     value::{Value, ValueType},
-    ExpressionError, ExpressionResult,
+    ColumnView, Decimal, ExpressionError, ExpressionResult, MAX_SCALE,
 };
 
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Function {
     // Statistic functions
     Sum,
     Mean,
     Median,
     Mode,
+    Count,
+
+    // Null handling functions
+    Coalesce,
+    IsNull,
+    NullIf,
+    IfNull,
+
+    // Error handling functions
+    IfError,
+    IsError,
+
+    // Conversion functions
+    ToNumber,
+    ToInteger,
+    ToDecimal,
+
+    // String functions
+    Trim,
+
+    // JSON functions
+    JsonParse,
+    JsonFormat,
+    JsonGet,
+    JsonKeys,
+    JsonArrayLength,
+    JsonType,
+    JsonNumber,
+    JsonString,
+    JsonBoolean,
+
+    // Array functions
+    Len,
+    Concat,
+    Unique,
+    Flatten,
+    At,
     // Min,
     // Max,
     // Range,
-    // Count,
     // Q1,
     // Q3,
 
@@ -40,11 +82,8 @@ pub enum Function {
     // Log,
 
     // // String functions
-    // Concat,
-    // Length,
     // Lower,
     // Upper,
-    // Trim,
     // Replace,
     // Substring,
     // Split,
@@ -68,17 +107,807 @@ impl Function {
             "mean" => Some(Self::Mean),
             "median" => Some(Self::Median),
             "mode" => Some(Self::Mode),
+            "count" => Some(Self::Count),
+            "coalesce" => Some(Self::Coalesce),
+            "is_null" => Some(Self::IsNull),
+            "null_if" => Some(Self::NullIf),
+            "if_null" => Some(Self::IfNull),
+            "if_error" => Some(Self::IfError),
+            "is_error" => Some(Self::IsError),
+            "to_number" => Some(Self::ToNumber),
+            "to_integer" => Some(Self::ToInteger),
+            "to_decimal" => Some(Self::ToDecimal),
+            "trim" => Some(Self::Trim),
+            "json_parse" => Some(Self::JsonParse),
+            "json_format" => Some(Self::JsonFormat),
+            "json_get" => Some(Self::JsonGet),
+            "json_keys" => Some(Self::JsonKeys),
+            "json_array_length" => Some(Self::JsonArrayLength),
+            "json_type" => Some(Self::JsonType),
+            "json_number" => Some(Self::JsonNumber),
+            "json_string" => Some(Self::JsonString),
+            "json_boolean" => Some(Self::JsonBoolean),
+            "len" => Some(Self::Len),
+            "concat" => Some(Self::Concat),
+            "unique" => Some(Self::Unique),
+            "flatten" => Some(Self::Flatten),
+            "at" => Some(Self::At),
             _ => None,
         }
     }
 
-    pub fn return_type(&self) -> ValueType {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Sum => "sum",
+            Self::Mean => "mean",
+            Self::Median => "median",
+            Self::Mode => "mode",
+            Self::Count => "count",
+            Self::Coalesce => "coalesce",
+            Self::IsNull => "is_null",
+            Self::NullIf => "null_if",
+            Self::IfNull => "if_null",
+            Self::IfError => "if_error",
+            Self::IsError => "is_error",
+            Self::ToNumber => "to_number",
+            Self::ToInteger => "to_integer",
+            Self::ToDecimal => "to_decimal",
+            Self::Trim => "trim",
+            Self::JsonParse => "json_parse",
+            Self::JsonFormat => "json_format",
+            Self::JsonGet => "json_get",
+            Self::JsonKeys => "json_keys",
+            Self::JsonArrayLength => "json_array_length",
+            Self::JsonType => "json_type",
+            Self::JsonNumber => "json_number",
+            Self::JsonString => "json_string",
+            Self::JsonBoolean => "json_boolean",
+            Self::Len => "len",
+            Self::Concat => "concat",
+            Self::Unique => "unique",
+            Self::Flatten => "flatten",
+            Self::At => "at",
+        }
+    }
+
+    /// Whether this function aggregates its arguments. Column references
+    /// passed to these are given the whole column rather than one row.
+    pub fn is_aggregate(&self) -> bool {
+        matches!(
+            self,
+            Self::Sum | Self::Mean | Self::Median | Self::Mode | Self::Count
+        )
+    }
+
+    fn is_conversion(&self) -> bool {
+        matches!(self, Self::ToNumber | Self::ToInteger | Self::ToDecimal)
+    }
+
+    fn is_json(&self) -> bool {
+        matches!(
+            self,
+            Self::JsonParse
+                | Self::JsonFormat
+                | Self::JsonGet
+                | Self::JsonKeys
+                | Self::JsonArrayLength
+                | Self::JsonType
+                | Self::JsonNumber
+                | Self::JsonString
+                | Self::JsonBoolean
+        )
+    }
+
+    fn is_array(&self) -> bool {
+        matches!(
+            self,
+            Self::Len | Self::Concat | Self::Unique | Self::Flatten | Self::At
+        )
+    }
+
+    fn check_arity(&self, found: usize, position: Position) -> ExpressionResult<()> {
+        let (valid, expected) = match self {
+            Self::Sum
+            | Self::Mean
+            | Self::Median
+            | Self::Mode
+            | Self::Count
+            | Self::Coalesce
+            | Self::Concat => (found >= 1, "at least 1"),
+            Self::NullIf
+            | Self::IfNull
+            | Self::IfError
+            | Self::ToDecimal
+            | Self::JsonGet
+            | Self::At => (found == 2, "2"),
+            _ => (found == 1, "1"),
+        };
+        if valid {
+            Ok(())
+        } else {
+            Err(ExpressionError::argument_count(
+                self.name(),
+                expected,
+                found,
+                position,
+            ))
+        }
+    }
+
+    /// The type returned when called with arguments of the given types.
+    /// `constants` holds the value of each argument that does not depend on
+    /// the context, for functions whose type depends on an argument's value.
+    ///
+    /// Aggregations skip nulls, so they only return null when every argument
+    /// may be null. Each value of a column or array argument counts as an
+    /// argument. Counts take values of any type and are never null.
+    pub fn return_type(
+        &self,
+        args: &[ValueType],
+        constants: &[Option<Value>],
+        position: Position,
+    ) -> ExpressionResult<ValueType> {
+        self.check_arity(args.len(), position)?;
+        if *self == Self::Count {
+            return Ok(ValueType::Integer);
+        }
+        if self.is_aggregate() {
+            // Columns and arrays may be empty, so they count as nullable
+            let args = args
+                .iter()
+                .map(|arg| match arg.base() {
+                    ValueType::Column(inner) | ValueType::Array(inner) => {
+                        inner.as_ref().clone().nullable()
+                    }
+                    _ => arg.clone(),
+                })
+                .collect::<Vec<_>>();
+            let unified = Self::unify_args(&args, position)?;
+            let base = match (self, unified.base()) {
+                (_, ValueType::Null) => ValueType::Number,
+                // The mean of integers is rarely an integer, so the caller has
+                // to pick the type to compute it in
+                (Self::Mean | Self::Median, ValueType::Integer) => {
+                    return Err(ExpressionError::type_error(
+                        &ValueType::Number,
+                        &unified,
+                        position,
+                    ))
+                }
+                (_, t) if t.is_numeric() => t.clone(),
+                (_, t) => return Err(ExpressionError::type_error(&ValueType::Number, t, position)),
+            };
+            return Ok(if args.iter().all(ValueType::is_nullable) {
+                base.nullable()
+            } else {
+                base
+            });
+        }
+        if self.is_conversion() {
+            let arg = &args[0];
+            if !(arg.is_numeric() || matches!(arg.base(), ValueType::String | ValueType::Null)) {
+                return Err(ExpressionError::type_error(
+                    &ValueType::String,
+                    arg,
+                    position,
+                ));
+            }
+            let converted = match self {
+                Self::ToNumber => ValueType::Number,
+                Self::ToInteger => ValueType::Integer,
+                _ => ValueType::Decimal(Self::decimal_scale(&constants[1], position)?),
+            };
+            return Ok(if arg.is_nullable() {
+                converted.nullable()
+            } else {
+                converted
+            });
+        }
+        if self.is_json() {
+            return self.json_return_type(args, constants, position);
+        }
+        if self.is_array() {
+            return self.array_return_type(args, position);
+        }
+        match self {
+            Self::IsNull | Self::IsError => Ok(ValueType::Boolean),
+            Self::Trim => match args[0].base() {
+                ValueType::String | ValueType::Null => Ok(args[0].clone()),
+                _ => Err(ExpressionError::type_error(
+                    &ValueType::String,
+                    &args[0],
+                    position,
+                )),
+            },
+            Self::IfError => Self::unify_args(args, position),
+            Self::Coalesce | Self::IfNull => {
+                let unified = Self::unify_args(args, position)?;
+                // The first non-nullable argument always stops the search
+                Ok(if args.iter().any(|arg| !arg.is_nullable()) {
+                    unified.base().clone()
+                } else {
+                    unified
+                })
+            }
+            Self::NullIf => Ok(Self::unify_args(args, position)?.nullable()),
+            _ => unreachable!(),
+        }
+    }
+
+    fn json_return_type(
+        &self,
+        args: &[ValueType],
+        constants: &[Option<Value>],
+        position: Position,
+    ) -> ExpressionResult<ValueType> {
+        let expected = match self {
+            Self::JsonParse => ValueType::String,
+            _ => ValueType::Json,
+        };
+        if !matches!(args[0].base(), ValueType::Null) && *args[0].base() != expected {
+            return Err(ExpressionError::type_error(&expected, &args[0], position));
+        }
+        if *self == Self::JsonGet {
+            match args[1].base() {
+                ValueType::String | ValueType::Null => {}
+                other => {
+                    return Err(ExpressionError::type_error(
+                        &ValueType::String,
+                        other,
+                        position,
+                    ))
+                }
+            }
+            // Constant paths are checked up front
+            if let Some(Value::String(path)) = &constants[1] {
+                JsonPath::parse(path)
+                    .map_err(|message| ExpressionError::new(&message, Some(&position)))?;
+            }
+            // Missing paths are null
+            return Ok(ValueType::Json.nullable());
+        }
+        let result = match self {
+            Self::JsonParse => ValueType::Json,
+            Self::JsonFormat | Self::JsonType | Self::JsonString => ValueType::String,
+            Self::JsonKeys => ValueType::Array(Box::new(ValueType::String)),
+            Self::JsonArrayLength => ValueType::Integer,
+            Self::JsonNumber => ValueType::Number,
+            Self::JsonBoolean => ValueType::Boolean,
+            _ => unreachable!(),
+        };
+        // JSON nulls extract as nulls
+        Ok(
+            if args[0].is_nullable()
+                || matches!(
+                    self,
+                    Self::JsonNumber | Self::JsonString | Self::JsonBoolean
+                )
+            {
+                result.nullable()
+            } else {
+                result
+            },
+        )
+    }
+
+    fn array_return_type(
+        &self,
+        args: &[ValueType],
+        position: Position,
+    ) -> ExpressionResult<ValueType> {
+        let element = |arg: &ValueType| match arg.base() {
+            ValueType::Array(element) => Ok(element.as_ref().clone()),
+            ValueType::Null => Ok(ValueType::Null),
+            other => Err(ExpressionError::type_error(
+                &ValueType::Array(Box::new(ValueType::Null)),
+                other,
+                position,
+            )),
+        };
+        let elements = args
+            .iter()
+            .take(if *self == Self::At { 1 } else { args.len() })
+            .map(element)
+            .collect::<ExpressionResult<Vec<_>>>()?;
+        let result = match self {
+            Self::Len => ValueType::Integer,
+            Self::Concat => ValueType::Array(Box::new(Self::unify_args(&elements, position)?)),
+            Self::Unique => ValueType::Array(Box::new(elements[0].clone())),
+            // Null inner arrays are skipped
+            Self::Flatten => ValueType::Array(Box::new(element(&elements[0])?.base().clone())),
+            Self::At => {
+                if !matches!(args[1].base(), ValueType::Integer | ValueType::Null) {
+                    return Err(ExpressionError::type_error(
+                        &ValueType::Integer,
+                        &args[1],
+                        position,
+                    ));
+                }
+                // Indices past either end give null
+                return Ok(elements[0].clone().nullable());
+            }
+            _ => unreachable!(),
+        };
+        Ok(if args.iter().any(ValueType::is_nullable) {
+            result.nullable()
+        } else {
+            result
+        })
+    }
+
+    fn unify_args(args: &[ValueType], position: Position) -> ExpressionResult<ValueType> {
+        let mut unified = args[0].clone();
+        for arg in &args[1..] {
+            unified = unified
+                .unify(arg)
+                .ok_or_else(|| ExpressionError::type_error(&unified, arg, position))?;
+        }
+        Ok(unified)
+    }
+
+    /// The scale argument of `to_decimal`, which has to be known before
+    /// evaluation so the result has a type
+    fn decimal_scale(scale: &Option<Value>, position: Position) -> ExpressionResult<u32> {
+        match scale {
+            Some(Value::Integer(scale)) if (0..=MAX_SCALE as i64).contains(scale) => {
+                Ok(*scale as u32)
+            }
+            _ => Err(ExpressionError::new(
+                &format!(
+                    "The scale of to_decimal must be a constant Integer from 0 to {}",
+                    MAX_SCALE
+                ),
+                Some(&position),
+            )),
+        }
+    }
+
+    /// Calls the function. Error values in the arguments are returned as-is,
+    /// except by the functions that exist to catch them.
+    pub fn call(&self, args: Vec<Value>, position: Position) -> ExpressionResult<Value> {
+        self.check_arity(args.len(), position)?;
+        if let [Value::Column(column)] = args.as_slice() {
+            if let Some(result) = self.summarise(column.as_ref(), position) {
+                return result;
+            }
+        }
+        let args = if self.is_aggregate() {
+            args.into_iter()
+                .flat_map(|arg| match arg {
+                    Value::Column(column) => column.iter().collect(),
+                    Value::Array(values) => values,
+                    arg => vec![arg],
+                })
+                .collect()
+        } else {
+            args
+        };
+        if !matches!(self, Self::IfError | Self::IsError) {
+            if let Some(error) = args.iter().find(|arg| arg.is_error()) {
+                return Ok(error.clone());
+            }
+        }
+        if self.is_aggregate() {
+            let values = args
+                .into_iter()
+                .filter(|arg| !arg.is_null())
+                .collect::<Vec<Value>>();
+            if *self == Self::Count {
+                return Ok(Value::Integer(values.len() as i64));
+            }
+            if values.is_empty() {
+                return Ok(Value::Null);
+            }
+            return self.aggregate(values, position);
+        }
+        if self.is_conversion() || self.is_json() || self.is_array() {
+            if args.iter().any(Value::is_null) {
+                return Ok(Value::Null);
+            }
+            if self.is_json() {
+                return self.call_json(&args, position);
+            }
+            if self.is_array() {
+                return self.call_array(args, position);
+            }
+            return self.convert(&args, position);
+        }
+        let mut args = args;
+        Ok(match self {
+            Self::IsNull => Value::Boolean(args[0].is_null()),
+            Self::Trim => match &args[0] {
+                Value::Null => Value::Null,
+                Value::String(s) => Value::String(s.trim().to_string()),
+                other => return Err(Self::not_convertible(other, position)),
+            },
+            Self::IsError => Value::Boolean(args[0].is_error()),
+            Self::IfError => {
+                let fallback = args.pop().unwrap();
+                let value = args.pop().unwrap();
+                if value.is_error() {
+                    fallback
+                } else {
+                    value
+                }
+            }
+            Self::Coalesce | Self::IfNull => args
+                .into_iter()
+                .find(|arg| !arg.is_null())
+                .unwrap_or(Value::Null),
+            Self::NullIf => {
+                let other = args.pop().unwrap();
+                let value = args.pop().unwrap();
+                if value == other {
+                    Value::Null
+                } else {
+                    value
+                }
+            }
+            _ => unreachable!(),
+        })
+    }
+
+    /// Aggregates a single column from its running totals, giving the same
+    /// result as reading every row. Returns `None` for the aggregates that
+    /// cannot be worked out that way.
+    fn summarise(
+        &self,
+        column: &dyn ColumnView,
+        position: Position,
+    ) -> Option<ExpressionResult<Value>> {
+        if !matches!(self, Self::Sum | Self::Mean | Self::Count) {
+            return None;
+        }
+        if let Some(error) = column.first_error() {
+            return Some(Ok(error));
+        }
+        let count = column.count();
+        if *self == Self::Count {
+            return Some(Ok(Value::Integer(count as i64)));
+        }
+        let units = column.units()?;
+        if count == 0 {
+            return Some(Ok(Value::Null));
+        }
+        Some(match (self, column.value_type().base()) {
+            (Self::Sum, ValueType::Integer) => i64::try_from(units)
+                .map(Value::Integer)
+                .map_err(|_| ExpressionError::overflow(position)),
+            (Self::Sum, ValueType::Decimal(scale)) => {
+                Ok(Value::Decimal(Decimal::new(units, *scale)))
+            }
+            (Self::Mean, ValueType::Decimal(scale)) => Decimal::new(units, *scale)
+                .checked_div_count(count)
+                .map(Value::Decimal)
+                .ok_or_else(|| ExpressionError::overflow(position)),
+            _ => return None,
+        })
+    }
+
+    fn convert(&self, args: &[Value], position: Position) -> ExpressionResult<Value> {
+        let value = &args[0];
+        let invalid = |target: &str| {
+            ExpressionError::domain(&format!("Cannot convert {} to {}", value, target), position)
+        };
+        let overflow = || ExpressionError::overflow(position);
         match self {
-            Self::Sum => ValueType::Number,
-            Self::Mean => ValueType::Number,
-            Self::Median => ValueType::Number,
-            Self::Mode => ValueType::Number,
-            _ => ValueType::Null,
+            Self::ToNumber => Ok(Value::Number(match value {
+                Value::Number(n) => *n,
+                Value::Integer(n) => *n as f64,
+                Value::Decimal(d) => d.to_f64(),
+                Value::String(s) => s.parse().map_err(|_| invalid("Number"))?,
+                other => return Err(Self::not_convertible(other, position)),
+            })),
+            Self::ToInteger => Ok(Value::Integer(match value {
+                Value::Number(n) if n.fract() != 0.0 => return Err(invalid("Integer")),
+                Value::Number(n) if n.abs() < i64::MAX as f64 => *n as i64,
+                Value::Number(_) => return Err(overflow()),
+                Value::Integer(n) => *n,
+                Value::Decimal(d) => d.to_integer().ok_or_else(|| invalid("Integer"))?,
+                Value::String(s) => s.parse().map_err(|_| invalid("Integer"))?,
+                other => return Err(Self::not_convertible(other, position)),
+            })),
+            _ => {
+                let scale = match &args[1] {
+                    Value::Integer(scale) if (0..=MAX_SCALE as i64).contains(scale) => {
+                        *scale as u32
+                    }
+                    _ => Self::decimal_scale(&None, position)?,
+                };
+                Ok(Value::Decimal(match value {
+                    Value::Number(n) => Decimal::from_f64(*n, scale).ok_or_else(overflow)?,
+                    Value::Integer(n) => Decimal::from_integer(*n, scale).ok_or_else(overflow)?,
+                    Value::Decimal(d) => d.rescale(scale).ok_or_else(overflow)?,
+                    Value::String(s) => {
+                        Decimal::parse(s, scale).ok_or_else(|| invalid("Decimal"))?
+                    }
+                    other => return Err(Self::not_convertible(other, position)),
+                }))
+            }
+        }
+    }
+
+    fn call_json(&self, args: &[Value], position: Position) -> ExpressionResult<Value> {
+        let json = match (self, &args[0]) {
+            (Self::JsonParse, Value::String(source)) => {
+                return serde_json::from_str(source)
+                    .map(|json| Value::Json(Arc::new(json)))
+                    .map_err(|error| {
+                        ExpressionError::domain(&format!("Invalid JSON: {}", error), position)
+                    })
+            }
+            (Self::JsonParse, other) => {
+                return Err(ExpressionError::type_error(
+                    &ValueType::String,
+                    &other.value_type(),
+                    position,
+                ))
+            }
+            (_, Value::Json(json)) => json,
+            (_, other) => {
+                return Err(ExpressionError::type_error(
+                    &ValueType::Json,
+                    &other.value_type(),
+                    position,
+                ))
+            }
+        };
+        let mismatch = |expected: &str| {
+            ExpressionError::type_error_message(
+                &format!("Expected a JSON {}, found {}", expected, json_type(json)),
+                position,
+            )
+        };
+        Ok(match self {
+            Self::JsonFormat => Value::String(json.to_string()),
+            Self::JsonType => Value::String(json_type(json).to_string()),
+            Self::JsonGet => {
+                let path = match &args[1] {
+                    Value::String(path) => JsonPath::parse(path)
+                        .map_err(|message| ExpressionError::domain(&message, position))?,
+                    other => {
+                        return Err(ExpressionError::type_error(
+                            &ValueType::String,
+                            &other.value_type(),
+                            position,
+                        ))
+                    }
+                };
+                match path.get(json) {
+                    Some(found) => Value::Json(Arc::new(found.clone())),
+                    None => Value::Null,
+                }
+            }
+            Self::JsonKeys => Value::Array(
+                json.as_object()
+                    .ok_or_else(|| mismatch("object"))?
+                    .keys()
+                    .map(|key| Value::String(key.clone()))
+                    .collect(),
+            ),
+            Self::JsonArrayLength => {
+                Value::Integer(json.as_array().ok_or_else(|| mismatch("array"))?.len() as i64)
+            }
+            Self::JsonNumber => match json.as_ref() {
+                JsonValue::Null => Value::Null,
+                JsonValue::Number(n) => {
+                    Value::Number(n.as_f64().ok_or_else(|| mismatch("number"))?)
+                }
+                _ => return Err(mismatch("number")),
+            },
+            Self::JsonString => match json.as_ref() {
+                JsonValue::Null => Value::Null,
+                JsonValue::String(s) => Value::String(s.clone()),
+                _ => return Err(mismatch("string")),
+            },
+            Self::JsonBoolean => match json.as_ref() {
+                JsonValue::Null => Value::Null,
+                JsonValue::Bool(b) => Value::Boolean(*b),
+                _ => return Err(mismatch("boolean")),
+            },
+            _ => unreachable!(),
+        })
+    }
+
+    fn call_array(&self, args: Vec<Value>, position: Position) -> ExpressionResult<Value> {
+        let mut arrays = Vec::with_capacity(args.len());
+        let mut args = args.into_iter();
+        for arg in args
+            .by_ref()
+            .take(if *self == Self::At { 1 } else { usize::MAX })
+        {
+            match arg {
+                Value::Array(values) => arrays.push(values),
+                other => {
+                    return Err(ExpressionError::type_error(
+                        &ValueType::Array(Box::new(ValueType::Null)),
+                        &other.value_type(),
+                        position,
+                    ))
+                }
+            }
+        }
+        let mut array = arrays.swap_remove(0);
+        Ok(match self {
+            Self::Len => Value::Integer(array.len() as i64),
+            Self::Concat => {
+                array.extend(arrays.into_iter().flatten());
+                Value::Array(array)
+            }
+            Self::Unique => {
+                let mut unique: Vec<Value> = Vec::with_capacity(array.len());
+                for value in array {
+                    if !unique.contains(&value) {
+                        unique.push(value);
+                    }
+                }
+                Value::Array(unique)
+            }
+            Self::Flatten => {
+                let mut flat = Vec::new();
+                for inner in array {
+                    match inner {
+                        Value::Array(values) => flat.extend(values),
+                        Value::Null => {}
+                        other => {
+                            return Err(ExpressionError::type_error(
+                                &ValueType::Array(Box::new(ValueType::Null)),
+                                &other.value_type(),
+                                position,
+                            ))
+                        }
+                    }
+                }
+                Value::Array(flat)
+            }
+            // Negative indices count back from the end
+            Self::At => {
+                let index = match args.next() {
+                    Some(Value::Integer(index)) => index,
+                    other => {
+                        return Err(ExpressionError::type_error(
+                            &ValueType::Integer,
+                            &other.unwrap_or(Value::Null).value_type(),
+                            position,
+                        ))
+                    }
+                };
+                let index = if index < 0 {
+                    array.len() as i64 + index
+                } else {
+                    index
+                };
+                usize::try_from(index)
+                    .ok()
+                    .filter(|index| *index < array.len())
+                    .map_or(Value::Null, |index| array.swap_remove(index))
+            }
+            _ => unreachable!(),
+        })
+    }
+
+    fn not_convertible(value: &Value, position: Position) -> ExpressionError {
+        ExpressionError::type_error(&ValueType::String, &value.value_type(), position)
+    }
+
+    /// Aggregates a non-empty list of non-null values, keeping their type
+    fn aggregate(&self, values: Vec<Value>, position: Position) -> ExpressionResult<Value> {
+        let mismatch = |value: &Value| {
+            ExpressionError::type_error(&values[0].value_type(), &value.value_type(), position)
+        };
+        match &values[0] {
+            Value::Number(_) => {
+                let numbers = values
+                    .iter()
+                    .map(|value| match value {
+                        Value::Number(n) => Ok(*n),
+                        other => Err(mismatch(other)),
+                    })
+                    .collect::<ExpressionResult<Vec<f64>>>()?;
+                Ok(Value::Number(self.aggregate_numbers(numbers)))
+            }
+            Value::Integer(_) => {
+                let integers = values
+                    .iter()
+                    .map(|value| match value {
+                        Value::Integer(n) => Ok(*n),
+                        other => Err(mismatch(other)),
+                    })
+                    .collect::<ExpressionResult<Vec<i64>>>()?;
+                match self {
+                    // Summed exactly so only the total can overflow, as when
+                    // summed from a column's running total
+                    Self::Sum => i64::try_from(integers.into_iter().map(i128::from).sum::<i128>())
+                        .map(Value::Integer)
+                        .map_err(|_| ExpressionError::overflow(position)),
+                    Self::Mode => Ok(Value::Integer(mode(integers, i64::cmp))),
+                    _ => Err(ExpressionError::type_error(
+                        &ValueType::Number,
+                        &ValueType::Integer,
+                        position,
+                    )),
+                }
+            }
+            Value::Decimal(_) => {
+                let decimals = values
+                    .iter()
+                    .map(|value| match value {
+                        Value::Decimal(d) => Ok(*d),
+                        other => Err(mismatch(other)),
+                    })
+                    .collect::<ExpressionResult<Vec<Decimal>>>()?;
+                self.aggregate_decimals(decimals)
+                    .map(Value::Decimal)
+                    .ok_or_else(|| ExpressionError::overflow(position))
+            }
+            other => Err(ExpressionError::type_error(
+                &ValueType::Number,
+                &other.value_type(),
+                position,
+            )),
+        }
+    }
+
+    fn aggregate_numbers(&self, mut numbers: Vec<f64>) -> f64 {
+        match self {
+            Self::Sum => numbers.iter().sum(),
+            Self::Mean => numbers.iter().sum::<f64>() / numbers.len() as f64,
+            Self::Median => {
+                numbers.sort_by(f64::total_cmp);
+                let mid = numbers.len() / 2;
+                if numbers.len() % 2 == 0 {
+                    (numbers[mid - 1] + numbers[mid]) / 2.0
+                } else {
+                    numbers[mid]
+                }
+            }
+            Self::Mode => mode(numbers, f64::total_cmp),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Aggregates decimals exactly. Means are rounded to the largest scale.
+    fn aggregate_decimals(&self, mut decimals: Vec<Decimal>) -> Option<Decimal> {
+        let scale = decimals.iter().map(Decimal::scale).max()?;
+        let zero = Decimal::new(0, scale);
+        match self {
+            Self::Sum => decimals.iter().try_fold(zero, |sum, d| sum.checked_add(d)),
+            Self::Mean => decimals
+                .iter()
+                .try_fold(zero, |sum, d| sum.checked_add(d))?
+                .checked_div_count(decimals.len()),
+            Self::Median => {
+                decimals.sort();
+                let mid = decimals.len() / 2;
+                if decimals.len() % 2 == 0 {
+                    decimals[mid - 1]
+                        .checked_add(&decimals[mid])?
+                        .rescale(scale)?
+                        .checked_div_count(2)
+                } else {
+                    decimals[mid].rescale(scale)
+                }
+            }
+            Self::Mode => Some(mode(decimals, Decimal::cmp)),
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// The most common value in a non-empty list. Ties go to the smallest value,
+/// so the result is stable.
+fn mode<T: Copy>(mut values: Vec<T>, cmp: impl Fn(&T, &T) -> Ordering) -> T {
+    values.sort_by(&cmp);
+    let (mut best, mut best_count) = (values[0], 0);
+    let mut start = 0;
+    for i in 1..=values.len() {
+        if i == values.len() || cmp(&values[i], &values[start]) != Ordering::Equal {
+            if i - start > best_count {
+                best = values[start];
+                best_count = i - start;
+            }
+            start = i;
         }
     }
+    best
 }
@@ -0,0 +1,128 @@
+use serde_json::Value as JsonValue;
+
+/// A step in a JSON path
+#[derive(Debug, Clone, PartialEq)]
+pub enum PathSegment {
+    Key(String),
+    Index(usize),
+}
+
+/// A parsed JSON path such as `$.orders[0]['unit price']`.
+///
+/// Paths start at the root `$`, followed by any number of `.key`, `['key']`
+/// and `[index]` steps.
+#[derive(Debug, Clone, PartialEq)]
+pub struct JsonPath {
+    segments: Vec<PathSegment>,
+}
+
+impl JsonPath {
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let mut chars = source.chars().peekable();
+        if chars.next() != Some('$') {
+            return Err(format!("JSON path {:?} must start with $", source));
+        }
+        let mut segments = Vec::new();
+        while let Some(c) = chars.next() {
+            match c {
+                '.' => {
+                    let mut key = String::new();
+                    while let Some(&c) = chars.peek() {
+                        if c == '.' || c == '[' {
+                            break;
+                        }
+                        key.push(c);
+                        chars.next();
+                    }
+                    if key.is_empty() {
+                        return Err(format!("Empty key in JSON path {:?}", source));
+                    }
+                    segments.push(PathSegment::Key(key));
+                }
+                '[' => match chars.peek() {
+                    Some(&quote) if quote == '\'' || quote == '"' => {
+                        chars.next();
+                        let mut key = String::new();
+                        loop {
+                            match chars.next() {
+                                Some(c) if c == quote => break,
+                                Some(c) => key.push(c),
+                                None => {
+                                    return Err(format!(
+                                        "Unterminated key in JSON path {:?}",
+                                        source
+                                    ))
+                                }
+                            }
+                        }
+                        if chars.next() != Some(']') {
+                            return Err(format!("Expected ] in JSON path {:?}", source));
+                        }
+                        segments.push(PathSegment::Key(key));
+                    }
+                    _ => {
+                        let mut index = String::new();
+                        loop {
+                            match chars.next() {
+                                Some(']') => break,
+                                Some(c) => index.push(c),
+                                None => {
+                                    return Err(format!("Expected ] in JSON path {:?}", source))
+                                }
+                            }
+                        }
+                        let index = index.trim().parse().map_err(|_| {
+                            format!("Invalid index {:?} in JSON path {:?}", index, source)
+                        })?;
+                        segments.push(PathSegment::Index(index));
+                    }
+                },
+                other => return Err(format!("Unexpected {:?} in JSON path {:?}", other, source)),
+            }
+        }
+        Ok(JsonPath { segments })
+    }
+
+    /// Follows the path from `root`, returning `None` if any step is missing
+    pub fn get<'a>(&self, root: &'a JsonValue) -> Option<&'a JsonValue> {
+        self.segments
+            .iter()
+            .try_fold(root, |value, segment| match segment {
+                PathSegment::Key(key) => value.as_object()?.get(key),
+                PathSegment::Index(index) => value.as_array()?.get(*index),
+            })
+    }
+}
+
+/// The name of a JSON value's type, as returned by `json_type`
+pub fn json_type(value: &JsonValue) -> &'static str {
+    match value {
+        JsonValue::Null => "null",
+        JsonValue::Bool(_) => "boolean",
+        JsonValue::Number(_) => "number",
+        JsonValue::String(_) => "string",
+        JsonValue::Array(_) => "array",
+        JsonValue::Object(_) => "object",
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn paths() {
+        let value: JsonValue =
+            serde_json::from_str(r#"{"a": {"b": [10, 20]}, "c d": true}"#).unwrap();
+        let get = |path: &str| JsonPath::parse(path).unwrap().get(&value).cloned();
+
+        assert_eq!(get("$.a.b[1]"), Some(JsonValue::from(20)));
+        assert_eq!(get("$['c d']"), Some(JsonValue::Bool(true)));
+        assert_eq!(get("$"), Some(value.clone()));
+        assert_eq!(get("$.a.b[2]"), None);
+        assert_eq!(get("$.a.x"), None);
+        assert!(JsonPath::parse("a.b").is_err());
+        assert!(JsonPath::parse("$.a[x]").is_err());
+        assert!(JsonPath::parse("$.a['b").is_err());
+    }
+}
@@ -1,10 +1,18 @@
+mod categorical;
+mod context;
+mod decimal;
 mod error;
 mod expression;
 mod function;
+mod json;
 mod parser;
 mod value;
 
-pub use error::{ExpressionError, ExpressionResult};
+pub use categorical::{CategoricalVec, Categories, Category};
+pub use context::{ColumnView, Context, EmptyContext};
+pub use decimal::{Decimal, MAX_SCALE};
+pub use error::{ErrorKind, ErrorReport, ExpressionError, ExpressionResult};
 pub use expression::Expression;
 pub use function::Function;
+pub use parser::{ColumnRef, ColumnSpec};
 pub use value::{Value, ValueType};
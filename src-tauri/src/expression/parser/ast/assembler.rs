@@ -4,7 +4,9 @@ use crate::expression::parser::{Position, Token, TokenType, TokenValue, Tokenise
 
 use super::{
     error::ParseResult,
-    node::{BinaryOpType, Identifier, Node, UnaryOpType},
+    node::{
+        BinaryOpType, ColumnRef, ColumnSpec, Identifier, Node, RowBound, RowSelector, UnaryOpType,
+    },
     ParseError,
 };
 
@@ -33,19 +35,29 @@ impl Assembler {
         self.current = self.source.next().unwrap_or(Token::eof());
     }
 
-    fn parse(&mut self) -> ParseResult<Node> {
+    pub fn parse(&mut self) -> ParseResult<Node> {
         debug!("Parsing expression");
-        self.expect(TokenType::Assign);
-        self.parse_or_expr()
+        self.expect(TokenType::Assign)?;
+        let node = self.parse_or_expr()?;
+        if self.current.token_type != TokenType::EOF {
+            return Err(ParseError::expected(
+                &TokenType::EOF,
+                &self.current.token_type,
+                self.current.position,
+            ));
+        }
+        Ok(node)
     }
 
     fn parse_or_expr(&mut self) -> ParseResult<Node> {
         let mut node = self.parse_and_expr()?;
         while self.current.token_type == TokenType::Or {
+            let position = self.current.position;
             self.advance();
             node = Node::BinaryOp {
                 left: Box::new(node),
                 right: Some((BinaryOpType::Or, Box::new(self.parse_and_expr()?))),
+                position,
             };
         }
         Ok(node)
@@ -54,10 +66,12 @@ impl Assembler {
     fn parse_and_expr(&mut self) -> ParseResult<Node> {
         let mut node = self.parse_not_expr()?;
         while self.current.token_type == TokenType::And {
+            let position = self.current.position;
             self.advance();
             node = Node::BinaryOp {
                 left: Box::new(node),
                 right: Some((BinaryOpType::And, Box::new(self.parse_not_expr()?))),
+                position,
             };
         }
         Ok(node)
@@ -65,10 +79,12 @@ impl Assembler {
 
     fn parse_not_expr(&mut self) -> ParseResult<Node> {
         if self.current.token_type == TokenType::Not {
+            let position = self.current.position;
             self.advance();
             Ok(Node::UnaryOp {
                 op: Some(UnaryOpType::Not),
                 right: Box::new(self.parse_not_expr()?),
+                position,
             })
         } else {
             self.parse_cmp_expr()
@@ -84,12 +100,15 @@ impl Assembler {
             TokenType::GreaterThan => Some(BinaryOpType::Gt),
             TokenType::LTEqual => Some(BinaryOpType::Le),
             TokenType::GTEqual => Some(BinaryOpType::Ge),
+            TokenType::In => Some(BinaryOpType::In),
             _ => None,
         } {
+            let position = self.current.position;
             self.advance();
             node = Node::BinaryOp {
                 left: Box::new(node),
                 right: Some((op, Box::new(self.parse_add_expr()?))),
+                position,
             };
         }
         Ok(node)
@@ -102,10 +121,12 @@ impl Assembler {
             TokenType::Minus => Some(BinaryOpType::Sub),
             _ => None,
         } {
+            let position = self.current.position;
             self.advance();
             node = Node::BinaryOp {
                 left: Box::new(node),
                 right: Some((op, Box::new(self.parse_mul_expr()?))),
+                position,
             };
         }
         Ok(node)
@@ -119,10 +140,12 @@ impl Assembler {
             TokenType::Mod => Some(BinaryOpType::Mod),
             _ => None,
         } {
+            let position = self.current.position;
             self.advance();
             node = Node::BinaryOp {
                 left: Box::new(node),
                 right: Some((op, Box::new(self.parse_pow_expr()?))),
+                position,
             };
         }
         Ok(node)
@@ -131,10 +154,12 @@ impl Assembler {
     fn parse_pow_expr(&mut self) -> ParseResult<Node> {
         let mut node = self.parse_unary_expr()?;
         while self.current.token_type == TokenType::Exp {
+            let position = self.current.position;
             self.advance();
             node = Node::BinaryOp {
                 left: Box::new(node),
                 right: Some((BinaryOpType::Pow, Box::new(self.parse_unary_expr()?))),
+                position,
             };
         }
         Ok(node)
@@ -142,10 +167,12 @@ impl Assembler {
 
     fn parse_unary_expr(&mut self) -> ParseResult<Node> {
         if self.current.token_type == TokenType::Minus {
+            let position = self.current.position;
             self.advance();
             Ok(Node::UnaryOp {
                 op: Some(UnaryOpType::Neg),
                 right: Box::new(self.parse_primary()?),
+                position,
             })
         } else {
             self.parse_primary()
@@ -170,6 +197,14 @@ impl Assembler {
                 self.advance();
                 Ok(Node::Number(value))
             }
+            TokenType::Integer => {
+                let value = match self.current.value {
+                    TokenValue::Integer(n) => n,
+                    _ => unreachable!(),
+                };
+                self.advance();
+                Ok(Node::Integer(value))
+            }
             TokenType::Boolean => {
                 let value = match self.current.value {
                     TokenValue::Boolean(b) => b,
@@ -178,11 +213,16 @@ impl Assembler {
                 self.advance();
                 Ok(Node::Boolean(value))
             }
+            TokenType::Null => {
+                self.advance();
+                Ok(Node::Null)
+            }
             TokenType::Identifier => {
                 let value = match self.current.value {
                     TokenValue::String(ref s) => s.clone(),
                     _ => unreachable!(),
                 };
+                let position = self.current.position;
                 self.advance();
                 if self.current.token_type == TokenType::OpenParen {
                     self.advance();
@@ -195,13 +235,28 @@ impl Assembler {
                     }
                     self.expect(TokenType::CloseParen)?;
                     Ok(Node::Function {
-                        name: Identifier(value),
+                        name: Identifier(value, position),
                         args,
                     })
                 } else {
-                    Ok(Node::Identifier(Identifier(value)))
+                    Ok(Node::Identifier(Identifier(value, position)))
                 }
             }
+            TokenType::Colon => self.parse_column(),
+            TokenType::OpenBracket => {
+                let position = self.current.position;
+                self.advance();
+                let mut items = Vec::new();
+                while self.current.token_type != TokenType::CloseBracket {
+                    items.push(self.parse_or_expr()?);
+                    if self.current.token_type != TokenType::Comma {
+                        break;
+                    }
+                    self.advance();
+                }
+                self.expect(TokenType::CloseBracket)?;
+                Ok(Node::Array { items, position })
+            }
             TokenType::OpenParen => {
                 self.advance();
                 let node = self.parse_or_expr()?;
@@ -212,7 +267,87 @@ impl Assembler {
         }
     }
 
-    fn from_string(input: &str) -> ParseResult<Self> {
+    /// Parses a column reference: `:'name'`, `:index`, or either of those
+    /// qualified with a sheet as `:'Sheet'!'name'`
+    fn parse_column(&mut self) -> ParseResult<Node> {
+        let position = self.current.position;
+        self.expect(TokenType::Colon)?;
+        let mut sheet = None;
+        if let (TokenType::String, TokenValue::String(name)) =
+            (&self.current.token_type, &self.current.value)
+        {
+            let name = name.clone();
+            self.advance();
+            if self.current.token_type != TokenType::Not {
+                let reference = ColumnRef {
+                    sheet: None,
+                    column: ColumnSpec::Name(name),
+                };
+                return self.parse_rows(reference, position);
+            }
+            self.advance();
+            sheet = Some(name);
+        }
+        let column = match (&self.current.token_type, &self.current.value) {
+            (TokenType::String, TokenValue::String(name)) => ColumnSpec::Name(name.clone()),
+            (TokenType::Integer, TokenValue::Integer(index)) if *index >= 0 => {
+                ColumnSpec::Index(*index as usize)
+            }
+            _ => return Err(ParseError::expected_column(&self.current)),
+        };
+        self.advance();
+        self.parse_rows(ColumnRef { sheet, column }, position)
+    }
+
+    /// Parses the rows picked in brackets after a column reference, as
+    /// `[0]`, `[-1]` or `[10..20]`, if there are any
+    fn parse_rows(&mut self, reference: ColumnRef, position: Position) -> ParseResult<Node> {
+        if self.current.token_type != TokenType::OpenBracket {
+            return Ok(Node::Column {
+                reference,
+                position,
+            });
+        }
+        self.advance();
+        let start = self.parse_row_bound()?;
+        let rows = if self.current.token_type == TokenType::Range {
+            self.advance();
+            RowSelector::Slice(start, self.parse_row_bound()?)
+        } else {
+            RowSelector::Row(start.ok_or_else(|| ParseError::expected_rows(&self.current))?)
+        };
+        self.expect(TokenType::CloseBracket)?;
+        Ok(Node::Rows {
+            reference,
+            rows,
+            position,
+        })
+    }
+
+    /// Parses a row in brackets, which is relative if it has a sign. Returns
+    /// `None` if there is no row, as at the open end of a slice.
+    fn parse_row_bound(&mut self) -> ParseResult<Option<RowBound>> {
+        let sign = match self.current.token_type {
+            TokenType::Plus => Some(1),
+            TokenType::Minus => Some(-1),
+            _ => None,
+        };
+        if sign.is_some() {
+            self.advance();
+        }
+        let row = match (&self.current.token_type, &self.current.value) {
+            (TokenType::Integer, TokenValue::Integer(row)) => *row,
+            _ if sign.is_some() => return Err(ParseError::expected_rows(&self.current)),
+            _ => return Ok(None),
+        };
+        self.advance();
+        Ok(Some(match sign {
+            Some(sign) => RowBound::Relative(sign * row),
+            None => RowBound::Absolute(row as usize),
+        }))
+    }
+
+    pub fn from_string(input: &str) -> ParseResult<Self> {
         let mut source = Tokeniser::from_string(&input.to_string());
         let t = source.next().ok_or(ParseError::unexpected_eof())?;
         Ok(Assembler {
@@ -245,6 +380,80 @@ mod test {
         }
     }
 
+    #[test]
+    fn columns() {
+        let parse = |source: &str| Assembler::from_string(source).unwrap().parse();
+        let reference = |source: &str| match parse(source).unwrap() {
+            Node::Column { reference, .. } => reference,
+            other => panic!("expected a column, found {}", other),
+        };
+
+        assert_eq!(
+            reference("=:'Orders'!'amount'"),
+            ColumnRef {
+                sheet: Some("Orders".to_string()),
+                column: ColumnSpec::Name("amount".to_string()),
+            }
+        );
+        assert_eq!(reference("=:2").column, ColumnSpec::Index(2));
+        assert_eq!(reference("=:'Orders'!2").to_string(), ":'Orders'!2");
+        assert_eq!(reference("=:'it\\'s'").to_string(), ":'it\\'s'");
+        assert!(parse("=:'a' != 1").is_ok());
+        assert!(parse("=:x").is_err());
+        assert!(parse("=:'Orders'!").is_err());
+    }
+
+    #[test]
+    fn rows() {
+        let parse = |source: &str| Assembler::from_string(source).unwrap().parse();
+        let rows = |source: &str| match parse(source).unwrap() {
+            Node::Rows { rows, .. } => rows,
+            other => panic!("expected rows, found {}", other),
+        };
+
+        assert_eq!(
+            rows("=:'price'[10..20]"),
+            RowSelector::Slice(Some(RowBound::Absolute(10)), Some(RowBound::Absolute(20)))
+        );
+        assert_eq!(
+            rows("=:'price'[-1]"),
+            RowSelector::Row(RowBound::Relative(-1))
+        );
+        assert_eq!(
+            rows("=:'Orders'!2[0]"),
+            RowSelector::Row(RowBound::Absolute(0))
+        );
+        assert_eq!(
+            rows("=:'price'[..+1]"),
+            RowSelector::Slice(None, Some(RowBound::Relative(1)))
+        );
+        assert_eq!(rows("=:'price'[-2..]").to_string(), "[-2..]");
+        for source in ["=:'price'[]", "=:'price'[-]", "=:'price'[1", "=:'price'[x]"] {
+            assert!(parse(source).is_err(), "{}", source);
+        }
+    }
+
+    #[test]
+    fn arrays() {
+        let parse = |source: &str| Assembler::from_string(source).unwrap().parse();
+
+        assert_eq!(
+            parse("=:'region' in ['EU', 'UK']").unwrap().to_string(),
+            "(:'region' in [EU, UK])"
+        );
+        assert_eq!(
+            parse("=[[1], [], [2, 3]]").unwrap().to_string(),
+            "[[1], [], [2, 3]]"
+        );
+        assert_eq!(
+            parse("=[1, 2] * 2 == [2, 4]").unwrap().to_string(),
+            "(([1, 2] * 2) == [2, 4])"
+        );
+        for source in ["=[1, 2", "=[1 2]", "=[,]", "=1 in"] {
+            assert!(parse(source).is_err(), "{}", source);
+        }
+    }
+
     #[test]
     fn test_assembler() {
         let _logger = init_logger();
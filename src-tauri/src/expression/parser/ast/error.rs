@@ -5,7 +5,7 @@ use log::{error, warn};
 
 pub type ParseResult<T> = Result<T, ParseError>;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct ParseError {
     pub message: String,
     pub position: Option<Position>,
@@ -59,6 +59,7 @@ impl ParseError {
                 &[
                     TokenType::String,
                     TokenType::Number,
+                    TokenType::Integer,
                     TokenType::Identifier,
                     TokenType::OpenParen
                 ],
@@ -68,6 +69,26 @@ impl ParseError {
         )
     }
 
+    pub fn expected_column(token: &Token) -> Self {
+        ParseError::new(
+            &format!(
+                "Expected a column name or index after :, found {:?}",
+                token.token_type
+            ),
+            Some(&token.position),
+        )
+    }
+
+    pub fn expected_rows(token: &Token) -> Self {
+        ParseError::new(
+            &format!(
+                "Expected a row such as [0] or [-1], or rows such as [10..20], found {:?}",
+                token.token_type
+            ),
+            Some(&token.position),
+        )
+    }
+
     pub fn warn(&self) {
         match self.source {
             Some(ref error) => error!("{}: {}", self, error),
@@ -4,4 +4,7 @@ mod node;
 
 pub use assembler::Assembler;
 pub use error::{ParseError, ParseResult};
-pub use node::{BinaryOpType, Identifier, Node, UnaryOpType};
+pub use node::{
+    quote, BinaryOpType, ColumnRef, ColumnSpec, Identifier, Node, RowBound, RowSelector,
+    UnaryOpType,
+};
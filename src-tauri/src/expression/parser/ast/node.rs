@@ -1,5 +1,7 @@
 use log::{debug, info};
 
+use super::super::Position;
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum BinaryOpType {
     Add,
@@ -16,6 +18,7 @@ pub enum BinaryOpType {
     Ge,
     And,
     Or,
+    In,
 }
 
 impl std::fmt::Display for BinaryOpType {
@@ -35,6 +38,7 @@ impl std::fmt::Display for BinaryOpType {
             BinaryOpType::Ge => write!(f, ">="),
             BinaryOpType::And => write!(f, "&"),
             BinaryOpType::Or => write!(f, "|"),
+            BinaryOpType::In => write!(f, "in"),
         }
     }
 }
@@ -49,7 +53,8 @@ impl BinaryOpType {
             | BinaryOpType::Lt
             | BinaryOpType::Le
             | BinaryOpType::Gt
-            | BinaryOpType::Ge => 4,
+            | BinaryOpType::Ge
+            | BinaryOpType::In => 4,
             BinaryOpType::Add | BinaryOpType::Sub => 5,
             BinaryOpType::Mul | BinaryOpType::Div | BinaryOpType::Mod => 6,
             BinaryOpType::Pow => 7,
@@ -82,43 +87,173 @@ impl UnaryOpType {
 }
 
 #[derive(Debug, Clone)]
-pub struct Identifier(pub String);
+pub struct Identifier(pub String, pub Position);
+
+/// Quotes a string so the tokeniser reads it back unchanged
+pub fn quote(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('\'');
+    for c in value.chars() {
+        match c {
+            '\\' => quoted.push_str("\\\\"),
+            '\'' => quoted.push_str("\\'"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('\'');
+    quoted
+}
+
+/// How a column reference picks its column
+#[derive(Debug, Clone, PartialEq)]
+pub enum ColumnSpec {
+    /// `:'amount'`
+    Name(String),
+    /// `:2`, counting from 0 in schema order
+    Index(usize),
+}
+
+/// A reference to a column, such as `:'amount'` or `:'Orders'!'amount'`.
+/// References without a sheet refer to the sheet the expression belongs to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnRef {
+    pub sheet: Option<String>,
+    pub column: ColumnSpec,
+}
+
+impl std::fmt::Display for ColumnRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, ":")?;
+        if let Some(sheet) = &self.sheet {
+            write!(f, "{}!", quote(sheet))?;
+        }
+        match &self.column {
+            ColumnSpec::Name(name) => write!(f, "{}", quote(name)),
+            ColumnSpec::Index(index) => write!(f, "{}", index),
+        }
+    }
+}
+
+/// A row in brackets after a column reference. A sign makes it count from
+/// the row being evaluated, so `-1` is the row before it and `+0` the row
+/// itself, while an unsigned row counts from the top of the column.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RowBound {
+    Absolute(usize),
+    Relative(i64),
+}
+
+impl RowBound {
+    /// The row this picks, which may be before the first row, given the
+    /// row being evaluated if there is one
+    pub fn resolve(&self, current: Option<usize>) -> Option<i64> {
+        match self {
+            RowBound::Absolute(row) => i64::try_from(*row).ok(),
+            RowBound::Relative(offset) => i64::try_from(current?).ok()?.checked_add(*offset),
+        }
+    }
+}
+
+impl std::fmt::Display for RowBound {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RowBound::Absolute(row) => write!(f, "{}", row),
+            RowBound::Relative(offset) => write!(f, "{:+}", offset),
+        }
+    }
+}
+
+/// The rows picked by brackets after a column reference
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RowSelector {
+    /// `:'price'[0]` or `:'price'[-1]`, one value
+    Row(RowBound),
+    /// `:'price'[10..20]`, the rows from the start up to but not including
+    /// the end, as a column. Either end can be left off.
+    Slice(Option<RowBound>, Option<RowBound>),
+}
+
+impl std::fmt::Display for RowSelector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RowSelector::Row(bound) => write!(f, "[{}]", bound),
+            RowSelector::Slice(start, end) => {
+                write!(f, "[")?;
+                if let Some(start) = start {
+                    write!(f, "{}", start)?;
+                }
+                write!(f, "..")?;
+                if let Some(end) = end {
+                    write!(f, "{}", end)?;
+                }
+                write!(f, "]")
+            }
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
 pub enum Node {
     BinaryOp {
         left: Box<Node>,
         right: Option<(BinaryOpType, Box<Node>)>,
+        position: Position,
     },
     UnaryOp {
         op: Option<UnaryOpType>,
         right: Box<Node>,
+        position: Position,
     },
     Number(f64),
+    Integer(i64),
     String(String),
     Boolean(bool),
+    Null,
     Identifier(Identifier),
+    Column {
+        reference: ColumnRef,
+        position: Position,
+    },
+    /// Some rows of a column, as `:'price'[-1]` or `:'price'[10..20]`
+    Rows {
+        reference: ColumnRef,
+        rows: RowSelector,
+        position: Position,
+    },
     Function {
         name: Identifier,
         args: Vec<Node>,
     },
+    /// An array literal, as `[1, 2, 3]`
+    Array {
+        items: Vec<Node>,
+        position: Position,
+    },
 }
 
 impl std::fmt::Display for Node {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Node::BinaryOp { left, right } => match right {
+            Node::BinaryOp { left, right, .. } => match right {
                 Some((op, right)) => write!(f, "({} {} {})", left, op, right),
                 None => write!(f, "{}", left),
             },
-            Node::UnaryOp { op, right } => match op {
+            Node::UnaryOp { op, right, .. } => match op {
                 Some(op) => write!(f, "{}{}", op, right),
                 None => write!(f, "{}", right),
             },
-            Node::Number(n) => write!(f, "{}", n),
+            Node::Number(n) => write!(f, "{:?}", n),
+            Node::Integer(n) => write!(f, "{}", n),
             Node::String(s) => write!(f, "{}", s),
             Node::Boolean(b) => write!(f, "{}", b),
+            Node::Null => write!(f, "null"),
             Node::Identifier(id) => write!(f, "{}", id.0),
+            Node::Column { reference, .. } => write!(f, "{}", reference),
+            Node::Rows {
+                reference, rows, ..
+            } => write!(f, "{}{}", reference, rows),
             Node::Function { name, args } => {
                 write!(f, "{}(", name.0)?;
                 for (i, arg) in args.iter().enumerate() {
@@ -129,6 +264,16 @@ impl std::fmt::Display for Node {
                 }
                 write!(f, ")")
             }
+            Node::Array { items, .. } => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            }
         }
     }
 }
@@ -136,7 +281,7 @@ impl std::fmt::Display for Node {
 impl Node {
     pub fn pprint(&self, indent: usize) {
         match self {
-            Node::BinaryOp { left, right } => {
+            Node::BinaryOp { left, right, .. } => {
                 if let Some((op, right)) = right {
                     println!(
                         "{:indent$}<BinaryOp BinOpType ={:?}>",
@@ -152,7 +297,7 @@ impl Node {
                 }
                 println!("{:indent$}</BinaryOp>", "", indent = indent);
             }
-            Node::UnaryOp { op, right } => {
+            Node::UnaryOp { op, right, .. } => {
                 if let Some(op) = op {
                     println!(
                         "{:indent$}<UnaryOp UnaryOpType ={:?}>",
@@ -169,15 +314,35 @@ impl Node {
             Node::Number(n) => {
                 println!("{:indent$}<Number ={:?}>", "", n, indent = indent);
             }
+            Node::Integer(n) => {
+                println!("{:indent$}<Integer ={:?}>", "", n, indent = indent);
+            }
             Node::String(s) => {
                 println!("{:indent$}<String ={:?}>", "", s, indent = indent);
             }
             Node::Boolean(b) => {
                 println!("{:indent$}<Boolean ={:?}>", "", b, indent = indent);
             }
+            Node::Null => {
+                println!("{:indent$}<Null>", "", indent = indent);
+            }
             Node::Identifier(id) => {
                 println!("{:indent$}<Identifier ={:?}>", "", id, indent = indent);
             }
+            Node::Column { reference, .. } => {
+                println!("{:indent$}<Column ={}>", "", reference, indent = indent);
+            }
+            Node::Rows {
+                reference, rows, ..
+            } => {
+                println!(
+                    "{:indent$}<Rows ={}{}>",
+                    "",
+                    reference,
+                    rows,
+                    indent = indent
+                );
+            }
             Node::Function { name, args } => {
                 println!(
                     "{:indent$}<Function FunctionName ={:?}>",
@@ -190,12 +355,19 @@ impl Node {
                 }
                 println!("{:indent$}</Function>", "", indent = indent);
             }
+            Node::Array { items, .. } => {
+                println!("{:indent$}<Array>", "", indent = indent);
+                for item in items {
+                    item.pprint(indent + 2);
+                }
+                println!("{:indent$}</Array>", "", indent = indent);
+            }
         }
     }
 
     pub fn reduce(&self) -> Box<Node> {
         return match self {
-            Node::BinaryOp { left, right } => match right {
+            Node::BinaryOp { left, right, .. } => match right {
                 Some((op, right)) => {
                     left.reduce();
                     right.reduce();
@@ -204,7 +376,7 @@ impl Node {
                 None => left.reduce(),
             },
 
-            Node::UnaryOp { op, right } => match op {
+            Node::UnaryOp { op, right, .. } => match op {
                 Some(op) => {
                     right.reduce();
                     Box::new(self.clone())
@@ -225,14 +397,13 @@ impl Node {
     fn precedence(&self) -> u8 {
         match self {
             Node::BinaryOp {
-                left: _,
                 right: Some((op, _)),
+                ..
             } => op.precedence(),
-            Node::BinaryOp { left, right: None } => left.precedence(),
-            Node::UnaryOp {
-                op: Some(op),
-                right: _,
-            } => op.precedence(),
+            Node::BinaryOp {
+                left, right: None, ..
+            } => left.precedence(),
+            Node::UnaryOp { op: Some(op), .. } => op.precedence(),
             _ => u8::MAX,
         }
     }
@@ -242,6 +413,7 @@ impl Node {
             Node::BinaryOp {
                 left,
                 right: Some((op, right)),
+                ..
             } => {
                 debug!("making expr from binary op {}|{}|{} ", left, op, right);
                 let mut s = "".to_string();
@@ -337,18 +509,22 @@ mod test {
         let ast = Node::BinaryOp {
             left: Box::new(Node::Number(1.0)),
             right: Some((BinaryOpType::Add, Box::new(Node::Number(2.0)))),
+            position: Position::default(),
         };
         let ast = Node::BinaryOp {
             left: Box::new(ast.clone()),
             right: Some((BinaryOpType::Mul, Box::new(ast))),
+            position: Position::default(),
         };
         let ast = Node::BinaryOp {
             left: Box::new(ast),
             right: Some((BinaryOpType::Pow, Box::new(Node::Number(8.0)))),
+            position: Position::default(),
         };
         let ast = Node::BinaryOp {
             left: Box::new(Node::Number(5.0)),
             right: Some((BinaryOpType::Add, Box::new(ast))),
+            position: Position::default(),
         };
         println!("{}", ast.make_expr());
     }
@@ -363,14 +539,19 @@ mod test {
                         left: Box::new(Node::BinaryOp {
                             left: Box::new(Node::Number(1.0)),
                             right: Some((BinaryOpType::Add, Box::new(Node::Number(2.0)))),
+                            position: Position::default(),
                         }),
                         right: None,
+                        position: Position::default(),
                     }),
                     right: None,
+                    position: Position::default(),
                 }),
                 right: Some((BinaryOpType::Mul, Box::new(Node::Number(3.0)))),
+                position: Position::default(),
             }),
             right: Some((BinaryOpType::Pow, Box::new(Node::Number(4.0)))),
+            position: Position::default(),
         };
         let reduced_ast = ast.reduce();
         println!("Original AST:");
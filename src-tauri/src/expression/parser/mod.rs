@@ -6,4 +6,6 @@ pub use position::Position;
 
 pub use tokeniser::{Token, TokenError, TokenType, TokenValue, Tokeniser};
 
-pub use ast::{Assembler, Node, ParseError, ParseResult};
+pub use ast::{
+    Assembler, ColumnRef, ColumnSpec, Node, ParseError, ParseResult, RowBound, RowSelector,
+};
@@ -1,11 +1,11 @@
-use std::num::ParseFloatError;
+use std::num::{ParseFloatError, ParseIntError};
 
 use super::super::Position;
 use log::warn;
 
 pub type TokenResult<T> = Result<T, TokenError>;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct TokenError {
     pub message: String,
     pub position: Option<Position>,
@@ -79,3 +79,12 @@ impl From<ParseFloatError> for TokenError {
         }
     }
 }
+
+impl From<ParseIntError> for TokenError {
+    fn from(error: ParseIntError) -> Self {
+        TokenError {
+            message: format!("Failed to parse integer: {}", error),
+            position: None,
+        }
+    }
+}
@@ -4,10 +4,14 @@ use super::super::Position;
 pub enum TokenType {
     /// A string
     String,
-    /// A number
+    /// A number with a fractional part or exponent
     Number,
+    /// A whole number
+    Integer,
     /// A boolean value
     Boolean,
+    /// The null literal
+    Null,
     /// An identifier to a variable or function
     Identifier,
     /// ( - Open parenthesis
@@ -26,6 +30,8 @@ pub enum TokenType {
     Comma,
     /// : - Colon
     Colon,
+    /// .. - Range of rows
+    Range,
     /// + - Plus
     Plus,
     /// - - Minus
@@ -62,6 +68,8 @@ pub enum TokenType {
     GTEqual,
     /// @ - Column specifier
     At,
+    /// in - Membership of an array
+    In,
     /// EOF - End of file
     EOF,
 }
@@ -70,6 +78,7 @@ pub enum TokenType {
 pub enum TokenValue {
     String(String),
     Number(f64),
+    Integer(i64),
     Boolean(bool),
     Symbol,
 }
@@ -98,6 +107,14 @@ impl Token {
         }
     }
 
+    pub fn integer(value: i64, position: &Position) -> Self {
+        Token {
+            token_type: TokenType::Integer,
+            value: TokenValue::Integer(value),
+            position: position.to_owned(),
+        }
+    }
+
     pub fn identifier(value: String, position: &Position) -> Self {
         Token {
             token_type: TokenType::Identifier,
@@ -114,6 +131,14 @@ impl Token {
         }
     }
 
+    pub fn null(position: &Position) -> Self {
+        Token {
+            token_type: TokenType::Null,
+            value: TokenValue::Symbol,
+            position: position.to_owned(),
+        }
+    }
+
     pub fn symbol(token_type: TokenType, position: &Position) -> Self {
         Token {
             token_type,
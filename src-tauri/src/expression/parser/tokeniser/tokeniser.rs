@@ -81,11 +81,28 @@ impl Tokeniser {
         if self.buffer.len() < self.bufsiz / 2 {
             self.pad_buffer()?;
         }
-        Ok(self
-            .buffer
-            .front()
+        self.buffer.front().copied().ok_or(TokenError::exhausted(1))
+    }
+
+    /// Looks `offset` chars past the current one without consuming anything
+    fn peek_char(&mut self, offset: usize) -> TokenResult<char> {
+        if self.buffer.len() <= offset {
+            self.pad_buffer()?;
+        }
+        self.buffer
+            .get(offset)
             .copied()
-            .ok_or(TokenError::exhausted(1))?)
+            .ok_or(TokenError::exhausted(offset + 1))
+    }
+
+    fn push_digits(&mut self, value: &mut String) -> TokenResult<char> {
+        let mut c = self.get_char()?;
+        while c.is_ascii_digit() {
+            value.push(c);
+            self.advance()?;
+            c = self.get_char()?;
+        }
+        Ok(c)
     }
 
     pub fn get_token(&mut self) -> TokenResult<Token> {
@@ -99,17 +116,56 @@ impl Tokeniser {
         match c {
             '0'..='9' => {
                 trace!("Found number");
-                while c.is_digit(10) {
+                c = self.push_digits(&mut value)?;
+                let mut fractional = false;
+                // A dot is only part of the number if a digit follows it
+                if c == '.' && self.peek_char(1)?.is_ascii_digit() {
+                    trace!("Found fraction");
+                    fractional = true;
                     value.push(c);
                     self.advance()?;
-                    c = self.get_char()?;
+                    c = self.push_digits(&mut value)?;
+                }
+                if c == 'e' || c == 'E' {
+                    let next = self.peek_char(1)?;
+                    let signed = next == '-' || next == '+';
+                    if next.is_ascii_digit() || (signed && self.peek_char(2)?.is_ascii_digit()) {
+                        trace!("Found exponent");
+                        fractional = true;
+                        value.push(c);
+                        self.advance()?;
+                        if signed {
+                            value.push(next);
+                            self.advance()?;
+                        }
+                        self.push_digits(&mut value)?;
+                    }
+                }
+                if fractional {
+                    debug!("Number: {}", value);
+                    Ok(Token::number(
+                        value.parse().map_err(|e| {
+                            let mut error = TokenError::from(e);
+                            error.position = Some(start);
+                            error
+                        })?,
+                        &start,
+                    ))
+                } else {
+                    debug!("Integer: {}", value);
+                    Ok(Token::integer(
+                        value.parse().map_err(|e| {
+                            let mut error = TokenError::from(e);
+                            error.position = Some(start);
+                            error
+                        })?,
+                        &start,
+                    ))
                 }
-                debug!("Number: {}", value);
-                Ok(Token::number(value.parse()?, &start))
             }
             'a'..='z' | 'A'..='Z' | '_' => {
                 trace!("Found identifier");
-                while c.is_alphanumeric() {
+                while c.is_alphanumeric() || c == '_' {
                     value.push(c);
                     self.advance()?;
                     c = self.get_char()?;
@@ -120,6 +176,12 @@ impl Tokeniser {
                         if value == "true" { true } else { false },
                         &start,
                     ))
+                } else if value == "null" {
+                    debug!("Null");
+                    Ok(Token::null(&start))
+                } else if value == "in" {
+                    debug!("Symbol: in");
+                    Ok(Token::symbol(TokenType::In, &start))
                 } else {
                     debug!("Identifier: {}", value);
                     Ok(Token::identifier(value, &start))
@@ -295,6 +357,12 @@ impl Tokeniser {
                 debug!("Symbol: :");
                 Ok(Token::symbol(TokenType::Colon, &start))
             }
+            '.' if self.peek_char(1)? == '.' => {
+                self.advance()?;
+                self.advance()?;
+                debug!("Symbol: ..");
+                Ok(Token::symbol(TokenType::Range, &start))
+            }
             '+' => {
                 self.advance()?;
                 debug!("Symbol: +");
@@ -380,4 +448,45 @@ mod test {
             }
         }
     }
+
+    #[test]
+    fn numbers() {
+        use super::super::token::TokenValue;
+
+        let tokens = super::Tokeniser::from_string(&"12 1.5 2e3 4.0e-2 7.".to_string())
+            .map(|token| token.value)
+            .collect::<Vec<_>>();
+        assert!(matches!(tokens[0], TokenValue::Integer(12)));
+        assert!(matches!(tokens[1], TokenValue::Number(n) if n == 1.5));
+        assert!(matches!(tokens[2], TokenValue::Number(n) if n == 2000.0));
+        assert!(matches!(tokens[3], TokenValue::Number(n) if n == 0.04));
+        // The trailing dot is not part of the number, and is not a valid char
+        assert!(matches!(tokens[4], TokenValue::Integer(7)));
+        assert_eq!(tokens.len(), 5);
+    }
+
+    #[test]
+    fn ranges() {
+        use super::TokenType;
+
+        let tokens = super::Tokeniser::from_string(&"[10..20] [..-1]".to_string())
+            .map(|token| token.token_type)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            tokens,
+            [
+                TokenType::OpenBracket,
+                TokenType::Integer,
+                TokenType::Range,
+                TokenType::Integer,
+                TokenType::CloseBracket,
+                TokenType::OpenBracket,
+                TokenType::Range,
+                TokenType::Minus,
+                TokenType::Integer,
+                TokenType::CloseBracket,
+                TokenType::EOF,
+            ]
+        );
+    }
 }
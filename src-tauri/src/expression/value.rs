@@ -1,31 +1,213 @@
-#[derive(Debug, PartialEq)]
+use std::{cmp::Ordering, sync::Arc};
+
+use super::{Categories, Category, ColumnView, Decimal, ErrorKind, ExpressionError};
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Value {
     Number(f64),
+    Integer(i64),
+    Decimal(Decimal),
     String(String),
     Boolean(bool),
+    Categorical(Category),
+    Json(Arc<serde_json::Value>),
     Array(Vec<Value>),
-    Column(Box<Value>),
+    /// A whole column, as passed to aggregations
+    Column(Arc<dyn ColumnView>),
     Null,
+    /// The result of a failed evaluation, with the error that caused it
+    Error(ErrorKind, Box<ExpressionError>),
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum ValueType {
     Number,
+    Integer,
+    /// A fixed-point decimal with the given number of fractional digits
+    Decimal(u32),
     String,
     Boolean,
-    Array,
+    /// One of a declared set of labels
+    Categorical(Arc<Categories>),
+    /// Any JSON document
+    Json,
+    /// A list of values of the inner type
+    Array(Box<ValueType>),
+    /// A column holding values of the inner type
+    Column(Box<ValueType>),
     Null,
+    Error,
+    /// A value of the inner type, or null
+    Nullable(Box<ValueType>),
 }
 
 impl Value {
     pub fn value_type(&self) -> ValueType {
         match self {
             Value::Number(_) => ValueType::Number,
+            Value::Integer(_) => ValueType::Integer,
+            Value::Decimal(d) => ValueType::Decimal(d.scale()),
             Value::String(_) => ValueType::String,
             Value::Boolean(_) => ValueType::Boolean,
-            Value::Array(_) => ValueType::Array,
-            Value::Column(t) => t.value_type(),
+            Value::Categorical(c) => ValueType::Categorical(c.categories().clone()),
+            Value::Json(_) => ValueType::Json,
+            // Arrays are built from values of one type, and empty arrays
+            // could hold any
+            Value::Array(values) => ValueType::Array(Box::new(
+                values
+                    .iter()
+                    .map(Value::value_type)
+                    .reduce(|a, b| a.unify(&b).unwrap_or(a))
+                    .unwrap_or(ValueType::Null),
+            )),
+            Value::Column(column) => ValueType::Column(Box::new(column.value_type().clone())),
             Value::Null => ValueType::Null,
+            Value::Error(..) => ValueType::Error,
+        }
+    }
+
+    /// Wraps an error as a value. Errors without a row-level kind are
+    /// reported as type errors.
+    pub fn error(error: ExpressionError) -> Value {
+        Value::Error(error.kind().unwrap_or(ErrorKind::Type), Box::new(error))
+    }
+
+    pub fn is_null(&self) -> bool {
+        matches!(self, Value::Null)
+    }
+
+    /// Orders two values of the same type. Returns `None` for values that
+    /// cannot be compared, and `Some(None)` for unordered numbers (NaN).
+    pub fn compare(&self, other: &Value) -> Option<Option<Ordering>> {
+        match (self, other) {
+            (Value::Number(a), Value::Number(b)) => Some(a.partial_cmp(b)),
+            (Value::Integer(a), Value::Integer(b)) => Some(Some(a.cmp(b))),
+            (Value::Decimal(a), Value::Decimal(b)) => Some(Some(a.cmp(b))),
+            (Value::String(a), Value::String(b)) => Some(Some(a.cmp(b))),
+            (Value::Boolean(a), Value::Boolean(b)) => Some(Some(a.cmp(b))),
+            (Value::Categorical(a), Value::Categorical(b)) => a.compare(b).map(Some),
+            _ => None,
+        }
+    }
+
+    pub fn is_error(&self) -> bool {
+        matches!(self, Value::Error(..))
+    }
+}
+
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Number(n) => write!(f, "{}", n),
+            Value::Integer(n) => write!(f, "{}", n),
+            Value::Decimal(d) => write!(f, "{}", d),
+            Value::String(s) => write!(f, "{:?}", s),
+            Value::Boolean(b) => write!(f, "{}", b),
+            Value::Categorical(c) => write!(f, "{:?}", c.label()),
+            Value::Json(json) => write!(f, "{}", json),
+            Value::Array(values) => {
+                write!(f, "[")?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                write!(f, "]")
+            }
+            Value::Column(column) => write!(f, "Column[{}]", column.name()),
+            Value::Null => write!(f, "null"),
+            Value::Error(kind, _) => write!(f, "{}", kind),
+        }
+    }
+}
+
+impl ValueType {
+    /// The nullable form of this type. `Null` and already nullable types are
+    /// returned unchanged.
+    pub fn nullable(self) -> ValueType {
+        match self {
+            ValueType::Null | ValueType::Nullable(_) => self,
+            _ => ValueType::Nullable(Box::new(self)),
+        }
+    }
+
+    /// Whether this is one of the numeric types. They are never converted
+    /// into each other implicitly.
+    pub fn is_numeric(&self) -> bool {
+        matches!(
+            self.base(),
+            ValueType::Number | ValueType::Integer | ValueType::Decimal(_)
+        )
+    }
+
+    /// Whether a value of this type may be null
+    pub fn is_nullable(&self) -> bool {
+        matches!(self, ValueType::Null | ValueType::Nullable(_))
+    }
+
+    /// The type with any nullability stripped, e.g. `Number?` -> `Number`
+    pub fn base(&self) -> &ValueType {
+        match self {
+            ValueType::Nullable(inner) => inner,
+            _ => self,
+        }
+    }
+
+    /// The narrowest type that both `self` and `other` fit in, if any. This
+    /// never converts between base types, it only widens to nullable, or to
+    /// the larger scale of two decimals.
+    pub fn unify(&self, other: &ValueType) -> Option<ValueType> {
+        match (self, other) {
+            (ValueType::Null, ValueType::Null) => Some(ValueType::Null),
+            (ValueType::Null, t) | (t, ValueType::Null) => Some(t.clone().nullable()),
+            (a, b) if a.base() != b.base() => match (a.base(), b.base()) {
+                (ValueType::Decimal(x), ValueType::Decimal(y)) => {
+                    let decimal = ValueType::Decimal(*x.max(y));
+                    if a.is_nullable() || b.is_nullable() {
+                        Some(decimal.nullable())
+                    } else {
+                        Some(decimal)
+                    }
+                }
+                (ValueType::Array(x), ValueType::Array(y)) => {
+                    let array = ValueType::Array(Box::new(x.unify(y)?));
+                    if a.is_nullable() || b.is_nullable() {
+                        Some(array.nullable())
+                    } else {
+                        Some(array)
+                    }
+                }
+                _ => None,
+            },
+            (a, b) if a.base() == b.base() => {
+                let base = a.base().clone();
+                if a.is_nullable() || b.is_nullable() {
+                    Some(base.nullable())
+                } else {
+                    Some(base)
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for ValueType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ValueType::Number => write!(f, "Number"),
+            ValueType::Integer => write!(f, "Integer"),
+            ValueType::Decimal(scale) => write!(f, "Decimal({})", scale),
+            ValueType::String => write!(f, "String"),
+            ValueType::Boolean => write!(f, "Boolean"),
+            ValueType::Categorical(categories) => write!(f, "Categorical{}", categories),
+            ValueType::Json => write!(f, "Json"),
+            ValueType::Array(inner) => write!(f, "Array[{}]", inner),
+            ValueType::Column(inner) => write!(f, "Column[{}]", inner),
+            ValueType::Null => write!(f, "Null"),
+            ValueType::Error => write!(f, "Error"),
+            ValueType::Nullable(inner) => write!(f, "{}?", inner),
         }
     }
 }
@@ -3,6 +3,9 @@
 
 pub mod error;
 mod expression;
+// TODO: drop once the app opens and edits workbooks through the model
+#[allow(dead_code, unused_imports)]
+mod model;
 
 fn main() {
     tauri::Builder::default()
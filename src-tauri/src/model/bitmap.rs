@@ -0,0 +1,127 @@
+/// A packed list of bits, used for boolean columns and validity masks
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Bitmap {
+    words: Vec<u64>,
+    len: usize,
+}
+
+impl Bitmap {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// A bitmap of the first `len` bits of packed words, least significant
+    /// bit first. Missing words read as zeros.
+    pub fn from_words(mut words: Vec<u64>, len: usize) -> Self {
+        words.resize((len + 63) / 64, 0);
+        let mut bitmap = Bitmap { words, len };
+        bitmap.clear_tail();
+        bitmap
+    }
+
+    /// A bitmap of `len` bits, all set to `value`
+    pub fn filled(len: usize, value: bool) -> Self {
+        let mut bitmap = Bitmap {
+            words: vec![if value { u64::MAX } else { 0 }; (len + 63) / 64],
+            len,
+        };
+        bitmap.clear_tail();
+        bitmap
+    }
+
+    /// Zeroes the unused bits of the last word, so equality and counting only
+    /// see the bits in use
+    fn clear_tail(&mut self) {
+        if self.len % 64 != 0 {
+            if let Some(last) = self.words.last_mut() {
+                *last &= (1 << (self.len % 64)) - 1;
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn get(&self, index: usize) -> Option<bool> {
+        if index < self.len {
+            Some(self.words[index / 64] & (1 << (index % 64)) != 0)
+        } else {
+            None
+        }
+    }
+
+    /// Sets a bit. Panics if `index` is out of range.
+    pub fn set(&mut self, index: usize, value: bool) {
+        assert!(index < self.len, "bit {} out of range {}", index, self.len);
+        if value {
+            self.words[index / 64] |= 1 << (index % 64);
+        } else {
+            self.words[index / 64] &= !(1 << (index % 64));
+        }
+    }
+
+    pub fn push(&mut self, value: bool) {
+        if self.len % 64 == 0 {
+            self.words.push(0);
+        }
+        self.len += 1;
+        self.set(self.len - 1, value);
+    }
+
+    /// Inserts a bit, shifting the following bits up by one
+    pub fn insert(&mut self, index: usize, value: bool) {
+        assert!(index <= self.len, "bit {} out of range {}", index, self.len);
+        self.push(false);
+        for i in (index + 1..self.len).rev() {
+            let previous = self.get(i - 1).unwrap();
+            self.set(i, previous);
+        }
+        self.set(index, value);
+    }
+
+    /// Removes a bit, shifting the following bits down by one
+    pub fn remove(&mut self, index: usize) -> bool {
+        let value = self.get(index).expect("bit out of range");
+        for i in index..self.len - 1 {
+            let next = self.get(i + 1).unwrap();
+            self.set(i, next);
+        }
+        self.len -= 1;
+        if self.len % 64 == 0 {
+            self.words.pop();
+        }
+        self.clear_tail();
+        value
+    }
+
+    pub fn count_ones(&self) -> usize {
+        self.words
+            .iter()
+            .map(|word| word.count_ones() as usize)
+            .sum()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = bool> + '_ {
+        (0..self.len).map(|i| self.get(i).unwrap())
+    }
+
+    /// The packed words, least significant bit first
+    pub fn words(&self) -> &[u64] {
+        &self.words
+    }
+}
+
+impl FromIterator<bool> for Bitmap {
+    fn from_iter<I: IntoIterator<Item = bool>>(iter: I) -> Self {
+        let mut bitmap = Bitmap::new();
+        for bit in iter {
+            bitmap.push(bit);
+        }
+        bitmap
+    }
+}
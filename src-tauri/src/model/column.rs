@@ -0,0 +1,875 @@
+use std::{collections::BTreeMap, ops::Range, sync::Arc};
+
+use serde_json::Value as JsonValue;
+
+use super::{
+    format::StyleCache, Bitmap, Format, ModelError, ModelResult, Rule, StringTable, Validation,
+};
+use crate::expression::{
+    CategoricalVec, Category, ColumnView, Decimal, ErrorKind, ErrorReport, Expression,
+    ExpressionError, Value, ValueType,
+};
+
+/// The typed, contiguous values of a column.
+///
+/// Rows that are null or hold an error keep a placeholder here, so every
+/// variant always has one entry per row.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ColumnData {
+    Number(Vec<f64>),
+    Integer(Vec<i64>),
+    /// Units of `10^-scale`, at the column's declared scale
+    Decimal(Vec<i128>),
+    Boolean(Bitmap),
+    /// Indices into the column's string table
+    String {
+        table: StringTable,
+        indices: Vec<u32>,
+    },
+    Categorical(CategoricalVec),
+    Json(Vec<Arc<JsonValue>>),
+}
+
+/// Running totals of the values in a column, kept up to date on every
+/// write so aggregates need not read every row
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Summary {
+    count: usize,
+    units: Option<i128>,
+}
+
+impl Summary {
+    fn new(data: &ColumnData) -> Self {
+        Summary {
+            count: 0,
+            units: match data {
+                ColumnData::Integer(_) | ColumnData::Decimal(_) => Some(0),
+                _ => None,
+            },
+        }
+    }
+
+    /// The number of rows holding a value, neither null nor an error
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// The exact sum of an Integer or Decimal column, in units of the
+    /// column's scale. Float sums are not exact under subtraction, so they
+    /// are not kept, and sums that overflowed are dropped.
+    pub fn units(&self) -> Option<i128> {
+        self.units
+    }
+}
+
+impl ColumnData {
+    /// Empty storage for a type, if values of that type can be stored
+    fn new(value_type: &ValueType) -> Option<Self> {
+        Some(match value_type.base() {
+            ValueType::Number => ColumnData::Number(Vec::new()),
+            ValueType::Integer => ColumnData::Integer(Vec::new()),
+            ValueType::Decimal(_) => ColumnData::Decimal(Vec::new()),
+            ValueType::Boolean => ColumnData::Boolean(Bitmap::new()),
+            ValueType::String => ColumnData::String {
+                table: StringTable::new(),
+                indices: Vec::new(),
+            },
+            ValueType::Categorical(categories) => {
+                ColumnData::Categorical(CategoricalVec::new(categories.clone()))
+            }
+            ValueType::Json => ColumnData::Json(Vec::new()),
+            _ => return None,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            ColumnData::Number(values) => values.len(),
+            ColumnData::Integer(values) => values.len(),
+            ColumnData::Decimal(values) => values.len(),
+            ColumnData::Boolean(values) => values.len(),
+            ColumnData::String { indices, .. } => indices.len(),
+            ColumnData::Categorical(values) => values.len(),
+            ColumnData::Json(values) => values.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Inserts a value of the storage's type, or a placeholder for anything
+    /// else. Values must already be type checked.
+    fn insert(&mut self, index: usize, value: &Value) {
+        match (self, value) {
+            (ColumnData::Number(values), Value::Number(n)) => values.insert(index, *n),
+            (ColumnData::Number(values), _) => values.insert(index, 0.0),
+            (ColumnData::Integer(values), Value::Integer(n)) => values.insert(index, *n),
+            (ColumnData::Integer(values), _) => values.insert(index, 0),
+            (ColumnData::Decimal(values), Value::Decimal(d)) => values.insert(index, d.units()),
+            (ColumnData::Decimal(values), _) => values.insert(index, 0),
+            (ColumnData::Boolean(values), Value::Boolean(b)) => values.insert(index, *b),
+            (ColumnData::Boolean(values), _) => values.insert(index, false),
+            (ColumnData::String { table, indices }, Value::String(s)) => {
+                indices.insert(index, table.intern(s))
+            }
+            (ColumnData::String { table, indices }, _) => indices.insert(index, table.intern("")),
+            (ColumnData::Categorical(values), Value::Categorical(c)) => {
+                // Categories are checked against the column's type first
+                values.insert(index, c).unwrap()
+            }
+            (ColumnData::Categorical(values), _) => values.insert_null(index),
+            (ColumnData::Json(values), Value::Json(json)) => values.insert(index, json.clone()),
+            (ColumnData::Json(values), _) => values.insert(index, Arc::new(JsonValue::Null)),
+        }
+    }
+
+    /// Overwrites the entry at `index` in place, like `insert`
+    fn set(&mut self, index: usize, value: &Value) {
+        match (&mut *self, value) {
+            (ColumnData::Number(values), Value::Number(n)) => values[index] = *n,
+            (ColumnData::Number(values), _) => values[index] = 0.0,
+            (ColumnData::Integer(values), Value::Integer(n)) => values[index] = *n,
+            (ColumnData::Integer(values), _) => values[index] = 0,
+            (ColumnData::Decimal(values), Value::Decimal(d)) => values[index] = d.units(),
+            (ColumnData::Decimal(values), _) => values[index] = 0,
+            (ColumnData::Boolean(values), Value::Boolean(b)) => values.set(index, *b),
+            (ColumnData::Boolean(values), _) => values.set(index, false),
+            (ColumnData::String { table, indices }, Value::String(s)) => {
+                indices[index] = table.intern(s)
+            }
+            (ColumnData::String { table, indices }, _) => indices[index] = table.intern(""),
+            (ColumnData::Categorical(values), Value::Categorical(c)) => {
+                // Categories are checked against the column's type first
+                values.set(index, c).unwrap()
+            }
+            (ColumnData::Categorical(values), _) => values.set_null(index),
+            (ColumnData::Json(values), Value::Json(json)) => values[index] = json.clone(),
+            (ColumnData::Json(values), _) => values[index] = Arc::new(JsonValue::Null),
+        }
+        self.compact_strings();
+    }
+
+    fn remove(&mut self, index: usize) {
+        match &mut *self {
+            ColumnData::Number(values) => {
+                values.remove(index);
+            }
+            ColumnData::Integer(values) => {
+                values.remove(index);
+            }
+            ColumnData::Decimal(values) => {
+                values.remove(index);
+            }
+            ColumnData::Boolean(values) => {
+                values.remove(index);
+            }
+            // Strings no longer used stay in the table until it is compacted
+            ColumnData::String { indices, .. } => {
+                indices.remove(index);
+            }
+            ColumnData::Categorical(values) => {
+                values.remove(index);
+            }
+            ColumnData::Json(values) => {
+                values.remove(index);
+            }
+        }
+        self.compact_strings();
+    }
+
+    /// Rebuilds the string table once most of its strings are no longer
+    /// used, so editing a column does not grow it without bound
+    fn compact_strings(&mut self) {
+        if let ColumnData::String { table, indices } = self {
+            // Each row uses one string, so past twice as many strings as
+            // rows, more of them are unused than used
+            if table.len() <= 2 * indices.len() {
+                return;
+            }
+            let mut compacted = StringTable::new();
+            for index in indices.iter_mut() {
+                *index = compacted.intern(table.get(*index).unwrap());
+            }
+            *table = compacted;
+        }
+    }
+
+    /// Puts the entries in a new order, entry `i` taking entry `order[i]`
+    fn permute(&mut self, order: &[usize]) {
+        fn permuted<T: Clone>(values: &[T], order: &[usize]) -> Vec<T> {
+            order.iter().map(|index| values[*index].clone()).collect()
+        }
+        match self {
+            ColumnData::Number(values) => *values = permuted(values, order),
+            ColumnData::Integer(values) => *values = permuted(values, order),
+            ColumnData::Decimal(values) => *values = permuted(values, order),
+            ColumnData::Boolean(values) => *values = permuted_bitmap(values, order),
+            ColumnData::String { indices, .. } => *indices = permuted(indices, order),
+            ColumnData::Categorical(values) => values.permute(order),
+            ColumnData::Json(values) => *values = permuted(values, order),
+        }
+    }
+
+    /// The stored value at `index`, ignoring validity
+    fn get(&self, index: usize, value_type: &ValueType) -> Value {
+        match self {
+            ColumnData::Number(values) => Value::Number(values[index]),
+            ColumnData::Integer(values) => Value::Integer(values[index]),
+            ColumnData::Decimal(values) => match value_type.base() {
+                ValueType::Decimal(scale) => Value::Decimal(Decimal::new(values[index], *scale)),
+                _ => unreachable!(),
+            },
+            ColumnData::Boolean(values) => Value::Boolean(values.get(index).unwrap()),
+            ColumnData::String { table, indices } => {
+                Value::String(table.get(indices[index]).unwrap().to_string())
+            }
+            ColumnData::Categorical(values) => Value::Categorical(values.get(index).unwrap()),
+            ColumnData::Json(values) => Value::Json(values[index].clone()),
+        }
+    }
+}
+
+/// A named, typed column of values.
+///
+/// Values are stored contiguously by type, with a validity bitmap marking
+/// which rows hold a value. Rows without one are null, unless they are listed
+/// in the column's errors.
+///
+/// A computed column has a formula instead of data entered by hand. Its
+/// values are evaluated by the workbook on demand and cached here, and
+/// rows that have not been evaluated yet read as null.
+///
+/// A static column can also have validation rules, which the workbook
+/// checks values entered into it against.
+///
+/// Any column can have conditional formats, and keeps the styles worked
+/// out from them until the workbook marks them out of date.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Column {
+    name: String,
+    value_type: ValueType,
+    data: ColumnData,
+    validity: Bitmap,
+    errors: BTreeMap<usize, (ErrorKind, Box<ExpressionError>)>,
+    formula: Option<Expression>,
+    validation: Option<Validation>,
+    formats: Vec<Format>,
+    styles: StyleCache,
+    /// Set bits mark computed rows with an up to date value
+    cached: Bitmap,
+    summary: Summary,
+}
+
+impl Column {
+    pub fn new(name: &str, value_type: ValueType) -> ModelResult<Self> {
+        let data = ColumnData::new(&value_type)
+            .ok_or_else(|| ModelError::unsupported_type(name, &value_type))?;
+        Ok(Column {
+            name: name.to_string(),
+            value_type,
+            summary: Summary::new(&data),
+            data,
+            validity: Bitmap::new(),
+            errors: BTreeMap::new(),
+            formula: None,
+            validation: None,
+            formats: Vec::new(),
+            styles: StyleCache::default(),
+            cached: Bitmap::new(),
+        })
+    }
+
+    pub fn from_values(
+        name: &str,
+        value_type: ValueType,
+        values: impl IntoIterator<Item = Value>,
+    ) -> ModelResult<Self> {
+        let mut column = Column::new(name, value_type)?;
+        for value in values {
+            column.push(value)?;
+        }
+        Ok(column)
+    }
+
+    /// A computed column of `rows` rows, none of them evaluated yet.
+    /// `value_type` is the type the formula checked as.
+    pub fn computed(
+        name: &str,
+        value_type: ValueType,
+        formula: Expression,
+        rows: usize,
+    ) -> ModelResult<Self> {
+        let mut column = Column::new(name, value_type)?;
+        column.formula = Some(formula);
+        for row in 0..rows {
+            column.insert_slot(row, Value::Null, false);
+        }
+        Ok(column)
+    }
+
+    /// A static copy of this column, keeping the values it holds now
+    pub fn to_static(&self) -> Column {
+        Column {
+            formula: None,
+            cached: Bitmap::filled(self.len(), false),
+            ..self.clone()
+        }
+    }
+
+    /// A static copy of the rows in `rows`, as a column of its own
+    pub fn slice(&self, rows: Range<usize>) -> Column {
+        // The type is already known to be storable
+        let mut slice = Column::new(&self.name, self.value_type.clone()).unwrap();
+        for row in rows.start..rows.end.min(self.len()) {
+            slice.insert_slot(slice.len(), self.get(row).unwrap(), false);
+        }
+        slice
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn rename(&mut self, name: &str) {
+        self.name = name.to_string();
+    }
+
+    pub fn value_type(&self) -> &ValueType {
+        &self.value_type
+    }
+
+    pub fn data(&self) -> &ColumnData {
+        &self.data
+    }
+
+    /// Set bits mark rows holding a value
+    pub fn validity(&self) -> &Bitmap {
+        &self.validity
+    }
+
+    pub fn formula(&self) -> Option<&Expression> {
+        self.formula.as_ref()
+    }
+
+    pub fn is_computed(&self) -> bool {
+        self.formula.is_some()
+    }
+
+    /// Every expression the column keeps
+    pub(super) fn expressions(&self) -> impl Iterator<Item = &Expression> {
+        let rules = self
+            .validation
+            .iter()
+            .flat_map(|validation| &validation.rules);
+        self.formula
+            .iter()
+            .chain(rules.filter_map(|rule| match rule {
+                Rule::Formula(formula) => Some(formula),
+                _ => None,
+            }))
+            .chain(self.formats.iter().map(Format::expression))
+    }
+
+    pub(super) fn expressions_mut(&mut self) -> impl Iterator<Item = &mut Expression> {
+        let rules = self
+            .validation
+            .iter_mut()
+            .flat_map(|validation| &mut validation.rules);
+        self.formula
+            .iter_mut()
+            .chain(rules.filter_map(|rule| match rule {
+                Rule::Formula(formula) => Some(formula),
+                _ => None,
+            }))
+            .chain(self.formats.iter_mut().map(Format::expression_mut))
+    }
+
+    pub fn validation(&self) -> Option<&Validation> {
+        self.validation.as_ref()
+    }
+
+    /// Sets the validation rules, returning the previous ones
+    pub(super) fn set_validation(&mut self, validation: Option<Validation>) -> Option<Validation> {
+        std::mem::replace(&mut self.validation, validation)
+    }
+
+    pub fn formats(&self) -> &[Format] {
+        &self.formats
+    }
+
+    /// Sets the conditional formats, returning the previous ones
+    pub(super) fn set_formats(&mut self, formats: Vec<Format>) -> Vec<Format> {
+        self.styles.clear();
+        std::mem::replace(&mut self.formats, formats)
+    }
+
+    pub(super) fn styles(&self) -> &StyleCache {
+        &self.styles
+    }
+
+    pub(super) fn styles_mut(&mut self) -> &mut StyleCache {
+        &mut self.styles
+    }
+
+    /// Whether the value at `row` is up to date. Only computed rows can be
+    /// out of date.
+    pub fn is_cached(&self, row: usize) -> bool {
+        !self.is_computed() || self.cached.get(row).unwrap_or(false)
+    }
+
+    /// Marks every computed value as out of date
+    pub fn invalidate(&mut self) {
+        if self.is_computed() {
+            self.cached = Bitmap::filled(self.len(), false);
+        }
+    }
+
+    /// Marks the computed values in `rows` as out of date
+    pub fn invalidate_rows(&mut self, rows: Range<usize>) {
+        if self.is_computed() {
+            for row in rows.start..rows.end.min(self.len()) {
+                self.cached.set(row, false);
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.validity.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.validity.is_empty()
+    }
+
+    pub fn null_count(&self) -> usize {
+        self.len() - self.summary.count - self.errors.len()
+    }
+
+    pub fn summary(&self) -> &Summary {
+        &self.summary
+    }
+
+    /// The rows holding errors, and the errors in them
+    pub fn errors(&self) -> &BTreeMap<usize, (ErrorKind, Box<ExpressionError>)> {
+        &self.errors
+    }
+
+    /// The error in the earliest row holding one
+    pub fn first_error(&self) -> Option<Value> {
+        let row = self.errors.keys().next()?;
+        self.get(*row)
+    }
+
+    /// Checks that `value` can be stored in this column. Errors are accepted
+    /// in any column, since computed values may fail on any row. Computed
+    /// columns only accept nulls, which stand for rows not evaluated yet.
+    pub fn check(&self, row: usize, value: &Value) -> ModelResult<()> {
+        match value {
+            Value::Null if self.is_computed() => Ok(()),
+            _ if self.is_computed() => Err(ModelError::new(
+                "Column is computed, change its formula instead",
+                Some(&self.name),
+                Some(row),
+            )),
+            Value::Null if self.value_type.is_nullable() => Ok(()),
+            Value::Null => Err(ModelError::not_nullable(&self.name, row)),
+            Value::Error(..) => Ok(()),
+            value => {
+                let found = value.value_type();
+                if self.value_type.base() == &found {
+                    Ok(())
+                } else {
+                    Err(ModelError::type_mismatch(
+                        &self.name,
+                        &self.value_type,
+                        &found,
+                        row,
+                    ))
+                }
+            }
+        }
+    }
+
+    pub fn get(&self, row: usize) -> Option<Value> {
+        if row >= self.len() {
+            return None;
+        }
+        if let Some((kind, error)) = self.errors.get(&row) {
+            return Some(Value::Error(*kind, error.clone()));
+        }
+        Some(if self.validity.get(row).unwrap() {
+            self.data.get(row, &self.value_type)
+        } else {
+            Value::Null
+        })
+    }
+
+    /// Adds the value at `row` to the summary, or takes it away
+    fn tally(&mut self, row: usize, add: bool) {
+        if !self.validity.get(row).unwrap_or(false) {
+            return;
+        }
+        let units = match &self.data {
+            ColumnData::Integer(values) => values[row] as i128,
+            ColumnData::Decimal(values) => values[row],
+            _ => 0,
+        };
+        if add {
+            self.summary.count += 1;
+            self.summary.units = self.summary.units.and_then(|sum| sum.checked_add(units));
+        } else {
+            self.summary.count -= 1;
+            self.summary.units = self.summary.units.and_then(|sum| sum.checked_sub(units));
+        }
+    }
+
+    /// Writes a value that has already been checked
+    fn write(&mut self, row: usize, value: Value) {
+        self.tally(row, false);
+        self.data.set(row, &value);
+        self.validity
+            .set(row, !value.is_null() && !value.is_error());
+        self.errors.remove(&row);
+        if let Value::Error(kind, error) = value {
+            self.errors.insert(row, (kind, error));
+        }
+        self.tally(row, true);
+    }
+
+    /// Replaces the value at `row`, returning the old one
+    pub fn set(&mut self, row: usize, value: Value) -> ModelResult<Value> {
+        let previous = self
+            .get(row)
+            .ok_or_else(|| ModelError::row_out_of_range(&self.name, row, self.len()))?;
+        self.check(row, &value)?;
+        self.write(row, value);
+        Ok(previous)
+    }
+
+    /// Caches an evaluated value of a computed column. Values that no longer
+    /// match the column's type, because an input changed type since the
+    /// formula was checked, are stored as type errors.
+    pub fn store(&mut self, row: usize, value: Value) {
+        let value = match &value {
+            Value::Null if !self.value_type.is_nullable() => Value::error(ExpressionError::new(
+                "Formula returned null in a column that is not nullable",
+                None,
+            )),
+            Value::Null | Value::Error(..) => value,
+            other if &other.value_type() != self.value_type.base() => {
+                Value::error(ExpressionError::new(
+                    &format!(
+                        "Type {} not valid, require {}",
+                        other.value_type(),
+                        self.value_type
+                    ),
+                    None,
+                ))
+            }
+            _ => value,
+        };
+        self.write(row, value);
+        self.cached.set(row, true);
+    }
+
+    pub fn push(&mut self, value: Value) -> ModelResult<()> {
+        self.insert(self.len(), value)
+    }
+
+    fn insert_slot(&mut self, row: usize, value: Value, cached: bool) {
+        self.data.insert(row, &value);
+        self.validity
+            .insert(row, !value.is_null() && !value.is_error());
+        self.cached.insert(row, cached);
+        let moved = self.errors.split_off(&row);
+        self.errors
+            .extend(moved.into_iter().map(|(r, error)| (r + 1, error)));
+        if let Value::Error(kind, error) = value {
+            self.errors.insert(row, (kind, error));
+        }
+        self.tally(row, true);
+    }
+
+    /// Inserts a value at `row`, moving the rows after it down
+    pub fn insert(&mut self, row: usize, value: Value) -> ModelResult<()> {
+        if row > self.len() {
+            return Err(ModelError::row_out_of_range(&self.name, row, self.len()));
+        }
+        self.check(row, &value)?;
+        self.insert_slot(row, value, false);
+        Ok(())
+    }
+
+    /// Removes the value at `row`, moving the rows after it up
+    pub fn remove(&mut self, row: usize) -> ModelResult<Value> {
+        let value = self
+            .get(row)
+            .ok_or_else(|| ModelError::row_out_of_range(&self.name, row, self.len()))?;
+        self.tally(row, false);
+        self.data.remove(row);
+        self.validity.remove(row);
+        self.cached.remove(row);
+        let moved = self.errors.split_off(&row);
+        self.errors.extend(
+            moved
+                .into_iter()
+                .filter(|(r, _)| *r != row)
+                .map(|(r, error)| (r - 1, error)),
+        );
+        Ok(value)
+    }
+
+    /// Moves the `count` rows starting at `from` so they start at `to`,
+    /// counted after the move
+    pub fn move_rows(&mut self, from: usize, count: usize, to: usize) -> ModelResult<()> {
+        if from + count > self.len() || to + count > self.len() {
+            return Err(ModelError::row_out_of_range(
+                &self.name,
+                from.max(to) + count,
+                self.len(),
+            ));
+        }
+        let mut moved = Vec::with_capacity(count);
+        for _ in 0..count {
+            let cached = self.cached.get(from).unwrap();
+            moved.push((self.remove(from)?, cached));
+        }
+        for (offset, (value, cached)) in moved.into_iter().enumerate() {
+            self.insert_slot(to + offset, value, cached);
+        }
+        Ok(())
+    }
+
+    /// Puts the rows in a new order, row `i` taking the value of row
+    /// `order[i]`. `order` must list every row exactly once.
+    pub fn permute(&mut self, order: &[usize]) -> ModelResult<()> {
+        let mut seen = Bitmap::filled(self.len(), false);
+        for row in order {
+            if seen.get(*row) != Some(false) {
+                return Err(ModelError::new(
+                    "Row order is not a permutation of the rows",
+                    Some(&self.name),
+                    Some(*row),
+                ));
+            }
+            seen.set(*row, true);
+        }
+        if order.len() != self.len() {
+            return Err(ModelError::length_mismatch(
+                &self.name,
+                self.len(),
+                order.len(),
+            ));
+        }
+        // Only the rows move, so everything else about the column is kept
+        self.data.permute(order);
+        self.validity = permuted_bitmap(&self.validity, order);
+        self.cached = permuted_bitmap(&self.cached, order);
+        let mut errors = BTreeMap::new();
+        for (row, from) in order.iter().enumerate() {
+            if let Some(error) = self.errors.remove(from) {
+                errors.insert(row, error);
+            }
+        }
+        self.errors = errors;
+        self.styles.clear();
+        Ok(())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Value> + '_ {
+        (0..self.len()).map(|row| self.get(row).unwrap())
+    }
+
+    /// A categorical value from this column's categories, by label
+    pub fn category(&self, label: &str) -> Option<Category> {
+        match self.value_type.base() {
+            ValueType::Categorical(categories) => Category::new(label, categories),
+            _ => None,
+        }
+    }
+
+    /// Counts the errors in this column by kind
+    pub fn error_report(&self) -> ErrorReport {
+        let mut report = ErrorReport::default();
+        for (row, (kind, error)) in &self.errors {
+            report.record(*row, &Value::Error(*kind, error.clone()));
+        }
+        report
+    }
+}
+
+/// A bitmap with bit `i` taken from bit `order[i]` of `bitmap`
+fn permuted_bitmap(bitmap: &Bitmap, order: &[usize]) -> Bitmap {
+    let mut permuted = Bitmap::new();
+    for index in order {
+        permuted.push(bitmap.get(*index).unwrap());
+    }
+    permuted
+}
+
+impl ColumnView for Column {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn value_type(&self) -> &ValueType {
+        &self.value_type
+    }
+
+    fn len(&self) -> usize {
+        Column::len(self)
+    }
+
+    fn get(&self, row: usize) -> Option<Value> {
+        Column::get(self, row)
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = Value> + '_> {
+        Box::new(Column::iter(self))
+    }
+
+    fn slice(&self, rows: Range<usize>) -> Arc<dyn ColumnView> {
+        Arc::new(Column::slice(self, rows))
+    }
+
+    fn first_error(&self) -> Option<Value> {
+        Column::first_error(self)
+    }
+
+    fn count(&self) -> usize {
+        self.summary.count()
+    }
+
+    fn units(&self) -> Option<i128> {
+        self.summary.units()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::expression::Categories;
+
+    #[test]
+    fn typed_storage() {
+        let mut column = Column::from_values(
+            "price",
+            ValueType::Number.nullable(),
+            [Value::Number(1.5), Value::Null, Value::Number(3.0)],
+        )
+        .unwrap();
+        assert_eq!(column.data(), &ColumnData::Number(vec![1.5, 0.0, 3.0]));
+        assert_eq!(
+            column.validity().iter().collect::<Vec<_>>(),
+            [true, false, true]
+        );
+        assert_eq!(column.null_count(), 1);
+
+        assert!(column.set(0, Value::String("a".to_string())).is_err());
+        assert!(column.set(0, Value::Integer(1)).is_err());
+        assert_eq!(column.set(1, Value::Number(2.0)), Ok(Value::Null));
+        assert_eq!(column.remove(0), Ok(Value::Number(1.5)));
+        assert_eq!(
+            column.iter().collect::<Vec<_>>(),
+            [Value::Number(2.0), Value::Number(3.0)]
+        );
+        assert!(column.get(2).is_none());
+        assert!(column.set(2, Value::Number(0.0)).is_err());
+
+        let mut strict = Column::new("qty", ValueType::Integer).unwrap();
+        assert!(strict.push(Value::Null).is_err());
+        assert!(Column::new("list", ValueType::Array(Box::new(ValueType::Integer))).is_err());
+    }
+
+    #[test]
+    fn strings_and_categories() {
+        let mut names = Column::from_values(
+            "name",
+            ValueType::String,
+            ["a", "b", "a"].map(|s| Value::String(s.to_string())),
+        )
+        .unwrap();
+        match names.data() {
+            ColumnData::String { table, indices } => {
+                assert_eq!(table.len(), 2);
+                assert_eq!(indices, &[0, 1, 0]);
+            }
+            other => panic!("unexpected storage {:?}", other),
+        }
+        names.set(2, Value::String("c".to_string())).unwrap();
+        match names.data() {
+            ColumnData::String { indices, .. } => assert_eq!(indices, &[0, 1, 2]),
+            other => panic!("unexpected storage {:?}", other),
+        }
+        // Overwritten strings are dropped once they outnumber the rows
+        for n in 0..100 {
+            names.set(2, Value::String(n.to_string())).unwrap();
+        }
+        match names.data() {
+            ColumnData::String { table, .. } => assert!(table.len() <= 6),
+            other => panic!("unexpected storage {:?}", other),
+        }
+        assert_eq!(names.get(0), Some(Value::String("a".to_string())));
+        assert_eq!(names.get(2), Some(Value::String("99".to_string())));
+
+        let regions = Categories::new(vec!["EU".to_string(), "US".to_string()], false).unwrap();
+        let mut column = Column::new("region", ValueType::Categorical(regions.clone())).unwrap();
+        column
+            .push(Value::Categorical(column.category("US").unwrap()))
+            .unwrap();
+        let other = Categories::new(vec!["US".to_string()], false).unwrap();
+        let foreign = Category::new("US", &other).unwrap();
+        assert!(column.push(Value::Categorical(foreign)).is_err());
+        assert_eq!(column.get(0).unwrap().to_string(), "\"US\"");
+        column
+            .set(0, Value::Categorical(column.category("EU").unwrap()))
+            .unwrap();
+        assert_eq!(column.get(0).unwrap().to_string(), "\"EU\"");
+    }
+
+    #[test]
+    fn errors() {
+        let mut column = Column::new("ratio", ValueType::Number).unwrap();
+        column.push(Value::Number(1.0)).unwrap();
+        column
+            .push(Value::error(ExpressionError::div_by_zero(
+                Default::default(),
+            )))
+            .unwrap();
+        column.insert(0, Value::Number(0.0)).unwrap();
+        assert!(column.get(2).unwrap().is_error());
+        let report = column.error_report();
+        assert_eq!(report.total, 1);
+        assert_eq!(report.first[&ErrorKind::DivByZero].0, 2);
+        column.remove(1).unwrap();
+        assert!(column.get(1).unwrap().is_error());
+        column.set(1, Value::Number(4.0)).unwrap();
+        assert!(column.error_report().is_empty());
+    }
+
+    #[test]
+    fn summary() {
+        let mut column = Column::from_values(
+            "amount",
+            ValueType::Integer.nullable(),
+            [Value::Integer(10), Value::Null, Value::Integer(30)],
+        )
+        .unwrap();
+        assert_eq!(
+            (column.summary().count(), column.summary().units()),
+            (2, Some(40))
+        );
+        column.set(1, Value::Integer(5)).unwrap();
+        column.set(0, Value::Null).unwrap();
+        column.insert(0, Value::Integer(i64::MAX)).unwrap();
+        column.remove(3).unwrap();
+        column.move_rows(0, 1, 1).unwrap();
+        assert_eq!(column.summary().count(), 2);
+        assert_eq!(column.summary().units(), Some(i64::MAX as i128 + 5));
+        assert_eq!(column.null_count(), 1);
+
+        let column = Column::from_values("ratio", ValueType::Number, [Value::Number(0.5)]);
+        assert_eq!(column.unwrap().summary().units(), None);
+    }
+}
@@ -0,0 +1,105 @@
+use crate::expression::{ExpressionError, ValueType};
+
+/// An error raised while changing the data model, naming the column and row
+/// it happened at where there is one
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModelError {
+    message: String,
+    column: Option<String>,
+    row: Option<usize>,
+}
+
+pub type ModelResult<T> = Result<T, ModelError>;
+
+impl std::error::Error for ModelError {}
+
+impl std::fmt::Display for ModelError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (&self.column, self.row) {
+            (Some(column), Some(row)) => write!(f, " [{:?}:{}] {}", column, row, self.message),
+            (Some(column), None) => write!(f, " [{:?}] {}", column, self.message),
+            (None, Some(row)) => write!(f, " [{}] {}", row, self.message),
+            (None, None) => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl ModelError {
+    pub fn new(message: &str, column: Option<&str>, row: Option<usize>) -> Self {
+        ModelError {
+            message: message.to_string(),
+            column: column.map(str::to_string),
+            row,
+        }
+    }
+
+    pub fn type_mismatch(
+        column: &str,
+        expected: &ValueType,
+        found: &ValueType,
+        row: usize,
+    ) -> Self {
+        ModelError::new(
+            &format!("Expected {}, found {}", expected, found),
+            Some(column),
+            Some(row),
+        )
+    }
+
+    pub fn not_nullable(column: &str, row: usize) -> Self {
+        ModelError::new(
+            "Column is not nullable, expected a value",
+            Some(column),
+            Some(row),
+        )
+    }
+
+    pub fn row_out_of_range(column: &str, row: usize, len: usize) -> Self {
+        ModelError::new(
+            &format!("Row out of range, column has {} rows", len),
+            Some(column),
+            Some(row),
+        )
+    }
+
+    pub fn unknown_column(column: &str) -> Self {
+        ModelError::new("No column with this name", Some(column), None)
+    }
+
+    pub fn duplicate_column(column: &str) -> Self {
+        ModelError::new("A column with this name already exists", Some(column), None)
+    }
+
+    pub fn length_mismatch(column: &str, expected: usize, found: usize) -> Self {
+        ModelError::new(
+            &format!("Expected {} rows, found {}", expected, found),
+            Some(column),
+            None,
+        )
+    }
+
+    /// An expression that failed to parse or type check
+    pub fn expression(error: &ExpressionError, column: Option<&str>) -> Self {
+        ModelError::new(error.to_string().trim_start(), column, None)
+    }
+
+    pub fn unsupported_type(column: &str, value_type: &ValueType) -> Self {
+        ModelError::new(
+            &format!("{} cannot be stored in a column", value_type),
+            Some(column),
+            None,
+        )
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn column(&self) -> Option<&str> {
+        self.column.as_deref()
+    }
+
+    pub fn row(&self) -> Option<usize> {
+        self.row
+    }
+}
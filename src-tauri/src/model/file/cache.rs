@@ -0,0 +1,127 @@
+use std::sync::Arc;
+
+use crate::model::Column;
+
+/// Decoded columns and blocks of strings, dropping the least recently used
+/// once they add up to more than `capacity` bytes.
+///
+/// Sizes are rough estimates of what each takes decoded. The entry used
+/// last is always kept, however large it is.
+#[derive(Debug)]
+pub(super) struct ColumnCache {
+    capacity: u64,
+    used: u64,
+    /// Least recently used first
+    entries: Vec<CacheEntry>,
+}
+
+/// What is kept: a column, by sheet and column index, or a block of the
+/// string table, by its number
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) enum CacheKey {
+    Column(usize, usize),
+    Strings(u32),
+}
+
+#[derive(Debug, Clone)]
+pub(super) enum Cached {
+    Column(Arc<Column>),
+    Strings(Arc<Vec<String>>),
+}
+
+#[derive(Debug)]
+struct CacheEntry {
+    key: CacheKey,
+    value: Cached,
+    size: u64,
+}
+
+impl ColumnCache {
+    pub(super) fn new(capacity: u64) -> Self {
+        ColumnCache {
+            capacity,
+            used: 0,
+            entries: Vec::new(),
+        }
+    }
+
+    pub(super) fn get(&mut self, key: CacheKey) -> Option<Cached> {
+        let index = self.entries.iter().position(|entry| entry.key == key)?;
+        let entry = self.entries.remove(index);
+        let value = entry.value.clone();
+        self.entries.push(entry);
+        Some(value)
+    }
+
+    /// Like `get`, without counting as a use
+    pub(super) fn peek(&self, key: CacheKey) -> Option<&Cached> {
+        self.entries
+            .iter()
+            .find(|entry| entry.key == key)
+            .map(|entry| &entry.value)
+    }
+
+    pub(super) fn insert(&mut self, key: CacheKey, value: Cached, size: u64) {
+        if let Some(index) = self.entries.iter().position(|entry| entry.key == key) {
+            self.used -= self.entries.remove(index).size;
+        }
+        self.entries.push(CacheEntry { key, value, size });
+        self.used += size;
+        self.evict();
+    }
+
+    pub(super) fn set_capacity(&mut self, capacity: u64) {
+        self.capacity = capacity;
+        self.evict();
+    }
+
+    pub(super) fn used(&self) -> u64 {
+        self.used
+    }
+
+    fn evict(&mut self) {
+        while self.used > self.capacity && self.entries.len() > 1 {
+            self.used -= self.entries.remove(0).size;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::expression::{Value, ValueType};
+
+    #[test]
+    fn eviction() {
+        let column = |name: &str| {
+            Cached::Column(Arc::new(
+                Column::from_values(name, ValueType::Integer, [Value::Integer(1)]).unwrap(),
+            ))
+        };
+        let name = |cached: Option<Cached>| match cached {
+            Some(Cached::Column(column)) => column.name().to_string(),
+            found => panic!("{:?}", found),
+        };
+        let mut cache = ColumnCache::new(100);
+        cache.insert(CacheKey::Column(0, 0), column("a"), 40);
+        cache.insert(CacheKey::Column(0, 1), column("b"), 40);
+        assert_eq!(name(cache.get(CacheKey::Column(0, 0))), "a");
+        // b is now the least recently used
+        cache.insert(CacheKey::Column(0, 2), column("c"), 40);
+        assert!(cache.peek(CacheKey::Column(0, 1)).is_none());
+        assert_eq!(cache.used(), 80);
+
+        // Strings count against the same capacity
+        let strings = Cached::Strings(Arc::new(vec!["x".to_string()]));
+        cache.insert(CacheKey::Strings(0), strings, 30);
+        assert!(cache.peek(CacheKey::Column(0, 0)).is_none());
+        assert!(cache.peek(CacheKey::Strings(0)).is_some());
+        assert_eq!(cache.used(), 70);
+
+        cache.insert(CacheKey::Column(1, 0), column("huge"), 500);
+        assert_eq!(cache.used(), 500);
+        assert_eq!(name(cache.get(CacheKey::Column(1, 0))), "huge");
+        cache.set_capacity(0);
+        assert_eq!(cache.used(), 500);
+    }
+}
@@ -0,0 +1,88 @@
+/// CRC-32 as used by zip and PNG, with the reflected polynomial `0xEDB88320`
+const TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                0xEDB88320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// A running CRC-32 of the bytes written so far
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct Crc32 {
+    state: u32,
+}
+
+impl Crc32 {
+    pub(super) fn new() -> Self {
+        Crc32 { state: !0 }
+    }
+
+    /// A checksum started from a zero state, which can only be used to
+    /// `chain` another one
+    pub(super) fn zeroed() -> Self {
+        Crc32 { state: 0 }
+    }
+
+    pub(super) fn update(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.state = TABLE[((self.state ^ *byte as u32) & 0xFF) as usize] ^ (self.state >> 8);
+        }
+    }
+
+    /// The checksum of this one's bytes followed by the `length` bytes a
+    /// zeroed checksum, `rest`, was updated with.
+    ///
+    /// The CRC of some bytes is linear in the starting state and in the
+    /// bytes, so it splits into the CRC of as many zeros from this state,
+    /// and of the bytes from zero. This lets a file's checksum be finished
+    /// after its header is rewritten, without reading back what follows.
+    pub(super) fn chain(&self, rest: &Crc32, length: u64) -> Crc32 {
+        let mut chained = *self;
+        let zeros = [0; 4096];
+        let mut left = length;
+        while left > 0 {
+            let count = left.min(zeros.len() as u64) as usize;
+            chained.update(&zeros[..count]);
+            left -= count as u64;
+        }
+        chained.state ^= rest.state;
+        chained
+    }
+
+    pub(super) fn finish(&self) -> u32 {
+        !self.state
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn crc32() {
+        let checksum = |bytes: &[u8]| {
+            let mut crc = Crc32::new();
+            crc.update(bytes);
+            crc
+        };
+        assert_eq!(checksum(b"").finish(), 0);
+        assert_eq!(checksum(b"123456789").finish(), 0xCBF43926);
+
+        let mut rest = Crc32::zeroed();
+        rest.update(b"56789");
+        assert_eq!(checksum(b"1234").chain(&rest, 5), checksum(b"123456789"));
+    }
+}
@@ -0,0 +1,163 @@
+//! A small LZ77 compressor for column data.
+//!
+//! Compressed data is a run of sequences, each the count of literal bytes
+//! that follow, those bytes, then the length and distance back of a match
+//! to copy. The last sequence has no match. Numbers are LEB128 varints.
+
+/// Matches shorter than this cost more than the literals they replace
+const MIN_MATCH: usize = 4;
+
+/// The furthest back a match is looked for
+const WINDOW: usize = 64 * 1024;
+
+const HASH_BITS: u32 = 14;
+
+/// Compresses `bytes`, starting with their length
+pub(super) fn compress(bytes: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(bytes.len() / 2);
+    varint(&mut output, bytes.len() as u64);
+    // The last position each hash of four bytes was seen at, plus one
+    let mut table = vec![0usize; 1 << HASH_BITS];
+    let (mut position, mut pending) = (0, 0);
+    while position + MIN_MATCH <= bytes.len() {
+        let hash = hash(&bytes[position..position + MIN_MATCH]);
+        let candidate = table[hash].checked_sub(1);
+        table[hash] = position + 1;
+        let length = candidate
+            .filter(|candidate| position - candidate <= WINDOW)
+            .map_or(0, |candidate| {
+                bytes[candidate..]
+                    .iter()
+                    .zip(&bytes[position..])
+                    .take_while(|(a, b)| a == b)
+                    .count()
+            });
+        if length < MIN_MATCH {
+            position += 1;
+            continue;
+        }
+        varint(&mut output, (position - pending) as u64);
+        output.extend(&bytes[pending..position]);
+        varint(&mut output, length as u64);
+        varint(&mut output, (position - candidate.unwrap()) as u64);
+        position += length;
+        pending = position;
+    }
+    varint(&mut output, (bytes.len() - pending) as u64);
+    output.extend(&bytes[pending..]);
+    output
+}
+
+/// Reads back what `compress` wrote, or says what is wrong with it
+pub(super) fn decompress(bytes: &[u8]) -> Result<Vec<u8>, &'static str> {
+    let mut input = Input { bytes, position: 0 };
+    let expected = input.varint()? as usize;
+    let mut output = Vec::with_capacity(expected.min(bytes.len() * 64));
+    loop {
+        let literals = input.varint()? as usize;
+        output.extend(input.take(literals)?);
+        if input.position == bytes.len() {
+            break;
+        }
+        let (length, distance) = (input.varint()? as usize, input.varint()? as usize);
+        if distance == 0 || distance > output.len() {
+            return Err("Compressed data refers to bytes before it starts");
+        }
+        if output.len() + length > expected {
+            break;
+        }
+        // Matches may overlap the bytes they copy, so a byte at a time
+        let start = output.len() - distance;
+        for index in start..start + length {
+            output.push(output[index]);
+        }
+    }
+    if output.len() != expected {
+        return Err("Compressed data is not the length it says");
+    }
+    Ok(output)
+}
+
+fn hash(bytes: &[u8]) -> usize {
+    let value = u32::from_le_bytes(bytes.try_into().unwrap());
+    (value.wrapping_mul(0x9E3779B1) >> (32 - HASH_BITS)) as usize
+}
+
+fn varint(output: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        output.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    output.push(value as u8);
+}
+
+struct Input<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Input<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8], &'static str> {
+        let bytes = self
+            .bytes
+            .get(self.position..self.position.saturating_add(count))
+            .ok_or("Compressed data ends early")?;
+        self.position += count;
+        Ok(bytes)
+    }
+
+    fn varint(&mut self) -> Result<u64, &'static str> {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = self.take(1)?[0];
+            value |= ((byte & 0x7F) as u64) << shift;
+            if byte < 0x80 {
+                return Ok(value);
+            }
+        }
+        Err("Compressed data holds a number that is too long")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let repetitive = b"open paid open open paid ".repeat(200);
+        let compressed = compress(&repetitive);
+        assert!(compressed.len() < repetitive.len() / 10);
+        assert_eq!(decompress(&compressed).unwrap(), repetitive);
+
+        // Bytes with nothing to match still come back, a little larger
+        let mut state = 1u32;
+        let noise = (0..5000)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                (state >> 16) as u8
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(decompress(&compress(&noise)).unwrap(), noise);
+        assert!(decompress(&compress(&[])).unwrap().is_empty());
+        assert_eq!(decompress(&compress(b"abc")).unwrap(), b"abc");
+    }
+
+    #[test]
+    fn damaged() {
+        let compressed = compress(&b"abcdabcdabcdabcd".repeat(10));
+        assert_eq!(
+            decompress(&compressed[..compressed.len() - 1]),
+            Err("Compressed data ends early")
+        );
+        // A match before the first byte
+        assert_eq!(
+            decompress(&[8, 0, 4, 1]),
+            Err("Compressed data refers to bytes before it starts")
+        );
+        assert_eq!(
+            decompress(&[9, 2, b'a', b'b']),
+            Err("Compressed data is not the length it says")
+        );
+    }
+}
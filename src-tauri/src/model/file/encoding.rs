@@ -0,0 +1,252 @@
+use std::collections::HashSet;
+
+use crate::expression::ValueType;
+
+/// How the values of a column are laid out in its data.
+///
+/// Every stored value is handled as a raw integer: the bits of a Number,
+/// an Integer, the units of a Decimal, 0 or 1 for a Boolean, the code of a
+/// Categorical and the string table index of a String or Json document.
+/// Each raw value is `width` bytes in the file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Encoding {
+    /// One raw value per row, or a bitmap for Booleans
+    Plain,
+    /// Runs of rows holding the same raw value
+    RunLength,
+    /// The distinct raw values, then a bit-packed index into them per row
+    Dictionary,
+    /// The first raw value, then the bit-packed difference of each row from
+    /// the row before, less the smallest difference
+    Delta,
+    /// The bit-packed difference of each row from the smallest raw value
+    BitPacked,
+}
+
+impl Encoding {
+    pub(super) fn tag(self) -> u8 {
+        match self {
+            Encoding::Plain => 0,
+            Encoding::RunLength => 1,
+            Encoding::Dictionary => 2,
+            Encoding::Delta => 3,
+            Encoding::BitPacked => 4,
+        }
+    }
+
+    pub(super) fn from_tag(tag: u8) -> Option<Self> {
+        Some(match tag {
+            0 => Encoding::Plain,
+            1 => Encoding::RunLength,
+            2 => Encoding::Dictionary,
+            3 => Encoding::Delta,
+            4 => Encoding::BitPacked,
+            _ => return None,
+        })
+    }
+}
+
+/// The bytes each raw value of a stored column of type `base` takes
+pub(super) fn width(base: &ValueType) -> usize {
+    match base {
+        ValueType::Number | ValueType::Integer => 8,
+        ValueType::Decimal(_) => 16,
+        ValueType::Boolean => 1,
+        _ => 4,
+    }
+}
+
+/// What the encodings of a column are chosen from
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Statistics {
+    rows: usize,
+    runs: usize,
+    distinct: usize,
+    /// The largest raw value less the smallest
+    range: u128,
+    /// The largest difference between neighbouring rows less the smallest
+    delta_range: u128,
+}
+
+impl Statistics {
+    pub(super) fn new(raws: &[i128]) -> Self {
+        let mut distinct = HashSet::new();
+        let (mut min, mut max) = (i128::MAX, i128::MIN);
+        let (mut min_delta, mut max_delta) = (i128::MAX, i128::MIN);
+        let mut runs = 0;
+        for (row, raw) in raws.iter().enumerate() {
+            distinct.insert(*raw);
+            (min, max) = (min.min(*raw), max.max(*raw));
+            if row == 0 || raws[row - 1] != *raw {
+                runs += 1;
+            }
+            if row > 0 {
+                let delta = raw.wrapping_sub(raws[row - 1]);
+                (min_delta, max_delta) = (min_delta.min(delta), max_delta.max(delta));
+            }
+        }
+        Statistics {
+            rows: raws.len(),
+            runs,
+            distinct: distinct.len(),
+            range: max.wrapping_sub(min) as u128,
+            delta_range: max_delta.wrapping_sub(min_delta) as u128,
+        }
+    }
+
+    /// The encoding making the values of a column of type `base` smallest.
+    /// Differences only make sense between integers, so Numbers, Strings
+    /// and Json documents are never delta encoded or bit-packed, and
+    /// Booleans are always bit-packed already.
+    pub(super) fn choose(&self, base: &ValueType) -> Encoding {
+        let width = width(base);
+        let integers = matches!(
+            base,
+            ValueType::Integer | ValueType::Decimal(_) | ValueType::Categorical(_)
+        );
+        let boolean = *base == ValueType::Boolean;
+        let candidates = [
+            (
+                Encoding::Plain,
+                true,
+                match boolean {
+                    true => packed_words(self.rows, 1) * 8,
+                    false => self.rows * width,
+                },
+            ),
+            (Encoding::RunLength, true, 4 + self.runs * (4 + width)),
+            (
+                Encoding::Dictionary,
+                !boolean,
+                4 + self.distinct * width
+                    + 1
+                    + packed_words(self.rows, bits(self.distinct.saturating_sub(1) as u128)) * 8,
+            ),
+            (
+                Encoding::Delta,
+                integers && self.rows > 1,
+                width
+                    + 16
+                    + 1
+                    + packed_words(self.rows.saturating_sub(1), bits(self.delta_range)) * 8,
+            ),
+            (
+                Encoding::BitPacked,
+                integers && self.rows > 0,
+                width + 1 + packed_words(self.rows, bits(self.range)) * 8,
+            ),
+        ];
+        let mut best = (Encoding::Plain, usize::MAX);
+        for (encoding, allowed, size) in candidates {
+            if allowed && size < best.1 {
+                best = (encoding, size);
+            }
+        }
+        best.0
+    }
+}
+
+/// The bits needed to hold every value up to `max`
+pub(super) fn bits(max: u128) -> u32 {
+    128 - max.leading_zeros()
+}
+
+/// The `u64` words holding `count` values of `bits` bits each
+pub(super) fn packed_words(count: usize, bits: u32) -> usize {
+    (count * bits as usize + 63) / 64
+}
+
+/// Packs the low `bits` bits of each value, value `i` starting at bit
+/// `i * bits`, with the first bit in the lowest bit of the first word
+pub(super) fn pack(values: impl ExactSizeIterator<Item = u128>, bits: u32) -> Vec<u64> {
+    let mut words = vec![0; packed_words(values.len(), bits)];
+    let mut position = 0;
+    for mut value in values {
+        let mut left = bits;
+        while left > 0 {
+            let (word, offset) = (position / 64, (position % 64) as u32);
+            let take = left.min(64 - offset);
+            words[word] |= (value as u64 & mask(take)) << offset;
+            value = value.checked_shr(take).unwrap_or(0);
+            position += take as usize;
+            left -= take;
+        }
+    }
+    words
+}
+
+/// Reads back `count` values packed by `pack`
+pub(super) fn unpack(words: &[u64], count: usize, bits: u32) -> Vec<u128> {
+    let mut values = Vec::with_capacity(count.min(words.len() * 64));
+    let mut position = 0;
+    for _ in 0..count {
+        let (mut value, mut done) = (0u128, 0);
+        while done < bits {
+            let (word, offset) = (position / 64, (position % 64) as u32);
+            let take = (bits - done).min(64 - offset);
+            value |= (((words[word] >> offset) & mask(take)) as u128) << done;
+            position += take as usize;
+            done += take;
+        }
+        values.push(value);
+    }
+    values
+}
+
+fn mask(bits: u32) -> u64 {
+    u64::MAX.checked_shr(64 - bits).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::expression::Categories;
+
+    #[test]
+    fn packing() {
+        for bits in [0, 1, 7, 63, 64, 65, 100, 128] {
+            let max = u128::MAX.checked_shr(128 - bits).unwrap_or(0);
+            let values = [0, 1 & max, max, max / 3, 5 & max, max];
+            let words = pack(values.iter().copied(), bits);
+            assert_eq!(words.len(), packed_words(values.len(), bits));
+            assert_eq!(unpack(&words, values.len(), bits), values, "{} bits", bits);
+        }
+        // Booleans packed one bit each are the same as a bitmap
+        assert_eq!(pack([1, 0, 1, 1].into_iter(), 1), [0b1101]);
+    }
+
+    #[test]
+    fn choices() {
+        let choose = |raws: &[i128], base: ValueType| Statistics::new(raws).choose(&base);
+        let timestamps = (0..100)
+            .map(|n| 1_714_521_600_000 + n * 60_000)
+            .collect::<Vec<_>>();
+        assert_eq!(choose(&timestamps, ValueType::Integer), Encoding::Delta);
+
+        let small = (0..100).map(|n| (n * 37) % 1000).collect::<Vec<_>>();
+        assert_eq!(choose(&small, ValueType::Integer), Encoding::BitPacked);
+        let status = Categories::new(vec!["a".into(), "b".into(), "c".into()], false).unwrap();
+        assert_eq!(
+            choose(
+                &small.iter().map(|n| n % 3).collect::<Vec<_>>(),
+                ValueType::Categorical(status)
+            ),
+            Encoding::BitPacked
+        );
+
+        let repeated = [[7; 50], [9; 50]].concat();
+        assert_eq!(choose(&repeated, ValueType::Number), Encoding::RunLength);
+        assert_eq!(choose(&repeated[..1], ValueType::Number), Encoding::Plain);
+
+        let labels = (0..100).map(|n| n % 4).collect::<Vec<_>>();
+        assert_eq!(choose(&labels, ValueType::String), Encoding::Dictionary);
+        let unique = (0..100).collect::<Vec<_>>();
+        assert_eq!(choose(&unique, ValueType::String), Encoding::Plain);
+
+        let flags = (0..100).map(|n| n % 2).collect::<Vec<_>>();
+        assert_eq!(choose(&flags, ValueType::Boolean), Encoding::Plain);
+        let set = repeated.iter().map(|n| n % 2).collect::<Vec<_>>();
+        assert_eq!(choose(&set, ValueType::Boolean), Encoding::RunLength);
+        assert_eq!(choose(&[], ValueType::Integer), Encoding::Plain);
+    }
+}
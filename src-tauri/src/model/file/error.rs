@@ -0,0 +1,43 @@
+/// An error reading or writing a workbook file, with the offset into the
+/// file it was found at where there is one
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileError {
+    message: String,
+    offset: Option<u64>,
+}
+
+pub type FileResult<T> = Result<T, FileError>;
+
+impl std::error::Error for FileError {}
+
+impl std::fmt::Display for FileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.offset {
+            Some(offset) => write!(f, " [{:#x}] {}", offset, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl From<std::io::Error> for FileError {
+    fn from(error: std::io::Error) -> Self {
+        FileError::new(&error.to_string(), None)
+    }
+}
+
+impl FileError {
+    pub fn new(message: &str, offset: Option<u64>) -> Self {
+        FileError {
+            message: message.to_string(),
+            offset,
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn offset(&self) -> Option<u64> {
+        self.offset
+    }
+}
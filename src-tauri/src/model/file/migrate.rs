@@ -0,0 +1,73 @@
+use super::{FileError, FileResult, OLDEST_VERSION, VERSION};
+
+/// Upgrades the header of a file of one version to the next, the first
+/// step upgrading version `OLDEST_VERSION`. Steps only change the header,
+/// so a version that lays out data differently must be told apart by
+/// what its header records.
+type Migration = fn(Vec<u8>) -> FileResult<Vec<u8>>;
+
+const MIGRATIONS: [Migration; (VERSION - OLDEST_VERSION) as usize] =
+    [add_sections, record_encodings];
+
+/// Checks a file of `version` can be read, and upgrades its header to the
+/// version this build reads, one step at a time
+pub(super) fn upgrade(version: u16, mut header: Vec<u8>) -> FileResult<Vec<u8>> {
+    if version > VERSION {
+        return Err(FileError::new(
+            &format!(
+                "This file needs version {} of the format, this build reads up to version {}",
+                version, VERSION
+            ),
+            Some(10),
+        ));
+    }
+    if version < OLDEST_VERSION {
+        return Err(FileError::new(
+            &format!("Unknown format version {}", version),
+            Some(10),
+        ));
+    }
+    for migrate in &MIGRATIONS[(version - OLDEST_VERSION) as usize..] {
+        header = migrate(header)?;
+    }
+    Ok(header)
+}
+
+/// Version 2 ends the header with a list of sections, which version 1
+/// files have none of
+fn add_sections(mut header: Vec<u8>) -> FileResult<Vec<u8>> {
+    header.extend(0u32.to_le_bytes());
+    Ok(header)
+}
+
+/// Version 3 records how each column is encoded and compressed in bits of
+/// its flags that version 2 leaves clear, which stand for plain values
+fn record_encodings(header: Vec<u8>) -> FileResult<Vec<u8>> {
+    Ok(header)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn versions() {
+        assert_eq!(upgrade(1, vec![7]).unwrap(), [7, 0, 0, 0, 0]);
+        assert_eq!(upgrade(2, vec![7]).unwrap(), [7]);
+        assert_eq!(upgrade(VERSION, vec![7]).unwrap(), [7]);
+
+        let error = upgrade(VERSION + 1, vec![]).unwrap_err();
+        assert_eq!(
+            error.message(),
+            format!(
+                "This file needs version {} of the format, this build reads up to version {}",
+                VERSION + 1,
+                VERSION
+            )
+        );
+        assert_eq!(
+            upgrade(0, vec![]).unwrap_err().message(),
+            "Unknown format version 0"
+        );
+    }
+}
@@ -0,0 +1,63 @@
+mod cache;
+mod checksum;
+mod compress;
+mod encoding;
+mod error;
+mod migrate;
+mod reader;
+mod writer;
+
+use chrono::{DateTime, Utc};
+
+pub use error::{FileError, FileResult};
+pub use reader::{load_workbook, FileColumn, FileSheet, WorkbookReader, DEFAULT_CACHE_BYTES};
+pub use writer::{save_workbook, save_workbook_with, SaveOptions};
+
+/// The first bytes of every workbook file. The high first byte and the line
+/// ending catch files mangled as text.
+pub const MAGIC: [u8; 8] = *b"\x89BOXED\r\n";
+
+/// Written as a little-endian `u16` after the magic number. Every number in
+/// a file is little-endian, whatever machine wrote it.
+pub const BYTE_ORDER: u16 = 0x0102;
+
+/// The version of the format this build writes. Files of older versions
+/// are upgraded as they are read, by the steps in `migrate`.
+pub const VERSION: u16 = 3;
+
+/// The oldest version of the format this build reads
+pub const OLDEST_VERSION: u16 = 1;
+
+/// The bytes before the header: the magic number, byte order, version and
+/// header length
+const PREAMBLE_LENGTH: u64 = 16;
+
+/// Stands for a missing string where a string table index is optional
+const NO_STRING: u32 = u32::MAX;
+
+/// The kind of the section pointing at the string index
+const STRING_INDEX: u16 = 0;
+
+/// How many strings of the string table are read at a time. The string
+/// index holds where each block of this many starts.
+const STRING_BLOCK: u32 = 64;
+
+/// What a file says about itself, rather than about the workbook in it
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileMetadata {
+    pub version: u16,
+    /// The name and version of the application that wrote the file
+    pub application: String,
+    pub created: DateTime<Utc>,
+}
+
+impl FileMetadata {
+    /// The metadata of a file written now, by this build
+    pub fn now() -> Self {
+        FileMetadata {
+            version: VERSION,
+            application: format!("boxed {}", env!("CARGO_PKG_VERSION")),
+            created: Utc::now(),
+        }
+    }
+}
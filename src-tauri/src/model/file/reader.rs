@@ -0,0 +1,1592 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::{BufReader, ErrorKind as IoErrorKind, Read, Seek, SeekFrom},
+    ops::Range,
+    sync::Arc,
+};
+
+use chrono::DateTime;
+
+use super::{
+    cache::{CacheKey, Cached, ColumnCache},
+    checksum::Crc32,
+    compress::decompress,
+    encoding::{packed_words, unpack, width, Encoding},
+    migrate, FileError, FileMetadata, FileResult, BYTE_ORDER, MAGIC, NO_STRING, PREAMBLE_LENGTH,
+    STRING_BLOCK, STRING_INDEX,
+};
+use crate::expression::{
+    Categories, Category, Decimal, ErrorKind, Expression, ExpressionError, Value, ValueType,
+};
+use crate::model::{
+    Bitmap, CellStyle, Colour, Column, EntryDefinition, Format, ModelError, Pattern, RowId, RowIds,
+    Rule, Sheet, SheetEdit, Validation, ValidationMode, Workbook, WorkbookEdit,
+};
+
+/// The most bytes of column data a reader keeps decoded, unless told
+/// otherwise
+pub const DEFAULT_CACHE_BYTES: u64 = 256 * 1024 * 1024;
+
+/// A sheet as the header of a file describes it
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileSheet {
+    pub name: String,
+    pub rows: usize,
+    /// The id the next new row will get
+    pub next_id: u64,
+    pub columns: Vec<FileColumn>,
+    ids: u64,
+}
+
+/// A column as the header of a file describes it, without its data
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileColumn {
+    pub name: String,
+    pub value_type: ValueType,
+    pub hidden: bool,
+    pub formula: Option<Expression>,
+    pub validation: Option<Validation>,
+    pub formats: Vec<Format>,
+    offset: u64,
+    length: u64,
+    encoding: Encoding,
+    compressed: bool,
+}
+
+/// Reads a workbook file a part at a time.
+///
+/// Opening a file only reads its preamble and header, which is enough to
+/// lay out every sheet. Column data is read when it is asked for, whole or a
+/// range of rows at a time. Whole columns are kept decoded until they are
+/// the least recently used of more than the cache holds, and so are the
+/// blocks of strings read for them.
+#[derive(Debug)]
+pub struct WorkbookReader<R> {
+    reader: R,
+    /// Where the file starts in `reader`
+    start: u64,
+    metadata: FileMetadata,
+    string_count: u32,
+    /// The strings the header uses, which start the string table. The rest
+    /// are read a block at a time, as data needs them.
+    strings: Vec<String>,
+    /// How many strings each block of the string table holds
+    block_size: u32,
+    string_index: StringIndex,
+    sheets: Vec<FileSheet>,
+    workspace: Vec<(String, EntryDefinition)>,
+    cache: ColumnCache,
+}
+
+impl<R: Read + Seek> WorkbookReader<R> {
+    /// Reads the header of a file starting at the reader's current position
+    pub fn open(mut reader: R) -> FileResult<Self> {
+        let start = reader.stream_position()?;
+        let mut preamble = [0; PREAMBLE_LENGTH as usize];
+        read_exact(&mut reader, &mut preamble, 0)?;
+        if preamble[..8] != MAGIC {
+            return Err(FileError::new("Not a workbook file", Some(0)));
+        }
+        if preamble[8..10] != BYTE_ORDER.to_le_bytes() {
+            return Err(FileError::new("Unknown byte order", Some(8)));
+        }
+        let version = u16::from_le_bytes([preamble[10], preamble[11]]);
+        let length = u32::from_le_bytes(preamble[12..16].try_into().unwrap());
+        let header = read_bytes(&mut reader, length as u64, PREAMBLE_LENGTH)?;
+        let header = migrate::upgrade(version, header)?;
+
+        // The string counts and the table's offset follow the application
+        // name and the date, and the strings are needed for everything else
+        let mut counts = Decoder::new(&header, PREAMBLE_LENGTH, &[]);
+        counts.take(12)?;
+        let (header_strings, string_count, string_table) =
+            (counts.u32()?, counts.u32()?, counts.u64()?);
+        reader.seek(SeekFrom::Start(start + string_table))?;
+        let mut strings = Vec::new();
+        read_strings(&mut reader, string_table, header_strings, &mut strings)?;
+
+        let mut header = Decoder::new(&header, PREAMBLE_LENGTH, &strings);
+        let application = header.string()?;
+        let created = header.i64()?;
+        let created = DateTime::from_timestamp_millis(created)
+            .ok_or_else(|| header.invalid("Date out of range"))?;
+        header.take(16)?;
+        let sheets = (0..header.count()?)
+            .map(|_| header.sheet())
+            .collect::<FileResult<Vec<_>>>()?;
+        let workspace = (0..header.count()?)
+            .map(|_| header.entry())
+            .collect::<FileResult<Vec<_>>>()?;
+        let (block_size, string_index) = match header.sections()? {
+            Some((block_size, index)) => (block_size, StringIndex::File(index)),
+            None => (STRING_BLOCK, StringIndex::Scanned(string_table, Vec::new())),
+        };
+
+        Ok(WorkbookReader {
+            reader,
+            start,
+            metadata: FileMetadata {
+                version,
+                application,
+                created,
+            },
+            string_count,
+            strings,
+            block_size,
+            string_index,
+            sheets,
+            workspace,
+            cache: ColumnCache::new(DEFAULT_CACHE_BYTES),
+        })
+    }
+
+    pub fn metadata(&self) -> &FileMetadata {
+        &self.metadata
+    }
+
+    pub fn sheets(&self) -> &[FileSheet] {
+        &self.sheets
+    }
+
+    pub fn sheet(&self, name: &str) -> Option<&FileSheet> {
+        self.sheets.iter().find(|sheet| sheet.name == name)
+    }
+
+    /// The workspace entries, in name order
+    pub fn workspace(&self) -> &[(String, EntryDefinition)] {
+        &self.workspace
+    }
+
+    /// Sets the most bytes of column data kept decoded, dropping columns if
+    /// there are more already
+    pub fn set_cache_capacity(&mut self, bytes: u64) {
+        self.cache.set_capacity(bytes);
+    }
+
+    pub fn row_ids(&mut self, sheet: &str) -> FileResult<RowIds> {
+        let index = self.sheet_index(sheet)?;
+        let FileSheet {
+            rows, next_id, ids, ..
+        } = self.sheets[index];
+        let bytes = self.read_at(ids, rows as u64 * 8)?;
+        let mut decoder = Decoder::new(&bytes, ids, &[]);
+        let ids = (0..rows)
+            .map(|_| decoder.u64().map(RowId::new))
+            .collect::<FileResult<Vec<_>>>()?;
+        Ok(RowIds::from_ids(ids, next_id))
+    }
+
+    /// A whole column, with its rules and formats. Computed columns come
+    /// back with none of their rows evaluated.
+    pub fn column(&mut self, sheet: &str, column: &str) -> FileResult<Arc<Column>> {
+        let key = self.column_index(sheet, column)?;
+        if let Some(Cached::Column(column)) = self.cache.get(CacheKey::Column(key.0, key.1)) {
+            return Ok(column);
+        }
+        let rows = self.sheets[key.0].rows;
+        let info = self.sheets[key.0].columns[key.1].clone();
+        let mut column = self.read_column(&info, rows)?;
+        column.set_validation(info.validation);
+        column.set_formats(info.formats);
+        let column = Arc::new(column);
+        // Roughly what the values and validity take decoded
+        let size = rows * width(info.value_type.base()) + (rows + 7) / 8;
+        self.cache.insert(
+            CacheKey::Column(key.0, key.1),
+            Cached::Column(column.clone()),
+            size as u64,
+        );
+        Ok(column)
+    }
+
+    /// Some rows of a column, as a column of their own without rules or
+    /// formats. Only those rows are read, unless the column is already, or
+    /// its values are encoded or compressed so that they can only be read
+    /// whole. Rows past the end are left out.
+    pub fn rows(&mut self, sheet: &str, column: &str, rows: Range<usize>) -> FileResult<Column> {
+        let key = self.column_index(sheet, column)?;
+        if let Some(Cached::Column(column)) = self.cache.peek(CacheKey::Column(key.0, key.1)) {
+            return Ok(column.slice(rows));
+        }
+        let total = self.sheets[key.0].rows;
+        let info = self.sheets[key.0].columns[key.1].clone();
+        if info.formula.is_none() && (info.encoding != Encoding::Plain || info.compressed) {
+            return Ok(self.column(sheet, column)?.slice(rows));
+        }
+        self.read_rows(&info, total, rows)
+    }
+
+    /// Reads the whole file to check it against the checksum in its footer.
+    /// The file is taken to run to the end of the reader.
+    pub fn verify(&mut self) -> FileResult<()> {
+        let end = self.reader.seek(SeekFrom::End(0))?;
+        let length = end
+            .checked_sub(self.start + 4)
+            .ok_or_else(|| FileError::new("File ends early", Some(0)))?;
+        self.reader.seek(SeekFrom::Start(self.start))?;
+        let mut checksum = Crc32::new();
+        let mut buffer = vec![0; 64 * 1024];
+        let mut left = length;
+        while left > 0 {
+            let count = left.min(buffer.len() as u64) as usize;
+            read_exact(&mut self.reader, &mut buffer[..count], length - left)?;
+            checksum.update(&buffer[..count]);
+            left -= count as u64;
+        }
+        let mut footer = [0; 4];
+        read_exact(&mut self.reader, &mut footer, length)?;
+        if checksum.finish() != u32::from_le_bytes(footer) {
+            return Err(FileError::new(
+                "Checksum does not match, the file is damaged",
+                Some(length),
+            ));
+        }
+        Ok(())
+    }
+
+    fn sheet_index(&self, sheet: &str) -> FileResult<usize> {
+        self.sheets
+            .iter()
+            .position(|found| found.name == sheet)
+            .ok_or_else(|| FileError::new(&format!("No sheet named {:?}", sheet), None))
+    }
+
+    fn column_index(&self, sheet: &str, column: &str) -> FileResult<(usize, usize)> {
+        let index = self.sheet_index(sheet)?;
+        let found = self.sheets[index]
+            .columns
+            .iter()
+            .position(|found| found.name == column)
+            .ok_or_else(|| {
+                FileError::new(&format!("No column named {:?} in {}", column, sheet), None)
+            })?;
+        Ok((index, found))
+    }
+
+    fn read_at(&mut self, offset: u64, length: u64) -> FileResult<Vec<u8>> {
+        self.reader.seek(SeekFrom::Start(self.start + offset))?;
+        read_bytes(&mut self.reader, length, offset)
+    }
+
+    /// The blocks of the string table holding `indices`, other than those
+    /// the header uses. Blocks not in the cache are read, a run of them at a
+    /// time, and kept there.
+    fn string_blocks(
+        &mut self,
+        indices: impl IntoIterator<Item = u32>,
+    ) -> FileResult<StringBlocks> {
+        let wanted = indices
+            .into_iter()
+            .filter(|index| (self.strings.len() as u32..self.string_count).contains(index))
+            .map(|index| index / self.block_size)
+            .collect::<BTreeSet<_>>();
+        let mut blocks = BTreeMap::new();
+        let mut missing = Vec::new();
+        for block in wanted {
+            match self.cache.get(CacheKey::Strings(block)) {
+                Some(Cached::Strings(strings)) => {
+                    blocks.insert(block, strings);
+                }
+                _ => missing.push(block),
+            }
+        }
+        let mut rest = missing.as_slice();
+        while !rest.is_empty() {
+            let length = (1..rest.len())
+                .find(|&n| rest[n] != rest[0] + n as u32)
+                .unwrap_or(rest.len());
+            let (run, after) = rest.split_at(length);
+            for (block, strings) in run.iter().zip(self.read_string_blocks(run[0], length)?) {
+                // Roughly what the strings take decoded
+                let size = strings
+                    .iter()
+                    .map(|string| string.len() + 24)
+                    .sum::<usize>();
+                let strings = Arc::new(strings);
+                let key = CacheKey::Strings(*block);
+                self.cache
+                    .insert(key, Cached::Strings(strings.clone()), size as u64);
+                blocks.insert(*block, strings);
+            }
+            rest = after;
+        }
+        Ok(blocks)
+    }
+
+    /// Reads `count` blocks of the string table from block `first`
+    fn read_string_blocks(&mut self, first: u32, count: usize) -> FileResult<Vec<Vec<String>>> {
+        let starts = self.block_starts(first, count + 1)?;
+        if starts.windows(2).any(|pair| pair[0] > pair[1]) {
+            return Err(FileError::new(
+                "String index is out of order",
+                Some(starts[0]),
+            ));
+        }
+        let bytes = self.read_at(starts[0], starts[count] - starts[0])?;
+        let mut blocks = Vec::new();
+        for (block, pair) in (first..).zip(starts.windows(2)) {
+            let count = self
+                .block_size
+                .min(self.string_count - block * self.block_size);
+            let mut strings = Vec::new();
+            let mut bytes = &bytes[(pair[0] - starts[0]) as usize..];
+            if read_strings(&mut bytes, pair[0], count, &mut strings)? != pair[1] {
+                return Err(FileError::new(
+                    "String index does not match the string table",
+                    Some(pair[0]),
+                ));
+            }
+            blocks.push(strings);
+        }
+        Ok(blocks)
+    }
+
+    /// Where `count` blocks of the string table from block `first` start.
+    /// The start after the last block is where the table ends.
+    fn block_starts(&mut self, first: u32, count: usize) -> FileResult<Vec<u64>> {
+        if let StringIndex::Scanned(table, starts) = &self.string_index {
+            if starts.is_empty() {
+                let table = *table;
+                self.string_index = StringIndex::Scanned(table, self.scan_strings(table)?);
+            }
+        }
+        match &self.string_index {
+            StringIndex::File(index) => {
+                let at = index + first as u64 * 8;
+                let bytes = self.read_at(at, count as u64 * 8)?;
+                let mut decoder = Decoder::new(&bytes, at, &[]);
+                (0..count).map(|_| decoder.u64()).collect()
+            }
+            StringIndex::Scanned(_, starts) => starts
+                .get(first as usize..first as usize + count)
+                .map(<[u64]>::to_vec)
+                .ok_or_else(|| FileError::new("No such block of the string table", None)),
+        }
+    }
+
+    /// Finds where each block of the string table starts by reading
+    /// through it, skipping the strings themselves, for files written
+    /// without a string index
+    fn scan_strings(&mut self, table: u64) -> FileResult<Vec<u64>> {
+        self.reader.seek(SeekFrom::Start(self.start + table))?;
+        let mut reader = BufReader::new(&mut self.reader);
+        let (mut starts, mut offset) = (Vec::new(), table);
+        for index in 0..self.string_count {
+            if index % self.block_size == 0 {
+                starts.push(offset);
+            }
+            let mut length = [0; 4];
+            read_exact(&mut reader, &mut length, offset)?;
+            let length = u32::from_le_bytes(length);
+            reader.seek_relative(length as i64)?;
+            offset += 4 + length as u64;
+        }
+        starts.push(offset);
+        Ok(starts)
+    }
+
+    /// Decodes `rows` of a stored column of type `base`, given which rows
+    /// hold a value and their raw values, each from the row paired with
+    /// them, and the list of errors ending its data, from `errors_at`. Only
+    /// the strings those rows use are read.
+    fn cells(
+        &mut self,
+        base: &ValueType,
+        rows: Range<usize>,
+        validity: (&Bitmap, usize),
+        raws: (&[i128], usize),
+        (errors, errors_at): (&[u8], u64),
+    ) -> FileResult<Vec<Value>> {
+        let mut strings = Decoder::new(errors, errors_at, &[]).error_strings(&rows)?;
+        if matches!(base, ValueType::String | ValueType::Json) {
+            strings.extend(
+                rows.clone()
+                    .filter(|row| validity.0.get(row - validity.1).unwrap())
+                    .map(|row| raws.0[row - raws.1] as u32),
+            );
+        }
+        let blocks = self.string_blocks(strings)?;
+        let mut decoder =
+            Decoder::new(errors, errors_at, &self.strings).with_blocks(&blocks, self.block_size);
+        let errors = decoder.errors(&rows)?;
+        decoder.cells(base, rows, validity, raws, errors)
+    }
+
+    /// Reads `rows` of a plain, uncompressed column of a sheet with `total`
+    /// rows. Its values are fixed width, so only the parts of the validity
+    /// and values covering those rows are read, along with its errors.
+    fn read_rows(
+        &mut self,
+        info: &FileColumn,
+        total: usize,
+        rows: Range<usize>,
+    ) -> FileResult<Column> {
+        let rows = rows.start.min(total)..rows.end.clamp(rows.start.min(total), total);
+        if let Some(formula) = &info.formula {
+            return Column::computed(
+                &info.name,
+                info.value_type.clone(),
+                formula.clone(),
+                rows.len(),
+            )
+            .map_err(|error| model_error(error, None));
+        }
+        let base = info.value_type.base();
+        let words = ((total + 63) / 64) as u64 * 8;
+        let (first_word, last_word) = (rows.start / 64, (rows.end + 63) / 64);
+        let word_range = |at: u64| {
+            (
+                at + first_word as u64 * 8,
+                (last_word - first_word) as u64 * 8,
+            )
+        };
+        // Booleans are a bitmap, so are read a word at a time too
+        let (width, boolean) = (width(base), *base == ValueType::Boolean);
+        let (values_at, values_length, count, first_value) = match boolean {
+            true => {
+                let (at, length) = word_range(info.offset + words);
+                (at, length, (last_word - first_word) * 64, first_word * 64)
+            }
+            false => (
+                info.offset + words + (rows.start * width) as u64,
+                (rows.len() * width) as u64,
+                rows.len(),
+                rows.start,
+            ),
+        };
+        let errors_at = info.offset
+            + words
+            + match boolean {
+                true => words,
+                false => (total * width) as u64,
+            };
+
+        let (validity_at, validity_length) = word_range(info.offset);
+        let validity = self.read_at(validity_at, validity_length)?;
+        let values = self.read_at(values_at, values_length)?;
+        let errors_length = (info.offset + info.length)
+            .checked_sub(errors_at)
+            .ok_or_else(|| FileError::new("Column data is too short", Some(info.offset)))?;
+        let errors = self.read_at(errors_at, errors_length)?;
+
+        let mut decoder = Decoder::new(&validity, validity_at, &[]);
+        let validity = decoder.bitmap((last_word - first_word) * 64)?;
+        let raws = Decoder::new(&values, values_at, &[]).raws(base, Encoding::Plain, count)?;
+        let cells = self.cells(
+            base,
+            rows,
+            (&validity, first_word * 64),
+            (&raws, first_value),
+            (&errors, errors_at),
+        )?;
+        Column::from_values(&info.name, info.value_type.clone(), cells)
+            .map_err(|error| model_error(error, Some(info.offset)))
+    }
+
+    /// Reads all of a column of a sheet with `total` rows, decompressing and
+    /// decoding its data if it needs it
+    fn read_column(&mut self, info: &FileColumn, total: usize) -> FileResult<Column> {
+        if info.formula.is_some() || (info.encoding == Encoding::Plain && !info.compressed) {
+            return self.read_rows(info, total, 0..total);
+        }
+        let base = info.value_type.base();
+        let mut data = self.read_at(info.offset, info.length)?;
+        if info.compressed {
+            data =
+                decompress(&data).map_err(|message| FileError::new(message, Some(info.offset)))?;
+        }
+        // Where something is wrong in compressed data, errors give offsets
+        // as if it were not compressed
+        let mut decoder = Decoder::new(&data, info.offset, &[]);
+        let validity = decoder.bitmap(total)?;
+        let raws = decoder.raws(base, info.encoding, total)?;
+        let errors = (
+            &data[decoder.position..],
+            info.offset + decoder.position as u64,
+        );
+        let cells = self.cells(base, 0..total, (&validity, 0), (&raws, 0), errors)?;
+        Column::from_values(&info.name, info.value_type.clone(), cells)
+            .map_err(|error| model_error(error, Some(info.offset)))
+    }
+
+    /// Reads a sheet of the file, every column of it, into the model
+    fn read_sheet(&mut self, index: usize) -> FileResult<Sheet> {
+        let name = self.sheets[index].name.clone();
+        let mut sheet = Sheet::new(&name);
+        for position in 0..self.sheets[index].columns.len() {
+            let info = &self.sheets[index].columns[position];
+            let (column, hidden) = (info.name.clone(), info.hidden);
+            let column = self.column(&name, &column)?;
+            sheet
+                .apply(SheetEdit::AddColumn {
+                    index: position,
+                    column,
+                    hidden,
+                })
+                .map_err(|error| model_error(error, None))?;
+        }
+        let ids = self.row_ids(&name)?;
+        sheet
+            .set_row_ids(ids)
+            .map_err(|error| model_error(error, None))?;
+        Ok(sheet)
+    }
+}
+
+/// Reads a whole workbook from a file starting at the reader's current
+/// position, checking it is undamaged first
+pub fn load_workbook(reader: impl Read + Seek) -> FileResult<Workbook> {
+    let mut reader = WorkbookReader::open(reader)?;
+    reader.verify()?;
+    // Every column is read once, into the workbook
+    reader.set_cache_capacity(0);
+    let mut workbook = Workbook::new();
+    for index in 0..reader.sheets.len() {
+        let sheet = reader.read_sheet(index)?;
+        workbook
+            .apply(WorkbookEdit::AddSheet { index, sheet })
+            .map_err(|error| model_error(error, None))?;
+    }
+    // Entries are saved in name order, so an entry can use one that comes
+    // after it. Each pass sets the entries whose inputs are already set.
+    let mut pending = reader.workspace.clone();
+    while !pending.is_empty() {
+        let mut failed = Vec::new();
+        let mut error = None;
+        for (name, definition) in pending.iter().cloned() {
+            let edit = WorkbookEdit::SetEntry {
+                name: name.clone(),
+                definition: Some(definition.clone()),
+            };
+            if let Err(found) = workbook.apply(edit) {
+                failed.push((name, definition));
+                error = Some(found);
+            }
+        }
+        if failed.len() == pending.len() {
+            return Err(model_error(error.unwrap(), None));
+        }
+        pending = failed;
+    }
+    Ok(workbook)
+}
+
+fn model_error(error: ModelError, offset: Option<u64>) -> FileError {
+    FileError::new(&error.to_string(), offset)
+}
+
+/// Fills `bytes`, read from `offset` in the file
+fn read_exact(reader: &mut impl Read, bytes: &mut [u8], offset: u64) -> FileResult<()> {
+    reader
+        .read_exact(bytes)
+        .map_err(|error| match error.kind() {
+            IoErrorKind::UnexpectedEof => FileError::new("File ends early", Some(offset)),
+            _ => error.into(),
+        })
+}
+
+/// Reads `length` bytes from `offset` in the file. Lengths come from the
+/// file, so the bytes are only allocated as far as they are really there.
+fn read_bytes(reader: &mut impl Read, length: u64, offset: u64) -> FileResult<Vec<u8>> {
+    let mut bytes = Vec::new();
+    Read::take(reader, length).read_to_end(&mut bytes)?;
+    if (bytes.len() as u64) < length {
+        return Err(FileError::new("File ends early", Some(offset)));
+    }
+    Ok(bytes)
+}
+
+/// Reads `count` strings of the string table into `strings`, from `offset`
+/// where the reader is, returning the offset after them
+fn read_strings(
+    reader: &mut impl Read,
+    mut offset: u64,
+    count: u32,
+    strings: &mut Vec<String>,
+) -> FileResult<u64> {
+    let mut reader = std::io::BufReader::new(reader);
+    for _ in 0..count {
+        let mut length = [0; 4];
+        read_exact(&mut reader, &mut length, offset)?;
+        let length = u32::from_le_bytes(length);
+        let bytes = read_bytes(&mut reader, length as u64, offset + 4)?;
+        let string = String::from_utf8(bytes)
+            .map_err(|_| FileError::new("String is not UTF-8", Some(offset)))?;
+        strings.push(string);
+        offset += 4 + length as u64;
+    }
+    Ok(offset)
+}
+
+/// Where each block of the string table starts
+#[derive(Debug)]
+enum StringIndex {
+    /// In the string index, at this offset
+    File(u64),
+    /// Found by reading through the table at this offset, for files
+    /// written without an index. Empty until a block is first needed.
+    Scanned(u64, Vec<u64>),
+}
+
+/// Blocks of the string table read for some data, by number
+type StringBlocks = BTreeMap<u32, Arc<Vec<String>>>;
+
+/// Reads back what the writer encoded, from bytes starting at `offset` in
+/// the file, so errors can say where they were found
+struct Decoder<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    offset: u64,
+    strings: &'a [String],
+    /// Blocks of strings past `strings`, and how many each holds
+    blocks: Option<(&'a StringBlocks, u32)>,
+}
+
+impl<'a> Decoder<'a> {
+    fn new(bytes: &'a [u8], offset: u64, strings: &'a [String]) -> Self {
+        Decoder {
+            bytes,
+            position: 0,
+            offset,
+            strings,
+            blocks: None,
+        }
+    }
+
+    fn with_blocks(mut self, blocks: &'a StringBlocks, size: u32) -> Self {
+        self.blocks = Some((blocks, size));
+        self
+    }
+
+    fn invalid(&self, message: &str) -> FileError {
+        FileError::new(message, Some(self.offset + self.position as u64))
+    }
+
+    fn take(&mut self, count: usize) -> FileResult<&'a [u8]> {
+        let bytes = self
+            .bytes
+            .get(self.position..self.position.saturating_add(count))
+            .ok_or_else(|| self.invalid("Section ends early"))?;
+        self.position += count;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> FileResult<[u8; N]> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    fn u8(&mut self) -> FileResult<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> FileResult<u16> {
+        self.array().map(u16::from_le_bytes)
+    }
+
+    fn u32(&mut self) -> FileResult<u32> {
+        self.array().map(u32::from_le_bytes)
+    }
+
+    fn u64(&mut self) -> FileResult<u64> {
+        self.array().map(u64::from_le_bytes)
+    }
+
+    fn i64(&mut self) -> FileResult<i64> {
+        self.array().map(i64::from_le_bytes)
+    }
+
+    fn i128(&mut self) -> FileResult<i128> {
+        self.array().map(i128::from_le_bytes)
+    }
+
+    fn f64(&mut self) -> FileResult<f64> {
+        self.array().map(f64::from_le_bytes)
+    }
+
+    fn bool(&mut self) -> FileResult<bool> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(self.invalid("Expected 0 or 1")),
+        }
+    }
+
+    fn count(&mut self) -> FileResult<usize> {
+        self.u32().map(|count| count as usize)
+    }
+
+    fn str(&mut self) -> FileResult<&'a str> {
+        let index = self.u32()?;
+        self.string_at(index)
+    }
+
+    fn string_at(&self, index: u32) -> FileResult<&'a str> {
+        let found = match self.strings.get(index as usize) {
+            Some(string) => Some(string),
+            None => self.blocks.and_then(|(blocks, size)| {
+                blocks.get(&(index / size))?.get((index % size) as usize)
+            }),
+        };
+        found
+            .map(String::as_str)
+            .ok_or_else(|| self.invalid(&format!("No string {} in the table", index)))
+    }
+
+    fn string(&mut self) -> FileResult<String> {
+        self.str().map(str::to_string)
+    }
+
+    fn expression(&mut self) -> FileResult<Expression> {
+        let source = self.str()?;
+        Expression::parse(source).map_err(|error| {
+            self.invalid(&format!(
+                "Expression {} does not parse: {}",
+                source,
+                error.to_string().trim_start()
+            ))
+        })
+    }
+
+    fn optional_expression(&mut self) -> FileResult<Option<Expression>> {
+        if self.bytes.get(self.position..self.position + 4) == Some(&NO_STRING.to_le_bytes()) {
+            self.position += 4;
+            return Ok(None);
+        }
+        self.expression().map(Some)
+    }
+
+    fn colour(&mut self) -> FileResult<Colour> {
+        let [r, g, b] = self.array()?;
+        Ok(Colour::new(r, g, b))
+    }
+
+    fn sheet(&mut self) -> FileResult<FileSheet> {
+        Ok(FileSheet {
+            name: self.string()?,
+            rows: self.u64()? as usize,
+            next_id: self.u64()?,
+            ids: self.u64()?,
+            columns: (0..self.count()?)
+                .map(|_| self.column())
+                .collect::<FileResult<_>>()?,
+        })
+    }
+
+    fn column(&mut self) -> FileResult<FileColumn> {
+        let name = self.string()?;
+        let flags = self.u8()?;
+        let encoding = Encoding::from_tag(flags >> 1 & 0b111)
+            .ok_or_else(|| self.invalid(&format!("Unknown encoding {}", flags >> 1 & 0b111)))?;
+        let value_type = self.value_type()?;
+        let formula = self.optional_expression()?;
+        let (offset, length) = (self.u64()?, self.u64()?);
+        let validation = self.validation()?;
+        let formats = (0..self.count()?)
+            .map(|_| self.format())
+            .collect::<FileResult<_>>()?;
+        Ok(FileColumn {
+            name,
+            value_type,
+            hidden: flags & 1 != 0,
+            formula,
+            validation,
+            formats,
+            offset,
+            length,
+            encoding,
+            compressed: flags & 1 << 4 != 0,
+        })
+    }
+
+    /// Reads the sections ending the header, returning the block size and
+    /// offset of the string index if there is one. Sections of other kinds
+    /// are skipped, unless the file marks them as required.
+    fn sections(&mut self) -> FileResult<Option<(u32, u64)>> {
+        let mut string_index = None;
+        for _ in 0..self.count()? {
+            let start = self.position;
+            let kind = self.u16()?;
+            let required = self.u8()? & 1 != 0;
+            let length = self.u32()?;
+            if required && kind != STRING_INDEX {
+                self.position = start;
+                return Err(self.invalid(&format!(
+                    "This file needs section {}, which this build does not know",
+                    kind
+                )));
+            }
+            let at = self.offset + self.position as u64;
+            let section = self.take(length as usize)?;
+            if kind == STRING_INDEX {
+                let mut section = Decoder::new(section, at, &[]);
+                let block_size = section.u32()?;
+                if block_size == 0 {
+                    return Err(section.invalid("String index blocks hold no strings"));
+                }
+                string_index = Some((block_size, section.u64()?));
+            }
+        }
+        Ok(string_index)
+    }
+
+    fn entry(&mut self) -> FileResult<(String, EntryDefinition)> {
+        let name = self.string()?;
+        let definition = match self.u8()? {
+            0 => EntryDefinition::Value(self.value()?),
+            1 => EntryDefinition::Expression(self.expression()?),
+            tag => return Err(self.invalid(&format!("Unknown entry kind {}", tag))),
+        };
+        Ok((name, definition))
+    }
+
+    fn value_type(&mut self) -> FileResult<ValueType> {
+        Ok(match self.u8()? {
+            0 => ValueType::Null,
+            1 => ValueType::Number,
+            2 => ValueType::Integer,
+            3 => ValueType::Decimal(self.u32()?),
+            4 => ValueType::String,
+            5 => ValueType::Boolean,
+            6 => {
+                let ordered = self.bool()?;
+                let labels = (0..self.count()?)
+                    .map(|_| self.string())
+                    .collect::<FileResult<_>>()?;
+                let categories = Categories::new(labels, ordered)
+                    .map_err(|error| self.invalid(error.to_string().trim_start()))?;
+                ValueType::Categorical(categories)
+            }
+            7 => ValueType::Json,
+            8 => ValueType::Array(Box::new(self.value_type()?)),
+            9 => ValueType::Column(Box::new(self.value_type()?)),
+            10 => ValueType::Error,
+            11 => ValueType::Nullable(Box::new(self.value_type()?)),
+            tag => return Err(self.invalid(&format!("Unknown type {}", tag))),
+        })
+    }
+
+    fn value(&mut self) -> FileResult<Value> {
+        Ok(match self.u8()? {
+            0 => Value::Null,
+            1 => Value::Number(self.f64()?),
+            2 => Value::Integer(self.i64()?),
+            3 => {
+                let units = self.i128()?;
+                Value::Decimal(Decimal::new(units, self.u32()?))
+            }
+            4 => Value::String(self.string()?),
+            5 => Value::Boolean(self.bool()?),
+            6 => {
+                let ValueType::Categorical(categories) = self.value_type()? else {
+                    return Err(self.invalid("Expected a Categorical type"));
+                };
+                let code = self.u32()?;
+                Value::Categorical(
+                    Category::from_code(code, &categories)
+                        .ok_or_else(|| self.invalid(&format!("No category {}", code)))?,
+                )
+            }
+            7 => Value::Json(Arc::new(self.json()?)),
+            8 => Value::Array(
+                (0..self.count()?)
+                    .map(|_| self.value())
+                    .collect::<FileResult<_>>()?,
+            ),
+            9 => {
+                let (kind, error) = self.error()?;
+                Value::Error(kind, Box::new(error))
+            }
+            tag => return Err(self.invalid(&format!("Unknown value {}", tag))),
+        })
+    }
+
+    fn json(&mut self) -> FileResult<serde_json::Value> {
+        let source = self.str()?;
+        self.parse_json(source)
+    }
+
+    fn parse_json(&self, source: &str) -> FileResult<serde_json::Value> {
+        serde_json::from_str(source).map_err(|error| self.invalid(&error.to_string()))
+    }
+
+    /// A bitmap of `len` bits
+    fn bitmap(&mut self, len: usize) -> FileResult<Bitmap> {
+        let words = (0..(len + 63) / 64)
+            .map(|_| self.u64())
+            .collect::<FileResult<Vec<_>>>()?;
+        Ok(Bitmap::from_words(words, len))
+    }
+
+    /// A raw value of a stored column of type `base`, as `Encoding`
+    /// describes
+    fn raw(&mut self, base: &ValueType) -> FileResult<i128> {
+        Ok(match base {
+            ValueType::Number => self.u64()? as i128,
+            ValueType::Integer => self.i64()? as i128,
+            ValueType::Decimal(_) => self.i128()?,
+            ValueType::Boolean => self.u8()? as i128,
+            _ => self.u32()? as i128,
+        })
+    }
+
+    /// The `u8` width in bits of `count` packed values, then the values
+    fn packed(&mut self, count: usize) -> FileResult<Vec<u128>> {
+        let bits = self.u8()? as u32;
+        if bits > 128 {
+            return Err(self.invalid(&format!("Values cannot be {} bits wide", bits)));
+        }
+        let words = (0..packed_words(count, bits))
+            .map(|_| self.u64())
+            .collect::<FileResult<Vec<_>>>()?;
+        Ok(unpack(&words, count, bits))
+    }
+
+    /// The raw values of `count` rows of a stored column of type `base`
+    fn raws(
+        &mut self,
+        base: &ValueType,
+        encoding: Encoding,
+        count: usize,
+    ) -> FileResult<Vec<i128>> {
+        Ok(match encoding {
+            Encoding::Plain if *base == ValueType::Boolean => {
+                let bitmap = self.bitmap(count)?;
+                bitmap.iter().map(|bit| bit as i128).collect()
+            }
+            Encoding::Plain => (0..count)
+                .map(|_| self.raw(base))
+                .collect::<FileResult<_>>()?,
+            Encoding::RunLength => {
+                let mut raws = Vec::new();
+                for _ in 0..self.count()? {
+                    let length = self.u32()? as usize;
+                    let raw = self.raw(base)?;
+                    if raws.len() + length > count {
+                        return Err(self.invalid("Runs add up to more rows than there are"));
+                    }
+                    raws.extend(std::iter::repeat(raw).take(length));
+                }
+                if raws.len() < count {
+                    return Err(self.invalid("Runs add up to fewer rows than there are"));
+                }
+                raws
+            }
+            Encoding::Dictionary => {
+                let entries = (0..self.count()?)
+                    .map(|_| self.raw(base))
+                    .collect::<FileResult<Vec<_>>>()?;
+                let codes = self.packed(count)?;
+                codes
+                    .into_iter()
+                    .map(|code| {
+                        entries
+                            .get(code as usize)
+                            .copied()
+                            .ok_or_else(|| self.invalid(&format!("No dictionary entry {}", code)))
+                    })
+                    .collect::<FileResult<_>>()?
+            }
+            Encoding::Delta => {
+                let first = self.raw(base)?;
+                let min = self.i128()?;
+                let mut raws = vec![first];
+                for delta in self.packed(count.saturating_sub(1))? {
+                    let raw = raws[raws.len() - 1].wrapping_add(min.wrapping_add(delta as i128));
+                    raws.push(raw);
+                }
+                raws.truncate(count);
+                raws
+            }
+            Encoding::BitPacked => {
+                let min = self.raw(base)?;
+                let values = self.packed(count)?;
+                values
+                    .into_iter()
+                    .map(|value| min.wrapping_add(value as i128))
+                    .collect()
+            }
+        })
+    }
+
+    /// The value a raw value of a stored column of type `base` stands for
+    fn cell(&self, raw: i128, base: &ValueType) -> FileResult<Value> {
+        Ok(match base {
+            ValueType::Number => Value::Number(f64::from_bits(raw as u64)),
+            ValueType::Integer => Value::Integer(raw as i64),
+            ValueType::Decimal(scale) => Value::Decimal(Decimal::new(raw, *scale)),
+            ValueType::Boolean => Value::Boolean(raw != 0),
+            ValueType::String => Value::String(self.string_at(raw as u32)?.to_string()),
+            ValueType::Categorical(categories) => Value::Categorical(
+                Category::from_code(raw as u32, categories)
+                    .ok_or_else(|| self.invalid(&format!("No category {}", raw)))?,
+            ),
+            ValueType::Json => Value::Json(Arc::new(self.parse_json(self.string_at(raw as u32)?)?)),
+            _ => return Err(self.invalid(&format!("{} cannot be stored in a column", base))),
+        })
+    }
+
+    /// The strings the errors of `rows` use, from the list of errors that
+    /// ends a column's data, so they can be read before the errors are
+    fn error_strings(&mut self, rows: &Range<usize>) -> FileResult<Vec<u32>> {
+        let mut strings = Vec::new();
+        for _ in 0..self.count()? {
+            let row = self.u64()? as usize;
+            self.u8()?;
+            let message = self.u32()?;
+            if rows.contains(&row) {
+                strings.push(message);
+            }
+        }
+        Ok(strings)
+    }
+
+    /// The list of rows holding errors that ends a column's data, keeping
+    /// those in `rows`
+    fn errors(&mut self, rows: &Range<usize>) -> FileResult<BTreeMap<usize, Value>> {
+        let mut errors = BTreeMap::new();
+        for _ in 0..self.count()? {
+            let row = self.u64()? as usize;
+            if !rows.contains(&row) {
+                // Its kind and message, which may not have been read
+                self.take(5)?;
+                continue;
+            }
+            let (kind, error) = self.error()?;
+            errors.insert(row, Value::Error(kind, Box::new(error)));
+        }
+        Ok(errors)
+    }
+
+    /// The values of `rows` of a stored column of type `base`, given which
+    /// rows hold errors, and which hold a value and their raw values, each
+    /// from the row paired with them
+    fn cells(
+        &self,
+        base: &ValueType,
+        rows: Range<usize>,
+        (validity, first_valid): (&Bitmap, usize),
+        (raws, first_raw): (&[i128], usize),
+        mut errors: BTreeMap<usize, Value>,
+    ) -> FileResult<Vec<Value>> {
+        rows.map(|row| match errors.remove(&row) {
+            Some(error) => Ok(error),
+            None if validity.get(row - first_valid).unwrap() => {
+                self.cell(raws[row - first_raw], base)
+            }
+            None => Ok(Value::Null),
+        })
+        .collect()
+    }
+
+    fn error(&mut self) -> FileResult<(ErrorKind, ExpressionError)> {
+        let kind = match self.u8()? {
+            0 => ErrorKind::DivByZero,
+            1 => ErrorKind::Domain,
+            2 => ErrorKind::NotFound,
+            3 => ErrorKind::Type,
+            4 => ErrorKind::Overflow,
+            5 => ErrorKind::Invalid,
+            tag => return Err(self.invalid(&format!("Unknown error kind {}", tag))),
+        };
+        Ok((kind, ExpressionError::with_kind(kind, self.str()?)))
+    }
+
+    fn validation(&mut self) -> FileResult<Option<Validation>> {
+        if !self.bool()? {
+            return Ok(None);
+        }
+        let mode = match self.u8()? {
+            0 => ValidationMode::Reject,
+            1 => ValidationMode::Warn,
+            2 => ValidationMode::Quarantine,
+            tag => return Err(self.invalid(&format!("Unknown validation mode {}", tag))),
+        };
+        let rules = (0..self.count()?)
+            .map(|_| {
+                Ok(match self.u8()? {
+                    0 => Rule::Formula(self.expression()?),
+                    1 => Rule::Unique,
+                    2 => Rule::Allowed(
+                        (0..self.count()?)
+                            .map(|_| self.value())
+                            .collect::<FileResult<_>>()?,
+                    ),
+                    3 => Rule::Pattern(
+                        Pattern::new(self.str()?).map_err(|error| self.invalid(error.message()))?,
+                    ),
+                    tag => return Err(self.invalid(&format!("Unknown rule {}", tag))),
+                })
+            })
+            .collect::<FileResult<_>>()?;
+        Ok(Some(Validation::new(rules, mode)))
+    }
+
+    fn format(&mut self) -> FileResult<Format> {
+        let tag = self.u8()?;
+        let value = self.expression()?;
+        Ok(match tag {
+            0 => {
+                let (low, high) = (self.colour()?, self.colour()?);
+                let mid = match self.bool()? {
+                    true => Some(self.colour()?),
+                    false => None,
+                };
+                Format::ColourScale {
+                    value,
+                    low,
+                    mid,
+                    high,
+                }
+            }
+            1 => Format::DataBar {
+                value,
+                colour: self.colour()?,
+            },
+            2 => Format::IconSet {
+                value,
+                icons: (0..self.count()?)
+                    .map(|_| self.string())
+                    .collect::<FileResult<_>>()?,
+            },
+            3 => Format::Highlight {
+                condition: value,
+                style: self.style()?,
+            },
+            tag => return Err(self.invalid(&format!("Unknown format {}", tag))),
+        })
+    }
+
+    fn style(&mut self) -> FileResult<CellStyle> {
+        let flags = self.u8()?;
+        let set = |bit: u8| flags & 1 << bit != 0;
+        Ok(CellStyle {
+            background: set(0).then(|| self.colour()).transpose()?,
+            foreground: set(1).then(|| self.colour()).transpose()?,
+            bold: set(2),
+            bar: set(3)
+                .then(|| Ok::<_, FileError>((self.f64()?, self.colour()?)))
+                .transpose()?,
+            icon: set(4).then(|| self.string()).transpose()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{cell::Cell, io::Cursor, path::Path, rc::Rc};
+
+    use super::*;
+    use crate::model::file::{
+        save_workbook, save_workbook_with, writer::test::workbook, SaveOptions, OLDEST_VERSION,
+        VERSION,
+    };
+    use crate::model::ModelResult;
+
+    /// Counts the bytes read through it
+    struct Counting {
+        inner: Cursor<Vec<u8>>,
+        read: Rc<Cell<usize>>,
+    }
+
+    impl Read for Counting {
+        fn read(&mut self, bytes: &mut [u8]) -> std::io::Result<usize> {
+            let count = self.inner.read(bytes)?;
+            self.read.set(self.read.get() + count);
+            Ok(count)
+        }
+    }
+
+    impl Seek for Counting {
+        fn seek(&mut self, from: SeekFrom) -> std::io::Result<u64> {
+            self.inner.seek(from)
+        }
+    }
+
+    fn save(workbook: &Workbook) -> Vec<u8> {
+        let mut bytes = Cursor::new(Vec::new());
+        save_workbook(workbook, &mut bytes).unwrap();
+        bytes.into_inner()
+    }
+
+    fn counting(bytes: Vec<u8>) -> (WorkbookReader<Counting>, Rc<Cell<usize>>) {
+        let read = Rc::new(Cell::new(0));
+        let inner = Cursor::new(bytes);
+        let reader = WorkbookReader::open(Counting {
+            inner,
+            read: read.clone(),
+        })
+        .unwrap();
+        (reader, read)
+    }
+
+    /// Spreads numbers over every Integer, so they are saved plain
+    fn hash(n: i64) -> i64 {
+        n.wrapping_mul(0x9E37_79B9_7F4A_7C15_u64 as i64)
+    }
+
+    /// Adds a sheet of `columns` to a new workbook
+    fn with_sheet(name: &str, columns: Vec<ModelResult<Column>>) -> Workbook {
+        let mut sheet = Sheet::new(name);
+        for (index, column) in columns.into_iter().enumerate() {
+            sheet
+                .apply(SheetEdit::AddColumn {
+                    index,
+                    column: Arc::new(column.unwrap()),
+                    hidden: false,
+                })
+                .unwrap();
+        }
+        let mut workbook = Workbook::new();
+        workbook
+            .apply(WorkbookEdit::AddSheet { index: 0, sheet })
+            .unwrap();
+        workbook
+    }
+
+    /// A sheet of `rows` rows, with Integer and String columns
+    fn large(rows: i64) -> Workbook {
+        with_sheet(
+            "Large",
+            vec![
+                Column::from_values("id", ValueType::Integer, (0..rows).map(Value::Integer)),
+                Column::from_values(
+                    "hash",
+                    ValueType::Integer,
+                    (0..rows).map(|n| Value::Integer(hash(n))),
+                ),
+                Column::from_values(
+                    "label",
+                    ValueType::String,
+                    (0..rows).map(|n| Value::String(format!("row {}", n))),
+                ),
+            ],
+        )
+    }
+
+    /// Checks a loaded workbook holds what `workbook` does
+    fn assert_loaded(mut loaded: Workbook) {
+        let mut original = workbook();
+        original.recalculate().unwrap();
+        loaded.recalculate().unwrap();
+        assert_eq!(loaded.workspace(), original.workspace());
+        // Columns are compared by what they hold, as how strings are stored
+        // inside them depends on the order they were added in
+        let names = |workbook: &Workbook| {
+            workbook
+                .sheets()
+                .map(|s| s.name().to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(&loaded), names(&original));
+        for expected in original.sheets() {
+            let sheet = loaded.sheet(expected.name()).unwrap();
+            assert_eq!(sheet.row_ids(), expected.row_ids());
+            assert_eq!(sheet.column_count(), expected.column_count());
+            for (column, expected_column) in sheet.columns().zip(expected.columns()) {
+                let name = column.name();
+                assert_eq!(name, expected_column.name());
+                assert_eq!(sheet.is_hidden(name), expected.is_hidden(name));
+                assert_eq!(column.value_type(), expected_column.value_type());
+                assert_eq!(column.formula(), expected_column.formula());
+                assert_eq!(column.validation(), expected_column.validation());
+                assert_eq!(column.formats(), expected_column.formats());
+                assert_eq!(
+                    column.iter().collect::<Vec<_>>(),
+                    expected_column.iter().collect::<Vec<_>>()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn roundtrip() {
+        assert_loaded(load_workbook(Cursor::new(save(&workbook()))).unwrap());
+    }
+
+    /// Files written by every past version of the format are kept in
+    /// `golden` as `v1.box` and on, each holding `workbook`
+    #[test]
+    fn past_versions() {
+        for version in OLDEST_VERSION..VERSION {
+            let path = Path::new(file!())
+                .with_file_name("golden")
+                .join(format!("v{}.box", version));
+            let bytes = std::fs::read(&path).unwrap();
+            let reader = WorkbookReader::open(Cursor::new(bytes.clone())).unwrap();
+            assert_eq!(reader.metadata().version, version);
+            assert_loaded(load_workbook(Cursor::new(bytes)).unwrap());
+        }
+    }
+
+    #[test]
+    fn newer_versions() {
+        let mut bytes = save(&workbook());
+        bytes[10..12].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        let error = WorkbookReader::open(Cursor::new(bytes)).unwrap_err();
+        let needed = format!("needs version {} of the format", VERSION + 1);
+        assert!(error.message().contains(&needed), "{}", error);
+    }
+
+    #[test]
+    fn sections() {
+        let section = |kind: u16, flags: u8, data: &[u8]| {
+            let mut bytes = kind.to_le_bytes().to_vec();
+            bytes.push(flags);
+            bytes.extend((data.len() as u32).to_le_bytes());
+            bytes.extend(data);
+            bytes
+        };
+        let sections = |sections: &[Vec<u8>]| {
+            let mut bytes = (sections.len() as u32).to_le_bytes().to_vec();
+            bytes.extend(sections.concat());
+            bytes
+        };
+        // Sections from a later build are skipped, unless they are required
+        let optional = sections(&[section(40, 0, b"later"), section(41, 0, b"")]);
+        let mut decoder = Decoder::new(&optional, 0, &[]);
+        assert_eq!(decoder.sections().unwrap(), None);
+        assert_eq!(decoder.position, optional.len());
+
+        let mut index = 16_u32.to_le_bytes().to_vec();
+        index.extend(1234_u64.to_le_bytes());
+        let known = sections(&[section(40, 0, b"later"), section(STRING_INDEX, 0, &index)]);
+        let found = Decoder::new(&known, 0, &[]).sections().unwrap();
+        assert_eq!(found, Some((16, 1234)));
+
+        let required = sections(&[section(40, 0, b"later"), section(41, 1, b"")]);
+        let error = Decoder::new(&required, 100, &[]).sections().unwrap_err();
+        assert_eq!(
+            error.message(),
+            "This file needs section 41, which this build does not know"
+        );
+        assert_eq!(error.offset(), Some(100 + 16));
+
+        let overlong = sections(&[section(40, 0, b"later")]);
+        let overlong = &overlong[..overlong.len() - 1];
+        let error = Decoder::new(overlong, 0, &[]).sections().unwrap_err();
+        assert_eq!(error.message(), "Section ends early");
+    }
+
+    #[test]
+    fn header() {
+        let original = workbook();
+        let mut reader = WorkbookReader::open(Cursor::new(save(&original))).unwrap();
+        assert_eq!(reader.metadata().version, VERSION);
+        let names = reader.sheets().iter().map(|sheet| &sheet.name);
+        assert_eq!(names.collect::<Vec<_>>(), ["Orders", "Empty"]);
+
+        let orders = reader.sheet("Orders").unwrap();
+        assert_eq!(orders.rows, 3);
+        let notes = orders.columns.iter().find(|c| c.name == "notes").unwrap();
+        assert!(notes.hidden);
+        let tax = orders.columns.iter().find(|c| c.name == "tax").unwrap();
+        assert_eq!(
+            tax.formula.as_ref().unwrap().to_string(),
+            "=:'amount' * rate"
+        );
+        assert_eq!(reader.workspace().len(), 2);
+
+        let expected = original.sheet("Orders").unwrap();
+        assert_eq!(&reader.row_ids("Orders").unwrap(), expected.row_ids());
+        let customer = reader.column("Orders", "customer").unwrap();
+        assert_eq!(
+            customer.iter().collect::<Vec<_>>(),
+            expected
+                .column("customer")
+                .unwrap()
+                .iter()
+                .collect::<Vec<_>>()
+        );
+        assert!(customer.validation().is_some());
+        // The first row was moved to the end
+        let weight = reader.rows("Orders", "weight", 0..5).unwrap();
+        assert_eq!(weight.len(), 3);
+        assert!(matches!(
+            weight.get(0),
+            Some(Value::Error(ErrorKind::Invalid, _))
+        ));
+        assert_eq!(weight.get(2), Some(Value::Number(1.5)));
+        let weight = reader.rows("Orders", "weight", 1..2).unwrap();
+        assert_eq!(weight.iter().collect::<Vec<_>>(), [Value::Number(0.25)]);
+
+        let error = reader.column("Orders", "missing").unwrap_err();
+        assert_eq!(error.message(), "No column named \"missing\" in Orders");
+    }
+
+    #[test]
+    fn reads_on_demand() {
+        let bytes = save(&large(100_000));
+        let length = bytes.len();
+        let (mut reader, read) = counting(bytes);
+        // Only the header, and what buffering reads past it
+        assert!(
+            read.get() < 16 * 1024,
+            "{} of {} bytes read",
+            read.get(),
+            length
+        );
+
+        read.set(0);
+        let rows = reader.rows("Large", "hash", 70_000..70_010).unwrap();
+        assert_eq!(rows.get(3), Some(Value::Integer(hash(70_003))));
+        assert!(read.get() < 1024, "{} bytes read", read.get());
+
+        // Strings are read a block at a time, and kept
+        read.set(0);
+        let labels = reader.rows("Large", "label", 99_998..100_200).unwrap();
+        assert_eq!(labels.len(), 2);
+        assert_eq!(labels.get(1), Some(Value::String("row 99999".to_string())));
+        assert!(read.get() < 2 * 1024, "{} bytes read", read.get());
+        read.set(0);
+        let labels = reader.rows("Large", "label", 0..1).unwrap();
+        assert_eq!(labels.get(0), Some(Value::String("row 0".to_string())));
+        assert!(read.get() < 2 * 1024, "{} bytes read", read.get());
+        read.set(0);
+        reader.rows("Large", "label", 99_999..100_000).unwrap();
+        assert!(read.get() < 64, "{} bytes read", read.get());
+    }
+
+    #[test]
+    fn encodings() {
+        let rows = 2_000;
+        let workbook = with_sheet(
+            "Encoded",
+            vec![
+                Column::from_values(
+                    "when",
+                    ValueType::Integer,
+                    (0..rows).map(|n| Value::Integer(1_714_521_600_000 + n * 60_000)),
+                ),
+                Column::from_values(
+                    "small",
+                    ValueType::Integer,
+                    (0..rows).map(|n| Value::Integer((n * 37) % 1000 - 500)),
+                ),
+                Column::from_values(
+                    "region",
+                    ValueType::String,
+                    (0..rows)
+                        .map(|n| Value::String(["north", "south", "east"][n as usize % 3].into())),
+                ),
+                Column::from_values(
+                    "price",
+                    ValueType::Number,
+                    (0..rows).map(|n| Value::Number((n / 500) as f64 * 1.25)),
+                ),
+                Column::from_values(
+                    "flag",
+                    ValueType::Boolean,
+                    (0..rows).map(|n| Value::Boolean(n % 3 == 0)),
+                ),
+                Column::from_values(
+                    "sparse",
+                    ValueType::Integer.nullable(),
+                    (0..rows).map(|n| match n % 2 {
+                        0 => Value::Integer(n),
+                        _ => Value::Null,
+                    }),
+                ),
+                Column::from_values(
+                    "hash",
+                    ValueType::Integer,
+                    (0..rows).map(|n| Value::Integer(hash(n))),
+                ),
+            ],
+        );
+        let expected = workbook.sheet("Encoded").unwrap();
+        for compress in [false, true] {
+            let mut bytes = Cursor::new(Vec::new());
+            save_workbook_with(&workbook, &mut bytes, &SaveOptions { compress }).unwrap();
+            let mut reader = WorkbookReader::open(Cursor::new(bytes.into_inner())).unwrap();
+            let columns = &reader.sheet("Encoded").unwrap().columns;
+            let encodings = columns.iter().map(|c| c.encoding).collect::<Vec<_>>();
+            assert_eq!(
+                encodings,
+                [
+                    Encoding::Delta,
+                    Encoding::BitPacked,
+                    Encoding::Dictionary,
+                    Encoding::RunLength,
+                    Encoding::Plain,
+                    Encoding::Delta,
+                    Encoding::Plain,
+                ]
+            );
+            let flag = columns.iter().find(|c| c.name == "flag").unwrap();
+            assert_eq!(flag.compressed, compress);
+
+            for column in expected.columns() {
+                let values = column.iter().collect::<Vec<_>>();
+                let rows = reader.rows("Encoded", column.name(), 1_500..1_510).unwrap();
+                assert_eq!(rows.iter().collect::<Vec<_>>(), values[1_500..1_510]);
+                let read = reader.column("Encoded", column.name()).unwrap();
+                assert_eq!(read.iter().collect::<Vec<_>>(), values);
+            }
+        }
+    }
+
+    #[test]
+    fn cache() {
+        let (mut reader, read) = counting(save(&large(1_000)));
+        let column = reader.column("Large", "id").unwrap();
+        assert_eq!(column.get(999), Some(Value::Integer(999)));
+
+        // Cached columns are not read again, whole or in part
+        read.set(0);
+        reader.column("Large", "id").unwrap();
+        assert_eq!(
+            reader.rows("Large", "id", 10..20).unwrap().get(0),
+            Some(Value::Integer(10))
+        );
+        assert_eq!(read.get(), 0);
+
+        // Room for one column, so reading another drops the first
+        reader.set_cache_capacity(10_000);
+        reader.column("Large", "hash").unwrap();
+        reader.column("Large", "label").unwrap();
+        read.set(0);
+        reader.column("Large", "label").unwrap();
+        assert_eq!(read.get(), 0);
+        reader.column("Large", "hash").unwrap();
+        assert!(read.get() > 8_000);
+    }
+
+    #[test]
+    fn damaged() {
+        let bytes = save(&workbook());
+        let error =
+            WorkbookReader::open(Cursor::new(b"not a workbook at all".to_vec())).unwrap_err();
+        assert_eq!(
+            (error.message(), error.offset()),
+            ("Not a workbook file", Some(0))
+        );
+
+        // The header is still readable, but not the table after it
+        let truncated = bytes[..bytes.len() / 2].to_vec();
+        let error = WorkbookReader::open(Cursor::new(truncated)).unwrap_err();
+        assert_eq!(error.message(), "File ends early");
+
+        // Lengths and counts far beyond the end of the file
+        let mut corrupted = bytes.clone();
+        corrupted[12..16].copy_from_slice(&u32::MAX.to_le_bytes());
+        let error = WorkbookReader::open(Cursor::new(corrupted)).unwrap_err();
+        assert_eq!(
+            (error.message(), error.offset()),
+            ("File ends early", Some(PREAMBLE_LENGTH))
+        );
+        let mut corrupted = bytes.clone();
+        let strings = PREAMBLE_LENGTH as usize + 12;
+        corrupted[strings..strings + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(WorkbookReader::open(Cursor::new(corrupted)).is_err());
+
+        // A value in the data, which opening the file does not read
+        let reader = WorkbookReader::open(Cursor::new(bytes.clone())).unwrap();
+        let quantity = &reader.sheet("Orders").unwrap().columns[5];
+        let mut corrupted = bytes.clone();
+        corrupted[quantity.offset as usize + 8] ^= 0xff;
+        let mut reader = WorkbookReader::open(Cursor::new(corrupted.clone())).unwrap();
+        let error = reader.verify().unwrap_err();
+        assert_eq!(error.offset(), Some(bytes.len() as u64 - 4));
+        assert!(load_workbook(Cursor::new(corrupted)).is_err());
+    }
+}
@@ -0,0 +1,882 @@
+use std::io::{Seek, SeekFrom, Write};
+
+use super::{
+    checksum::Crc32,
+    compress::compress,
+    encoding::{bits, pack, width, Encoding, Statistics},
+    FileError, FileMetadata, FileResult, BYTE_ORDER, MAGIC, NO_STRING, PREAMBLE_LENGTH,
+    STRING_BLOCK, STRING_INDEX, VERSION,
+};
+use crate::expression::{ErrorKind, Expression, ExpressionError, Value, ValueType};
+use crate::model::{
+    Bitmap, CellStyle, Colour, Column, ColumnData, EntryDefinition, Format, Rule, StringTable,
+    Validation, ValidationMode, Workbook,
+};
+
+/// How a workbook is saved
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SaveOptions {
+    /// Compresses the data of each column it makes much smaller. Reading any
+    /// row of a compressed column reads all of it.
+    pub compress: bool,
+}
+
+/// Writes a workbook as laid out in docs/files.md, starting at the writer's
+/// current position.
+///
+/// The header comes first but holds the offsets of everything after it, so
+/// it is written once to make room and again at the end, which is what the
+/// writer seeks for. Computed columns are saved as their formulas and
+/// evaluated again once the file is opened.
+pub fn save_workbook(workbook: &Workbook, writer: impl Write + Seek) -> FileResult<()> {
+    save_workbook_with(workbook, writer, &SaveOptions::default())
+}
+
+pub fn save_workbook_with(
+    workbook: &Workbook,
+    writer: impl Write + Seek,
+    options: &SaveOptions,
+) -> FileResult<()> {
+    write_workbook(workbook, writer, &FileMetadata::now(), options)
+}
+
+fn write_workbook(
+    workbook: &Workbook,
+    mut writer: impl Write + Seek,
+    metadata: &FileMetadata,
+    options: &SaveOptions,
+) -> FileResult<()> {
+    let start = writer.stream_position()?;
+    let mut strings = StringTable::new();
+    let mut layout = Layout::new(workbook);
+    // The strings the header uses are interned first, so they can be read
+    // without the rest of the table
+    let header = encode_header(workbook, metadata, &layout, &mut strings)?;
+    layout.header_strings = strings.len() as u32;
+    let preamble = preamble(&header)?;
+    writer.write_all(&preamble)?;
+    writer.write_all(&header)?;
+
+    let data_start = PREAMBLE_LENGTH + header.len() as u64;
+    let mut data = Output::new(&mut writer, data_start);
+    for (sheet, sheet_layout) in workbook.sheets().zip(&mut layout.sheets) {
+        let mut ids = Encoder::new(&mut strings);
+        for id in sheet.row_ids().iter() {
+            ids.u64(id.get());
+        }
+        sheet_layout.row_ids = data.position;
+        data.write(&ids.bytes)?;
+        for (column, block) in sheet.columns().zip(&mut sheet_layout.columns) {
+            if column.is_computed() {
+                continue;
+            }
+            let (bytes, encoding) = encode_column(column, &mut strings);
+            let (bytes, compressed) = match options.compress {
+                true => compress_block(bytes),
+                false => (bytes, false),
+            };
+            *block = ColumnLayout {
+                offset: data.position,
+                length: bytes.len() as u64,
+                encoding,
+                compressed,
+            };
+            data.write(&bytes)?;
+        }
+    }
+    layout.strings = data.position;
+    layout.string_count = strings.len() as u32;
+    let (mut table, mut index) = (Vec::new(), Vec::new());
+    for (number, string) in strings.iter().enumerate() {
+        if number % STRING_BLOCK as usize == 0 {
+            index.extend((layout.strings + table.len() as u64).to_le_bytes());
+        }
+        let length = u32::try_from(string.len())
+            .map_err(|_| FileError::new("String longer than 4GiB", None))?;
+        table.extend(length.to_le_bytes());
+        table.extend(string.as_bytes());
+    }
+    data.write(&table)?;
+    layout.string_index = data.position;
+    index.extend(layout.string_index.to_le_bytes());
+    data.write(&index)?;
+    let (end, rest) = (data.position, data.crc);
+
+    let header = encode_header(workbook, metadata, &layout, &mut strings)?;
+    debug_assert_eq!(strings.len() as u32, layout.string_count);
+    writer.seek(SeekFrom::Start(start + PREAMBLE_LENGTH))?;
+    writer.write_all(&header)?;
+    writer.seek(SeekFrom::Start(start + end))?;
+    let mut checksum = Crc32::new();
+    checksum.update(&preamble);
+    checksum.update(&header);
+    let checksum = checksum.chain(&rest, end - data_start).finish();
+    writer.write_all(&checksum.to_le_bytes())?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Where each part of the data section is, relative to the start of the
+/// file. Everything is zero until it has been written.
+struct Layout {
+    header_strings: u32,
+    strings: u64,
+    string_count: u32,
+    string_index: u64,
+    sheets: Vec<SheetLayout>,
+}
+
+struct SheetLayout {
+    row_ids: u64,
+    /// Where each column's data is and how it is encoded, in schema order
+    columns: Vec<ColumnLayout>,
+}
+
+#[derive(Clone)]
+struct ColumnLayout {
+    offset: u64,
+    length: u64,
+    encoding: Encoding,
+    compressed: bool,
+}
+
+impl Layout {
+    fn new(workbook: &Workbook) -> Self {
+        Layout {
+            header_strings: 0,
+            strings: 0,
+            string_count: 0,
+            string_index: 0,
+            sheets: workbook
+                .sheets()
+                .map(|sheet| SheetLayout {
+                    row_ids: 0,
+                    columns: vec![
+                        ColumnLayout {
+                            offset: 0,
+                            length: 0,
+                            encoding: Encoding::Plain,
+                            compressed: false,
+                        };
+                        sheet.column_count()
+                    ],
+                })
+                .collect(),
+        }
+    }
+}
+
+/// The data section as it is written, with the position it has reached
+/// and the checksum of what it has written, from a zeroed state
+struct Output<W> {
+    writer: W,
+    position: u64,
+    crc: Crc32,
+}
+
+impl<W: Write> Output<W> {
+    fn new(writer: W, position: u64) -> Self {
+        Output {
+            writer,
+            position,
+            crc: Crc32::zeroed(),
+        }
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> FileResult<()> {
+        self.writer.write_all(bytes)?;
+        self.crc.update(bytes);
+        self.position += bytes.len() as u64;
+        Ok(())
+    }
+}
+
+fn preamble(header: &[u8]) -> FileResult<Vec<u8>> {
+    let length =
+        u32::try_from(header.len()).map_err(|_| FileError::new("Header longer than 4GiB", None))?;
+    let mut preamble = Vec::with_capacity(PREAMBLE_LENGTH as usize);
+    preamble.extend(MAGIC);
+    preamble.extend(BYTE_ORDER.to_le_bytes());
+    preamble.extend(VERSION.to_le_bytes());
+    preamble.extend(length.to_le_bytes());
+    Ok(preamble)
+}
+
+fn encode_header(
+    workbook: &Workbook,
+    metadata: &FileMetadata,
+    layout: &Layout,
+    strings: &mut StringTable,
+) -> FileResult<Vec<u8>> {
+    let mut header = Encoder::new(strings);
+    header.string(&metadata.application);
+    header.i64(metadata.created.timestamp_millis());
+    header.u32(layout.header_strings);
+    header.u32(layout.string_count);
+    header.u64(layout.strings);
+    header.count(workbook.sheets().count());
+    for (sheet, sheet_layout) in workbook.sheets().zip(&layout.sheets) {
+        header.string(sheet.name());
+        header.u64(sheet.row_count() as u64);
+        header.u64(sheet.row_ids().next());
+        header.u64(sheet_layout.row_ids);
+        header.count(sheet.column_count());
+        for (column, block) in sheet.columns().zip(&sheet_layout.columns) {
+            header.string(column.name());
+            let hidden = sheet.is_hidden(column.name()).unwrap() as u8;
+            header.u8(hidden | block.encoding.tag() << 1 | (block.compressed as u8) << 4);
+            header.value_type(column.value_type());
+            match column.formula() {
+                Some(formula) => header.expression(formula),
+                None => header.u32(NO_STRING),
+            }
+            header.u64(block.offset);
+            header.u64(block.length);
+            header.validation(column.validation())?;
+            header.count(column.formats().len());
+            for format in column.formats() {
+                header.format(format);
+            }
+        }
+    }
+    header.count(workbook.workspace().len());
+    for (name, entry) in workbook.workspace().entries() {
+        header.string(name);
+        match entry.definition() {
+            EntryDefinition::Value(value) => {
+                header.u8(0);
+                header.value(value)?;
+            }
+            EntryDefinition::Expression(expression) => {
+                header.u8(1);
+                header.expression(expression);
+            }
+        }
+    }
+    header.count(1);
+    header.u16(STRING_INDEX);
+    header.u8(0);
+    header.u32(12);
+    header.u32(STRING_BLOCK);
+    header.u64(layout.string_index);
+    Ok(header.bytes)
+}
+
+/// The data of a static column: which rows hold a value, the values in
+/// the encoding that makes them smallest, and the errors
+fn encode_column(column: &Column, strings: &mut StringTable) -> (Vec<u8>, Encoding) {
+    let base = column.value_type().base();
+    let mut block = Encoder::new(strings);
+    block.bitmap(column.validity());
+    let raws = raw_values(column, block.strings);
+    let encoding = Statistics::new(&raws).choose(base);
+    block.values(&raws, base, encoding);
+    block.count(column.errors().len());
+    for (row, (kind, error)) in column.errors() {
+        block.u64(*row as u64);
+        block.error(*kind, error);
+    }
+    (block.bytes, encoding)
+}
+
+/// The raw value of each row of a static column, as `Encoding` describes.
+/// Rows without a value hold the raw value of the row before, which makes
+/// runs longer and differences smaller.
+fn raw_values(column: &Column, strings: &mut StringTable) -> Vec<i128> {
+    let mut raws: Vec<i128> = match column.data() {
+        ColumnData::Number(values) => values.iter().map(|n| n.to_bits() as i128).collect(),
+        ColumnData::Integer(values) => values.iter().map(|n| *n as i128).collect(),
+        ColumnData::Decimal(values) => values.clone(),
+        ColumnData::Boolean(values) => values.iter().map(|b| b as i128).collect(),
+        ColumnData::String { table, indices } => indices
+            .iter()
+            .map(|index| strings.intern(table.get(*index).unwrap()) as i128)
+            .collect(),
+        ColumnData::Categorical(values) => values.codes().iter().map(|c| *c as i128).collect(),
+        ColumnData::Json(values) => values
+            .iter()
+            .map(|value| strings.intern(&value.to_string()) as i128)
+            .collect(),
+    };
+    for row in 1..raws.len() {
+        if !column.validity().get(row).unwrap() {
+            raws[row] = raws[row - 1];
+        }
+    }
+    raws
+}
+
+/// Compresses a column's data, if that saves at least an eighth of it
+fn compress_block(bytes: Vec<u8>) -> (Vec<u8>, bool) {
+    let compressed = compress(&bytes);
+    match compressed.len() <= bytes.len() - bytes.len() / 8 {
+        true => (compressed, true),
+        false => (bytes, false),
+    }
+}
+
+/// Encodes part of a file, little-endian, interning strings into the file's
+/// string table and writing their indices
+struct Encoder<'a> {
+    bytes: Vec<u8>,
+    strings: &'a mut StringTable,
+}
+
+impl<'a> Encoder<'a> {
+    fn new(strings: &'a mut StringTable) -> Self {
+        Encoder {
+            bytes: Vec::new(),
+            strings,
+        }
+    }
+
+    fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.bytes.extend(value.to_le_bytes());
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.bytes.extend(value.to_le_bytes());
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.bytes.extend(value.to_le_bytes());
+    }
+
+    fn i64(&mut self, value: i64) {
+        self.bytes.extend(value.to_le_bytes());
+    }
+
+    fn i128(&mut self, value: i128) {
+        self.bytes.extend(value.to_le_bytes());
+    }
+
+    fn f64(&mut self, value: f64) {
+        self.bytes.extend(value.to_le_bytes());
+    }
+
+    fn count(&mut self, count: usize) {
+        self.u32(count as u32);
+    }
+
+    fn string(&mut self, value: &str) {
+        let index = self.strings.intern(value);
+        self.u32(index);
+    }
+
+    fn expression(&mut self, expression: &Expression) {
+        self.string(&expression.to_string());
+    }
+
+    /// The low `width` bytes of a raw value
+    fn raw(&mut self, raw: i128, width: usize) {
+        self.bytes.extend(&raw.to_le_bytes()[..width]);
+    }
+
+    /// The raw values of a column, in `encoding`
+    fn values(&mut self, raws: &[i128], base: &ValueType, encoding: Encoding) {
+        let width = width(base);
+        let packed = |encoder: &mut Self, values: &[u128]| {
+            let max = values.iter().max().copied().unwrap_or(0);
+            encoder.u8(bits(max) as u8);
+            for word in pack(values.iter().copied(), bits(max)) {
+                encoder.u64(word);
+            }
+        };
+        match encoding {
+            Encoding::Plain if *base == ValueType::Boolean => {
+                for word in pack(raws.iter().map(|raw| *raw as u128), 1) {
+                    self.u64(word);
+                }
+            }
+            Encoding::Plain => raws.iter().for_each(|raw| self.raw(*raw, width)),
+            Encoding::RunLength => {
+                let mut runs: Vec<(u32, i128)> = Vec::new();
+                for raw in raws {
+                    match runs.last_mut() {
+                        Some((length, value)) if value == raw => *length += 1,
+                        _ => runs.push((1, *raw)),
+                    }
+                }
+                self.count(runs.len());
+                for (length, raw) in runs {
+                    self.u32(length);
+                    self.raw(raw, width);
+                }
+            }
+            Encoding::Dictionary => {
+                let mut entries: Vec<i128> = Vec::new();
+                let mut codes = Vec::with_capacity(raws.len());
+                let mut index = std::collections::HashMap::new();
+                for raw in raws {
+                    let code = *index.entry(*raw).or_insert_with(|| {
+                        entries.push(*raw);
+                        entries.len() - 1
+                    });
+                    codes.push(code as u128);
+                }
+                self.count(entries.len());
+                entries.iter().for_each(|raw| self.raw(*raw, width));
+                packed(self, &codes);
+            }
+            Encoding::Delta => {
+                let deltas = raws
+                    .windows(2)
+                    .map(|pair| pair[1].wrapping_sub(pair[0]))
+                    .collect::<Vec<_>>();
+                let min = deltas.iter().min().copied().unwrap_or(0);
+                self.raw(raws[0], width);
+                self.i128(min);
+                let deltas = deltas.iter().map(|delta| delta.wrapping_sub(min) as u128);
+                packed(self, &deltas.collect::<Vec<_>>());
+            }
+            Encoding::BitPacked => {
+                let min = raws.iter().min().copied().unwrap();
+                self.raw(min, width);
+                let values = raws.iter().map(|raw| raw.wrapping_sub(min) as u128);
+                packed(self, &values.collect::<Vec<_>>());
+            }
+        }
+    }
+
+    /// The words of a bitmap, the first bit in the lowest bit of the first
+    /// word
+    fn bitmap(&mut self, bitmap: &Bitmap) {
+        for word in bitmap.words() {
+            self.u64(*word);
+        }
+    }
+
+    fn colour(&mut self, colour: &Colour) {
+        self.bytes.extend([colour.r, colour.g, colour.b]);
+    }
+
+    fn value_type(&mut self, value_type: &ValueType) {
+        match value_type {
+            ValueType::Null => self.u8(0),
+            ValueType::Number => self.u8(1),
+            ValueType::Integer => self.u8(2),
+            ValueType::Decimal(scale) => {
+                self.u8(3);
+                self.u32(*scale);
+            }
+            ValueType::String => self.u8(4),
+            ValueType::Boolean => self.u8(5),
+            ValueType::Categorical(categories) => {
+                self.u8(6);
+                self.u8(categories.is_ordered() as u8);
+                self.count(categories.len());
+                for label in categories.labels() {
+                    self.string(label);
+                }
+            }
+            ValueType::Json => self.u8(7),
+            ValueType::Array(inner) => {
+                self.u8(8);
+                self.value_type(inner);
+            }
+            ValueType::Column(inner) => {
+                self.u8(9);
+                self.value_type(inner);
+            }
+            ValueType::Error => self.u8(10),
+            ValueType::Nullable(inner) => {
+                self.u8(11);
+                self.value_type(inner);
+            }
+        }
+    }
+
+    fn value(&mut self, value: &Value) -> FileResult<()> {
+        match value {
+            Value::Null => self.u8(0),
+            Value::Number(n) => {
+                self.u8(1);
+                self.f64(*n);
+            }
+            Value::Integer(n) => {
+                self.u8(2);
+                self.i64(*n);
+            }
+            Value::Decimal(d) => {
+                self.u8(3);
+                self.i128(d.units());
+                self.u32(d.scale());
+            }
+            Value::String(s) => {
+                self.u8(4);
+                self.string(s);
+            }
+            Value::Boolean(b) => {
+                self.u8(5);
+                self.u8(*b as u8);
+            }
+            Value::Categorical(category) => {
+                self.u8(6);
+                self.value_type(&ValueType::Categorical(category.categories().clone()));
+                self.u32(category.code());
+            }
+            Value::Json(json) => {
+                self.u8(7);
+                self.string(&json.to_string());
+            }
+            Value::Array(values) => {
+                self.u8(8);
+                self.count(values.len());
+                for value in values {
+                    self.value(value)?;
+                }
+            }
+            Value::Error(kind, error) => {
+                self.u8(9);
+                self.error(*kind, error);
+            }
+            Value::Column(_) => {
+                return Err(FileError::new(
+                    "Whole columns cannot be saved as values",
+                    None,
+                ))
+            }
+        }
+        Ok(())
+    }
+
+    fn error(&mut self, kind: ErrorKind, error: &ExpressionError) {
+        self.u8(match kind {
+            ErrorKind::DivByZero => 0,
+            ErrorKind::Domain => 1,
+            ErrorKind::NotFound => 2,
+            ErrorKind::Type => 3,
+            ErrorKind::Overflow => 4,
+            ErrorKind::Invalid => 5,
+        });
+        self.string(error.message());
+    }
+
+    fn validation(&mut self, validation: Option<&Validation>) -> FileResult<()> {
+        let Some(validation) = validation else {
+            self.u8(0);
+            return Ok(());
+        };
+        self.u8(1);
+        self.u8(match validation.mode {
+            ValidationMode::Reject => 0,
+            ValidationMode::Warn => 1,
+            ValidationMode::Quarantine => 2,
+        });
+        self.count(validation.rules.len());
+        for rule in &validation.rules {
+            match rule {
+                Rule::Formula(formula) => {
+                    self.u8(0);
+                    self.expression(formula);
+                }
+                Rule::Unique => self.u8(1),
+                Rule::Allowed(values) => {
+                    self.u8(2);
+                    self.count(values.len());
+                    for value in values {
+                        self.value(value)?;
+                    }
+                }
+                Rule::Pattern(pattern) => {
+                    self.u8(3);
+                    self.string(pattern.source());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn format(&mut self, format: &Format) {
+        match format {
+            Format::ColourScale {
+                value,
+                low,
+                mid,
+                high,
+            } => {
+                self.u8(0);
+                self.expression(value);
+                self.colour(low);
+                self.colour(high);
+                self.u8(mid.is_some() as u8);
+                if let Some(mid) = mid {
+                    self.colour(mid);
+                }
+            }
+            Format::DataBar { value, colour } => {
+                self.u8(1);
+                self.expression(value);
+                self.colour(colour);
+            }
+            Format::IconSet { value, icons } => {
+                self.u8(2);
+                self.expression(value);
+                self.count(icons.len());
+                for icon in icons {
+                    self.string(icon);
+                }
+            }
+            Format::Highlight { condition, style } => {
+                self.u8(3);
+                self.expression(condition);
+                self.style(style);
+            }
+        }
+    }
+
+    /// A flags byte saying which fields are set, then the fields that are
+    fn style(&mut self, style: &CellStyle) {
+        self.u8(style.background.is_some() as u8
+            | (style.foreground.is_some() as u8) << 1
+            | (style.bold as u8) << 2
+            | (style.bar.is_some() as u8) << 3
+            | (style.icon.is_some() as u8) << 4);
+        if let Some(background) = &style.background {
+            self.colour(background);
+        }
+        if let Some(foreground) = &style.foreground {
+            self.colour(foreground);
+        }
+        if let Some((length, colour)) = &style.bar {
+            self.f64(*length);
+            self.colour(colour);
+        }
+        if let Some(icon) = &style.icon {
+            self.string(icon);
+        }
+    }
+}
+
+#[cfg(test)]
+pub(super) mod test {
+    use std::{io::Cursor, path::Path, sync::Arc};
+
+    use chrono::DateTime;
+
+    use super::*;
+    use crate::expression::{Categories, Category, Decimal};
+    use crate::model::{Pattern, Sheet, SheetEdit, WorkbookEdit};
+
+    /// A workbook using every part of the format: each storable type, nulls,
+    /// errors, hidden and computed columns, rules, formats and the workspace
+    pub(in crate::model::file) fn workbook() -> Workbook {
+        let status = Categories::new(vec!["open".to_string(), "paid".to_string()], true).unwrap();
+        let columns = vec![
+            Column::from_values(
+                "amount",
+                ValueType::Decimal(2),
+                [1050, 2000, 995].map(|units| Value::Decimal(Decimal::new(units, 2))),
+            ),
+            Column::from_values(
+                "customer",
+                ValueType::String.nullable(),
+                vec![
+                    Value::String("Ada".to_string()),
+                    Value::Null,
+                    Value::String("Ada".to_string()),
+                ],
+            ),
+            Column::from_values(
+                "status",
+                ValueType::Categorical(status.clone()),
+                ["open", "paid", "open"]
+                    .map(|label| Value::Categorical(Category::new(label, &status).unwrap())),
+            ),
+            Column::from_values(
+                "shipped",
+                ValueType::Boolean,
+                [true, false, true].map(Value::Boolean),
+            ),
+            Column::from_values(
+                "weight",
+                ValueType::Number,
+                vec![
+                    Value::Number(1.5),
+                    Value::error(ExpressionError::invalid("-2 fails =x > 0")),
+                    Value::Number(0.25),
+                ],
+            ),
+            Column::from_values(
+                "quantity",
+                ValueType::Integer,
+                [1, 2, 3].map(Value::Integer),
+            ),
+            Column::from_values(
+                "notes",
+                ValueType::Json.nullable(),
+                vec![
+                    Value::Json(Arc::new(serde_json::json!({"gift": true}))),
+                    Value::Null,
+                    Value::Null,
+                ],
+            ),
+        ];
+        let mut sheet = Sheet::new("Orders");
+        for (index, column) in columns.into_iter().enumerate() {
+            sheet
+                .apply(SheetEdit::AddColumn {
+                    index,
+                    column: Arc::new(column.unwrap()),
+                    hidden: index == 6,
+                })
+                .unwrap();
+        }
+        let mut workbook = Workbook::new();
+        let edits = vec![
+            WorkbookEdit::AddSheet { index: 0, sheet },
+            // Row ids that are not just the row numbers
+            WorkbookEdit::Sheet {
+                name: "Orders".to_string(),
+                edit: SheetEdit::MoveRows {
+                    from: 0,
+                    count: 1,
+                    to: 2,
+                },
+            },
+            WorkbookEdit::AddSheet {
+                index: 1,
+                sheet: Sheet::new("Empty"),
+            },
+            WorkbookEdit::SetEntry {
+                name: "rate".to_string(),
+                definition: Some(EntryDefinition::Value(Value::Decimal(Decimal::new(15, 2)))),
+            },
+            WorkbookEdit::SetEntry {
+                name: "total".to_string(),
+                definition: Some(EntryDefinition::Expression(
+                    Expression::parse("=sum(:'Orders'!'amount')").unwrap(),
+                )),
+            },
+            WorkbookEdit::SetFormula {
+                sheet: "Orders".to_string(),
+                column: "tax".to_string(),
+                formula: Some(Expression::parse("=:'amount' * rate").unwrap()),
+            },
+            WorkbookEdit::SetValidation {
+                sheet: "Orders".to_string(),
+                column: "customer".to_string(),
+                validation: Some(Validation::new(
+                    vec![
+                        Rule::Pattern(Pattern::new("[A-Z][a-z]+").unwrap()),
+                        Rule::Allowed(vec![
+                            Value::String("Ada".to_string()),
+                            Value::String("Grace".to_string()),
+                        ]),
+                    ],
+                    ValidationMode::Quarantine,
+                )),
+            },
+            WorkbookEdit::SetValidation {
+                sheet: "Orders".to_string(),
+                column: "quantity".to_string(),
+                validation: Some(Validation::new(
+                    vec![
+                        Rule::Formula(Expression::parse("=x > 0").unwrap()),
+                        Rule::Unique,
+                    ],
+                    ValidationMode::Reject,
+                )),
+            },
+            WorkbookEdit::SetFormats {
+                sheet: "Orders".to_string(),
+                column: "amount".to_string(),
+                formats: vec![
+                    Format::ColourScale {
+                        value: Expression::parse("=x").unwrap(),
+                        low: Colour::new(255, 255, 255),
+                        mid: Some(Colour::new(255, 235, 132)),
+                        high: Colour::new(99, 190, 123),
+                    },
+                    Format::DataBar {
+                        value: Expression::parse("=x").unwrap(),
+                        colour: Colour::new(90, 138, 198),
+                    },
+                    Format::IconSet {
+                        value: Expression::parse("=:'quantity'").unwrap(),
+                        icons: vec!["down".to_string(), "up".to_string()],
+                    },
+                    Format::Highlight {
+                        condition: Expression::parse("=:'shipped' & x > rate").unwrap(),
+                        style: CellStyle {
+                            foreground: Some(Colour::new(156, 0, 6)),
+                            bold: true,
+                            ..Default::default()
+                        },
+                    },
+                ],
+            },
+        ];
+        for edit in edits {
+            workbook.apply(edit).unwrap();
+        }
+        workbook
+    }
+
+    pub(in crate::model::file) fn metadata() -> FileMetadata {
+        FileMetadata {
+            version: VERSION,
+            application: "boxed 0.1.0".to_string(),
+            created: DateTime::from_timestamp_millis(1_714_521_600_000).unwrap(),
+        }
+    }
+
+    fn save(workbook: &Workbook, prefix: &[u8]) -> Vec<u8> {
+        let mut cursor = Cursor::new(prefix.to_vec());
+        cursor.set_position(prefix.len() as u64);
+        write_workbook(workbook, &mut cursor, &metadata(), &SaveOptions::default()).unwrap();
+        cursor.into_inner()
+    }
+
+    /// Compares against a file in `golden`, or writes it instead when
+    /// `BOXED_BLESS` is set, after a change to the format
+    fn golden(name: &str, bytes: &[u8]) {
+        let path = Path::new(file!()).with_file_name("golden").join(name);
+        if std::env::var_os("BOXED_BLESS").is_some() {
+            std::fs::write(&path, bytes).unwrap();
+        }
+        let expected = std::fs::read(&path).unwrap();
+        assert!(
+            expected == bytes,
+            "{} no longer matches what is written",
+            name
+        );
+    }
+
+    #[test]
+    fn golden_files() {
+        golden("empty.box", &save(&Workbook::new(), &[]));
+        golden("workbook.box", &save(&workbook(), &[]));
+    }
+
+    #[test]
+    fn layout() {
+        let bytes = save(&workbook(), &[]);
+        let u32_at = |at: usize| u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap());
+        assert_eq!(bytes[..8], MAGIC);
+        assert_eq!(bytes[8..10], [0x02, 0x01]);
+        assert_eq!(u16::from_le_bytes([bytes[10], bytes[11]]), VERSION);
+
+        // The string table's offset follows the application name, the date
+        // and the two string counts
+        let header_length = u32_at(12) as usize;
+        let strings = u64::from_le_bytes(bytes[36..44].try_into().unwrap()) as usize;
+        assert!(strings > 16 + header_length);
+        assert_eq!(u32_at(strings), "boxed 0.1.0".len() as u32);
+        assert_eq!(&bytes[strings + 4..strings + 15], b"boxed 0.1.0");
+
+        let (body, footer) = bytes.split_at(bytes.len() - 4);
+        let mut checksum = Crc32::new();
+        checksum.update(body);
+        assert_eq!(checksum.finish().to_le_bytes(), footer);
+
+        // Offsets are from the start of the file, wherever it is written
+        let prefixed = save(&workbook(), b"prefix");
+        assert_eq!(prefixed[..6], *b"prefix");
+        assert_eq!(prefixed[6..], bytes);
+    }
+}
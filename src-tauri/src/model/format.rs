@@ -0,0 +1,301 @@
+use std::ops::Range;
+
+use super::{retype::ValueContext, Column, ModelError, ModelResult, Workbook};
+use crate::expression::{Expression, Value, ValueType};
+
+/// A colour, as red, green and blue
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Colour {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Colour {
+    pub fn new(r: u8, g: u8, b: u8) -> Self {
+        Colour { r, g, b }
+    }
+
+    /// The colour `t` of the way from this one to `other`
+    pub fn mix(&self, other: &Colour, t: f64) -> Colour {
+        let channel = |a: u8, b: u8| (a as f64 + (b as f64 - a as f64) * t).round() as u8;
+        Colour {
+            r: channel(self.r, other.r),
+            g: channel(self.g, other.g),
+            b: channel(self.b, other.b),
+        }
+    }
+}
+
+impl std::fmt::Display for Colour {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "#{:02x}{:02x}{:02x}", self.r, self.g, self.b)
+    }
+}
+
+/// How a cell is drawn, resolved from its column's formats. Fields left
+/// unset are drawn as usual.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CellStyle {
+    pub background: Option<Colour>,
+    pub foreground: Option<Colour>,
+    pub bold: bool,
+    /// The length of a data bar, from 0 to 1, and its colour
+    pub bar: Option<(f64, Colour)>,
+    pub icon: Option<String>,
+}
+
+impl CellStyle {
+    /// Sets the fields `other` sets, keeping the rest
+    fn overlay(&mut self, other: &CellStyle) {
+        self.background = other.background.or(self.background);
+        self.foreground = other.foreground.or(self.foreground);
+        self.bold |= other.bold;
+        if other.bar.is_some() {
+            self.bar = other.bar;
+        }
+        if other.icon.is_some() {
+            self.icon = other.icon.clone();
+        }
+    }
+}
+
+/// A conditional format of a column.
+///
+/// Each format has an expression evaluated at every row, where `x` is the
+/// cell's value and every other name resolves as in a formula on the
+/// column's sheet. Scales, bars and icons place a numeric `value` between
+/// the smallest and largest it takes in the column. Null, error and
+/// non-numeric values are left unformatted.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Format {
+    /// Colours the background from `low` to `high`, through `mid` if given
+    ColourScale {
+        value: Expression,
+        low: Colour,
+        mid: Option<Colour>,
+        high: Colour,
+    },
+    /// Draws a bar as long as the value is far along
+    DataBar { value: Expression, colour: Colour },
+    /// Picks one of `icons`, from lowest to highest, splitting the range of
+    /// values into equal bands
+    IconSet {
+        value: Expression,
+        icons: Vec<String>,
+    },
+    /// Applies `style` to the cells `condition` is true for
+    Highlight {
+        condition: Expression,
+        style: CellStyle,
+    },
+}
+
+impl Format {
+    pub fn expression(&self) -> &Expression {
+        match self {
+            Format::ColourScale { value, .. }
+            | Format::DataBar { value, .. }
+            | Format::IconSet { value, .. } => value,
+            Format::Highlight { condition, .. } => condition,
+        }
+    }
+
+    pub(super) fn expression_mut(&mut self) -> &mut Expression {
+        match self {
+            Format::ColourScale { value, .. }
+            | Format::DataBar { value, .. }
+            | Format::IconSet { value, .. } => value,
+            Format::Highlight { condition, .. } => condition,
+        }
+    }
+
+    /// Whether the style of a cell depends on every row of the column, as
+    /// the range of values does
+    pub fn is_ranged(&self) -> bool {
+        !matches!(self, Format::Highlight { .. })
+    }
+}
+
+/// The styles of a column's cells worked out so far, and the range of each
+/// ranged format's values. Anything `None` is out of date.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(super) struct StyleCache {
+    ranges: Option<Vec<Option<(f64, f64)>>>,
+    rows: Vec<Option<CellStyle>>,
+}
+
+impl StyleCache {
+    pub(super) fn ranges(&self) -> Option<&[Option<(f64, f64)>]> {
+        self.ranges.as_deref()
+    }
+
+    pub(super) fn set_ranges(&mut self, ranges: Vec<Option<(f64, f64)>>) {
+        self.ranges = Some(ranges);
+    }
+
+    pub(super) fn get(&self, row: usize) -> Option<&CellStyle> {
+        self.rows.get(row)?.as_ref()
+    }
+
+    pub(super) fn store(&mut self, row: usize, style: CellStyle) {
+        if self.rows.len() <= row {
+            self.rows.resize(row + 1, None);
+        }
+        self.rows[row] = Some(style);
+    }
+
+    /// Marks rows out of date. The ranges only change with the values, so
+    /// they are too.
+    pub(super) fn invalidate_rows(&mut self, rows: Range<usize>) {
+        self.ranges = None;
+        let end = rows.end.min(self.rows.len());
+        for row in rows.start.min(end)..end {
+            self.rows[row] = None;
+        }
+    }
+
+    pub(super) fn clear(&mut self) {
+        *self = StyleCache::default();
+    }
+}
+
+/// Checks that formats fit a column of a sheet: values are numeric and
+/// conditions Boolean
+pub(super) fn check(
+    workbook: &Workbook,
+    sheet: &str,
+    column: &Column,
+    formats: &[Format],
+) -> ModelResult<()> {
+    let context = ValueContext {
+        sheet: workbook.context(sheet, Some(0)).unwrap(),
+        value_type: column.value_type(),
+        value: Value::Null,
+    };
+    for format in formats {
+        let expression = format.expression();
+        let result = expression
+            .type_check(&context)
+            .map_err(|error| ModelError::expression(&error, Some(column.name())))?;
+        let fits = match format {
+            Format::Highlight { .. } => {
+                matches!(result.base(), ValueType::Boolean | ValueType::Null)
+            }
+            _ => result.is_numeric() || result == ValueType::Null,
+        };
+        if !fits {
+            let expected = match format {
+                Format::Highlight { .. } => "Boolean",
+                _ => "a number",
+            };
+            return Err(ModelError::new(
+                &format!("Format {} gives {}, needs {}", expression, result, expected),
+                Some(column.name()),
+                None,
+            ));
+        }
+        if let Format::IconSet { icons, .. } = format {
+            if icons.is_empty() {
+                return Err(ModelError::new(
+                    "Icon sets need at least one icon",
+                    Some(column.name()),
+                    None,
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Evaluates a format's expression at a row
+fn eval(workbook: &Workbook, sheet: &str, column: &Column, format: &Format, row: usize) -> Value {
+    let context = ValueContext {
+        sheet: workbook.context(sheet, Some(row)).unwrap(),
+        value_type: column.value_type(),
+        value: column.get(row).unwrap_or(Value::Null),
+    };
+    format
+        .expression()
+        .eval(&context)
+        .unwrap_or_else(Value::error)
+}
+
+fn number(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(n) if n.is_finite() => Some(*n),
+        Value::Integer(n) => Some(*n as f64),
+        Value::Decimal(d) => Some(d.to_f64()),
+        _ => None,
+    }
+}
+
+/// The smallest and largest value of each ranged format over the column,
+/// or `None` if it has no numeric values. The columns the formats use must
+/// be evaluated already.
+pub(super) fn ranges(workbook: &Workbook, sheet: &str, column: &Column) -> Vec<Option<(f64, f64)>> {
+    column
+        .formats()
+        .iter()
+        .map(|format| {
+            if !format.is_ranged() {
+                return None;
+            }
+            (0..column.len())
+                .filter_map(|row| number(&eval(workbook, sheet, column, format, row)))
+                .fold(None, |range, n| match range {
+                    None => Some((n, n)),
+                    Some((low, high)) => Some((n.min(low), n.max(high))),
+                })
+        })
+        .collect()
+}
+
+/// The style of a cell, from every format of its column in order, later
+/// formats overriding earlier ones. `ranges` are as given by `ranges`.
+pub(super) fn style(
+    workbook: &Workbook,
+    sheet: &str,
+    column: &Column,
+    ranges: &[Option<(f64, f64)>],
+    row: usize,
+) -> CellStyle {
+    let mut style = CellStyle::default();
+    for (format, range) in column.formats().iter().zip(ranges) {
+        let value = eval(workbook, sheet, column, format, row);
+        if let Format::Highlight {
+            style: highlight, ..
+        } = format
+        {
+            if value == Value::Boolean(true) {
+                style.overlay(highlight);
+            }
+            continue;
+        }
+        let (Some(n), Some((low, high))) = (number(&value), range) else {
+            continue;
+        };
+        // Every value is the same, so there is nothing to place it between
+        let t = if high > low {
+            (n - low) / (high - low)
+        } else {
+            1.0
+        };
+        match format {
+            Format::ColourScale { low, mid, high, .. } => {
+                style.background = Some(match mid {
+                    Some(mid) if t < 0.5 => low.mix(mid, t * 2.0),
+                    Some(mid) => mid.mix(high, t * 2.0 - 1.0),
+                    None => low.mix(high, t),
+                })
+            }
+            Format::DataBar { colour, .. } => style.bar = Some((t, *colour)),
+            Format::IconSet { icons, .. } => {
+                let band = ((t * icons.len() as f64) as usize).min(icons.len() - 1);
+                style.icon = Some(icons[band].clone());
+            }
+            Format::Highlight { .. } => unreachable!(),
+        }
+    }
+    style
+}
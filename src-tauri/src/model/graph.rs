@@ -0,0 +1,225 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use super::{ModelError, ModelResult};
+
+/// Something in a workbook that other things can depend on
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum NodeId {
+    Column { sheet: String, column: String },
+    Entry(String),
+}
+
+impl NodeId {
+    pub fn column(sheet: &str, column: &str) -> Self {
+        NodeId::Column {
+            sheet: sheet.to_string(),
+            column: column.to_string(),
+        }
+    }
+
+    pub fn entry(name: &str) -> Self {
+        NodeId::Entry(name.to_string())
+    }
+}
+
+impl std::fmt::Display for NodeId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NodeId::Column { sheet, column } => write!(f, "{}!{}", sheet, column),
+            NodeId::Entry(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+/// Which nodes use which.
+///
+/// A node's precedents are the nodes its formula uses, and its dependents
+/// are the nodes whose formulas use it. Every node is tracked, including
+/// static columns and literal entries that have no precedents.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DependencyGraph {
+    precedents: BTreeMap<NodeId, BTreeSet<NodeId>>,
+    dependents: BTreeMap<NodeId, BTreeSet<NodeId>>,
+}
+
+impl DependencyGraph {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Sets the precedents of a node, replacing any it had
+    pub fn set_precedents(&mut self, node: NodeId, precedents: BTreeSet<NodeId>) {
+        if let Some(previous) = self.precedents.remove(&node) {
+            for precedent in previous {
+                if let Some(dependents) = self.dependents.get_mut(&precedent) {
+                    dependents.remove(&node);
+                }
+            }
+        }
+        for precedent in &precedents {
+            self.precedents.entry(precedent.clone()).or_default();
+            self.dependents
+                .entry(precedent.clone())
+                .or_default()
+                .insert(node.clone());
+        }
+        self.dependents.entry(node.clone()).or_default();
+        self.precedents.insert(node, precedents);
+    }
+
+    pub fn nodes(&self) -> impl Iterator<Item = &NodeId> {
+        self.precedents.keys()
+    }
+
+    pub fn contains(&self, node: &NodeId) -> bool {
+        self.precedents.contains_key(node)
+    }
+
+    /// The nodes `node` uses directly
+    pub fn precedents(&self, node: &NodeId) -> BTreeSet<NodeId> {
+        self.precedents.get(node).cloned().unwrap_or_default()
+    }
+
+    /// The nodes that use `node` directly
+    pub fn dependents(&self, node: &NodeId) -> BTreeSet<NodeId> {
+        self.dependents.get(node).cloned().unwrap_or_default()
+    }
+
+    /// Every node `node` uses, directly or through other nodes
+    pub fn all_precedents(&self, node: &NodeId) -> BTreeSet<NodeId> {
+        Self::reachable(&self.precedents, node)
+    }
+
+    /// Every node that uses `node`, directly or through other nodes
+    pub fn all_dependents(&self, node: &NodeId) -> BTreeSet<NodeId> {
+        Self::reachable(&self.dependents, node)
+    }
+
+    fn reachable(edges: &BTreeMap<NodeId, BTreeSet<NodeId>>, node: &NodeId) -> BTreeSet<NodeId> {
+        let mut found = BTreeSet::new();
+        let mut stack = vec![node];
+        while let Some(next) = stack.pop() {
+            for neighbour in edges.get(next).into_iter().flatten() {
+                if found.insert(neighbour.clone()) {
+                    stack.push(neighbour);
+                }
+            }
+        }
+        found
+    }
+
+    /// Every node, ordered so that each comes after its precedents. Fails
+    /// with the full cycle path if nodes depend on each other.
+    pub fn order(&self) -> ModelResult<Vec<NodeId>> {
+        let mut remaining = self
+            .precedents
+            .iter()
+            .map(|(node, precedents)| (node, precedents.len()))
+            .collect::<BTreeMap<_, _>>();
+        let mut ready = remaining
+            .iter()
+            .filter(|(_, count)| **count == 0)
+            .map(|(node, _)| *node)
+            .collect::<Vec<_>>();
+        let mut order = Vec::with_capacity(remaining.len());
+        while let Some(node) = ready.pop() {
+            remaining.remove(node);
+            order.push(node.clone());
+            for dependent in &self.dependents[node] {
+                let count = remaining.get_mut(dependent).unwrap();
+                *count -= 1;
+                if *count == 0 {
+                    ready.push(dependent);
+                }
+            }
+        }
+        match remaining.keys().next() {
+            None => Ok(order),
+            Some(start) => {
+                let cycle = self.cycle_from(start);
+                Err(ModelError::new(
+                    &format!(
+                        "Dependency cycle: {}",
+                        cycle
+                            .iter()
+                            .map(NodeId::to_string)
+                            .collect::<Vec<_>>()
+                            .join(" -> ")
+                    ),
+                    None,
+                    None,
+                ))
+            }
+        }
+    }
+
+    /// Follows precedents from a node left over by `order` until one
+    /// repeats. Every such node has a precedent that is also left over.
+    fn cycle_from(&self, start: &NodeId) -> Vec<NodeId> {
+        let on_cycle = |node: &NodeId| self.all_precedents(node).contains(node);
+        let mut path: Vec<NodeId> = Vec::new();
+        let mut node = start.clone();
+        loop {
+            if let Some(index) = path.iter().position(|visited| *visited == node) {
+                let mut cycle = path.split_off(index);
+                cycle.push(node);
+                return cycle;
+            }
+            path.push(node.clone());
+            node = self.precedents[&node]
+                .iter()
+                .find(|precedent| on_cycle(precedent))
+                .cloned()
+                .unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn graph(edges: &[(&str, &[&str])]) -> DependencyGraph {
+        let mut graph = DependencyGraph::new();
+        for (node, precedents) in edges {
+            graph.set_precedents(
+                NodeId::entry(node),
+                precedents.iter().map(|name| NodeId::entry(name)).collect(),
+            );
+        }
+        graph
+    }
+
+    #[test]
+    fn order_and_queries() {
+        let graph = graph(&[("c", &["a", "b"]), ("b", &["a"]), ("d", &["c"])]);
+        let order = graph.order().unwrap();
+        let position = |name: &str| order.iter().position(|node| *node == NodeId::entry(name));
+        assert!(position("a") < position("b"));
+        assert!(position("b") < position("c"));
+        assert!(position("c") < position("d"));
+
+        let names =
+            |nodes: BTreeSet<NodeId>| nodes.iter().map(NodeId::to_string).collect::<Vec<_>>();
+        assert_eq!(names(graph.precedents(&NodeId::entry("c"))), ["a", "b"]);
+        assert_eq!(names(graph.dependents(&NodeId::entry("a"))), ["b", "c"]);
+        assert_eq!(
+            names(graph.all_precedents(&NodeId::entry("d"))),
+            ["a", "b", "c"]
+        );
+        assert_eq!(names(graph.all_dependents(&NodeId::entry("b"))), ["c", "d"]);
+    }
+
+    #[test]
+    fn cycles() {
+        let mut graph = graph(&[("a", &["b"]), ("b", &["c"]), ("c", &[]), ("x", &["a"])]);
+        assert!(graph.order().is_ok());
+        graph.set_precedents(NodeId::entry("c"), [NodeId::entry("a")].into());
+        assert_eq!(
+            graph.order().unwrap_err().message(),
+            "Dependency cycle: a -> b -> c -> a"
+        );
+        graph.set_precedents(NodeId::entry("c"), BTreeSet::new());
+        assert!(graph.order().is_ok());
+    }
+}
@@ -0,0 +1,371 @@
+use std::collections::VecDeque;
+
+use super::{ModelResult, SheetEdit, Transaction, TransactionError, Workbook, WorkbookEdit};
+
+/// One undoable step, holding the edits that revert it
+#[derive(Debug, Clone, PartialEq)]
+struct Step {
+    label: Option<String>,
+    /// Applied last to first to revert the step
+    edits: Vec<WorkbookEdit>,
+    /// The cell a single cell edit changed, so typing into the same cell
+    /// again can be folded into it
+    cell: Option<(String, String, usize)>,
+}
+
+impl Step {
+    fn new(label: Option<&str>) -> Self {
+        Step {
+            label: label.map(str::to_string),
+            edits: Vec::new(),
+            cell: None,
+        }
+    }
+
+    /// Applies the step's edits last to first, returning the step that
+    /// reverts it. A failed edit reverts the ones before it. The edits put
+    /// back values checked when they were first entered, so validation
+    /// rules are not checked again.
+    fn apply(&self, workbook: &mut Workbook) -> ModelResult<Step> {
+        let mut reverted = Step {
+            edits: Vec::with_capacity(self.edits.len()),
+            ..self.clone()
+        };
+        for edit in self.edits.iter().rev() {
+            match workbook.restore(edit.clone()) {
+                Ok(inverse) => reverted.edits.push(inverse),
+                Err(error) => {
+                    for inverse in reverted.edits.into_iter().rev() {
+                        workbook
+                            .restore(inverse)
+                            .expect("inverse edits always apply");
+                    }
+                    return Err(error);
+                }
+            }
+        }
+        Ok(reverted)
+    }
+}
+
+/// A bounded list of the steps applied to a workbook, which can be undone
+/// and redone.
+///
+/// Every edit applied through the history is a step of its own, except for
+/// repeated edits to the same cell, which are folded into one step until
+/// the history is sealed, and edits made while a group is open, which all
+/// belong to the group's step.
+#[derive(Debug, Clone, PartialEq)]
+pub struct History {
+    undo: VecDeque<Step>,
+    redo: Vec<Step>,
+    limit: usize,
+    group: Option<Step>,
+    depth: usize,
+    sealed: bool,
+}
+
+impl History {
+    /// A history keeping at most `limit` steps to undo
+    pub fn new(limit: usize) -> Self {
+        History {
+            undo: VecDeque::new(),
+            redo: Vec::new(),
+            limit,
+            group: None,
+            depth: 0,
+            sealed: false,
+        }
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    /// The label of the step `undo` would revert
+    pub fn undo_label(&self) -> Option<&str> {
+        self.undo.back().and_then(|step| step.label.as_deref())
+    }
+
+    /// The label of the step `redo` would apply again
+    pub fn redo_label(&self) -> Option<&str> {
+        self.redo.last().and_then(|step| step.label.as_deref())
+    }
+
+    /// Applies an edit to `workbook` and records it
+    pub fn apply(&mut self, workbook: &mut Workbook, edit: WorkbookEdit) -> ModelResult<()> {
+        let cell = match &edit {
+            WorkbookEdit::Sheet {
+                name,
+                edit: SheetEdit::SetCell { column, row, .. },
+            } => Some((name.clone(), column.clone(), *row)),
+            _ => None,
+        };
+        let inverse = workbook.apply(edit)?;
+        self.record(None, vec![inverse], cell);
+        Ok(())
+    }
+
+    /// Commits a transaction to `workbook` and records it as one step
+    pub fn commit(
+        &mut self,
+        workbook: &mut Workbook,
+        transaction: Transaction,
+    ) -> Result<(), TransactionError> {
+        let label = transaction.label().map(str::to_string);
+        let inverses = workbook.commit(transaction)?;
+        self.record(label, inverses, None);
+        Ok(())
+    }
+
+    /// Records edits that were already applied, given the edits that revert
+    /// them in the order they were applied
+    fn record(
+        &mut self,
+        label: Option<String>,
+        edits: Vec<WorkbookEdit>,
+        cell: Option<(String, String, usize)>,
+    ) {
+        self.redo.clear();
+        if let Some(group) = &mut self.group {
+            group.edits.extend(edits);
+            return;
+        }
+        let sealed = std::mem::replace(&mut self.sealed, false);
+        if let Some(last) = self.undo.back() {
+            if !sealed && cell.is_some() && last.cell == cell && last.label.is_none() {
+                // The first edit to the cell already reverts this one too
+                return;
+            }
+        }
+        self.push(Step { label, edits, cell });
+    }
+
+    fn push(&mut self, step: Step) {
+        if step.edits.is_empty() {
+            return;
+        }
+        self.undo.push_back(step);
+        while self.undo.len() > self.limit {
+            self.undo.pop_front();
+        }
+    }
+
+    /// Stops the next edit from being folded into the last step
+    pub fn seal(&mut self) {
+        self.sealed = true;
+    }
+
+    /// Starts a group, so every edit until the matching `end_group` is
+    /// undone and redone as one step. Groups inside groups belong to the
+    /// outermost one.
+    pub fn begin_group(&mut self, label: &str) {
+        if self.depth == 0 {
+            self.group = Some(Step::new(Some(label)));
+        }
+        self.depth += 1;
+    }
+
+    pub fn end_group(&mut self) {
+        self.depth = self.depth.saturating_sub(1);
+        if self.depth == 0 {
+            if let Some(group) = self.group.take() {
+                self.push(group);
+            }
+        }
+    }
+
+    /// Reverts the last step. Returns whether there was one to revert.
+    /// Open groups are ended first.
+    pub fn undo(&mut self, workbook: &mut Workbook) -> ModelResult<bool> {
+        self.close_groups();
+        let Some(step) = self.undo.back() else {
+            return Ok(false);
+        };
+        let redo = step.apply(workbook)?;
+        self.undo.pop_back();
+        self.redo.push(redo);
+        Ok(true)
+    }
+
+    /// Applies the last undone step again. Returns whether there was one.
+    pub fn redo(&mut self, workbook: &mut Workbook) -> ModelResult<bool> {
+        self.close_groups();
+        let Some(step) = self.redo.last() else {
+            return Ok(false);
+        };
+        let mut undo = step.apply(workbook)?;
+        // Redone steps are not folded into by later edits
+        undo.cell = None;
+        self.redo.pop();
+        self.undo.push_back(undo);
+        Ok(true)
+    }
+
+    fn close_groups(&mut self) {
+        if self.depth > 0 {
+            self.depth = 1;
+            self.end_group();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{
+        expression::{Expression, Value, ValueType},
+        model::{Column, Sheet},
+    };
+
+    fn workbook() -> Workbook {
+        let mut sheet = Sheet::new("Orders");
+        let column = Column::from_values(
+            "amount",
+            ValueType::Integer,
+            [30, 10, 20].map(Value::Integer),
+        )
+        .unwrap();
+        sheet
+            .apply(SheetEdit::AddColumn {
+                index: 0,
+                column: Arc::new(column),
+                hidden: false,
+            })
+            .unwrap();
+        let mut workbook = Workbook::new();
+        workbook
+            .apply(WorkbookEdit::AddSheet { index: 0, sheet })
+            .unwrap();
+        workbook
+    }
+
+    fn set_cell(row: usize, value: i64) -> WorkbookEdit {
+        WorkbookEdit::Sheet {
+            name: "Orders".to_string(),
+            edit: SheetEdit::SetCell {
+                column: "amount".to_string(),
+                row,
+                value: Value::Integer(value),
+            },
+        }
+    }
+
+    fn amounts(workbook: &mut Workbook, column: &str) -> Vec<Value> {
+        (0..3)
+            .map(|row| workbook.value("Orders", column, row).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn undo_redo() {
+        let mut workbook = workbook();
+        let mut history = History::new(10);
+        let formula = Expression::parse("=:'amount' * 2").unwrap();
+        history
+            .apply(
+                &mut workbook,
+                WorkbookEdit::SetFormula {
+                    sheet: "Orders".to_string(),
+                    column: "double".to_string(),
+                    formula: Some(formula),
+                },
+            )
+            .unwrap();
+        let graph = workbook.graph().clone();
+        let sort = workbook.sort_by("Orders", "double", false).unwrap();
+        history.apply(&mut workbook, sort).unwrap();
+        assert_eq!(
+            amounts(&mut workbook, "double"),
+            [20, 40, 60].map(Value::Integer)
+        );
+
+        // Undo restores values and dependencies, redo applies them again
+        assert!(history.undo(&mut workbook).unwrap());
+        assert_eq!(
+            amounts(&mut workbook, "amount"),
+            [30, 10, 20].map(Value::Integer)
+        );
+        assert!(history.undo(&mut workbook).unwrap());
+        assert!(workbook.sheet("Orders").unwrap().column("double").is_none());
+        assert_ne!(workbook.graph(), &graph);
+        assert!(!history.undo(&mut workbook).unwrap());
+        assert!(history.redo(&mut workbook).unwrap());
+        assert_eq!(workbook.graph(), &graph);
+        assert!(history.redo(&mut workbook).unwrap());
+        assert_eq!(
+            amounts(&mut workbook, "double"),
+            [20, 40, 60].map(Value::Integer)
+        );
+        assert!(!history.redo(&mut workbook).unwrap());
+
+        // New edits drop what could be redone
+        history.undo(&mut workbook).unwrap();
+        history.apply(&mut workbook, set_cell(0, 1)).unwrap();
+        assert!(!history.can_redo());
+    }
+
+    #[test]
+    fn coalescing_and_groups() {
+        let mut workbook = workbook();
+        let mut history = History::new(2);
+        for value in [1, 12, 123] {
+            history.apply(&mut workbook, set_cell(0, value)).unwrap();
+        }
+        history.seal();
+        history.apply(&mut workbook, set_cell(0, 1234)).unwrap();
+        history.undo(&mut workbook).unwrap();
+        assert_eq!(
+            workbook.value("Orders", "amount", 0).unwrap(),
+            Value::Integer(123)
+        );
+        history.undo(&mut workbook).unwrap();
+        assert_eq!(
+            workbook.value("Orders", "amount", 0).unwrap(),
+            Value::Integer(30)
+        );
+
+        history.begin_group("Import orders.csv");
+        history.apply(&mut workbook, set_cell(1, 0)).unwrap();
+        history.begin_group("Nested");
+        history.apply(&mut workbook, set_cell(2, 0)).unwrap();
+        history.end_group();
+        history.end_group();
+        assert_eq!(history.undo_label(), Some("Import orders.csv"));
+        history.undo(&mut workbook).unwrap();
+        assert_eq!(
+            amounts(&mut workbook, "amount"),
+            [30, 10, 20].map(Value::Integer)
+        );
+        assert_eq!(history.redo_label(), Some("Import orders.csv"));
+
+        let mut transaction = Transaction::labelled("Paste 2 cells");
+        transaction.push(set_cell(0, 5));
+        transaction.push(set_cell(1, 5));
+        history.commit(&mut workbook, transaction).unwrap();
+        assert_eq!(history.undo_label(), Some("Paste 2 cells"));
+        history.undo(&mut workbook).unwrap();
+        assert_eq!(
+            amounts(&mut workbook, "amount"),
+            [30, 10, 20].map(Value::Integer)
+        );
+
+        // Only the most recent steps are kept
+        for row in 0..3 {
+            history.apply(&mut workbook, set_cell(row, 0)).unwrap();
+        }
+        assert!(history.undo(&mut workbook).unwrap());
+        assert!(history.undo(&mut workbook).unwrap());
+        assert!(!history.undo(&mut workbook).unwrap());
+        assert_eq!(
+            workbook.value("Orders", "amount", 0).unwrap(),
+            Value::Integer(0)
+        );
+    }
+}
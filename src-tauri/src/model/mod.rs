@@ -0,0 +1,36 @@
+mod bitmap;
+mod column;
+mod error;
+mod file;
+mod format;
+mod graph;
+mod history;
+mod recalc;
+mod retype;
+mod row_ids;
+mod sheet;
+mod string_table;
+mod transaction;
+mod validation;
+mod workbook;
+mod workspace;
+
+pub use bitmap::Bitmap;
+pub use column::{Column, ColumnData, Summary};
+pub use error::{ModelError, ModelResult};
+pub use file::{
+    load_workbook, save_workbook, save_workbook_with, FileColumn, FileError, FileMetadata,
+    FileResult, FileSheet, SaveOptions, WorkbookReader, DEFAULT_CACHE_BYTES,
+};
+pub use format::{CellStyle, Colour, Format};
+pub use graph::{DependencyGraph, NodeId};
+pub use history::History;
+pub use recalc::{CancelToken, Recalculation, CHUNK_ROWS};
+pub use retype::{ConversionFailure, ConversionReport, FailedRow};
+pub use row_ids::{RowId, RowIds};
+pub use sheet::{Sheet, SheetEdit};
+pub use string_table::StringTable;
+pub use transaction::{Transaction, TransactionError, TransactionFailure};
+pub use validation::{Pattern, Rule, Validation, ValidationMode, ValidationReport, Violation};
+pub use workbook::{SheetContext, Workbook, WorkbookEdit};
+pub use workspace::{EntryDefinition, Workspace, WorkspaceEntry};
@@ -0,0 +1,92 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use futures::{executor::block_on, future::RemoteHandle};
+
+use super::Workbook;
+use crate::expression::{Expression, Value};
+
+/// The most rows of one column evaluated by a single job
+pub const CHUNK_ROWS: usize = 4096;
+
+/// Shared flag telling a running recalculation to stop
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// A recalculation running on a thread pool, started by
+/// `Workbook::start_recalculation`
+pub struct Recalculation {
+    generation: u64,
+    token: CancelToken,
+    handle: RemoteHandle<Option<Workbook>>,
+}
+
+impl Recalculation {
+    pub(super) fn new(
+        generation: u64,
+        token: CancelToken,
+        handle: RemoteHandle<Option<Workbook>>,
+    ) -> Self {
+        Recalculation {
+            generation,
+            token,
+            handle,
+        }
+    }
+
+    /// The generation of the workbook the recalculation started from
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Stops the recalculation, throwing away what it has done so far
+    pub fn cancel(&self) {
+        self.token.cancel();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.token.is_cancelled()
+    }
+
+    /// Waits for the recalculation to finish, returning the snapshot it
+    /// worked on, or `None` if it was cancelled
+    pub(super) fn wait(self) -> Option<Workbook> {
+        block_on(self.handle)
+    }
+}
+
+/// Evaluates a formula at some rows of `sheet`, stopping early with `None`
+/// if `token` is cancelled
+pub(super) fn evaluate_rows(
+    workbook: &Workbook,
+    sheet: &str,
+    formula: &Expression,
+    rows: &[usize],
+    token: &CancelToken,
+) -> Option<Vec<Value>> {
+    rows.iter()
+        .map(|row| {
+            if token.is_cancelled() {
+                return None;
+            }
+            let context = workbook.context(sheet, Some(*row))?;
+            Some(formula.eval(&context).unwrap_or_else(Value::error))
+        })
+        .collect()
+}
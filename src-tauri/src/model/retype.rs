@@ -0,0 +1,137 @@
+use std::sync::Arc;
+
+use super::{Column, ModelError, ModelResult, SheetContext, Workbook};
+use crate::expression::{ColumnRef, ColumnView, Context, Expression, Value, ValueType};
+
+/// What happens to the rows of a column that fail to convert to a new type
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConversionFailure {
+    /// Failed rows become null, which needs a nullable type
+    Null,
+    /// Any failed row stops the conversion
+    Block,
+}
+
+/// A row that failed to convert, with its old value
+#[derive(Debug, Clone, PartialEq)]
+pub struct FailedRow {
+    pub row: usize,
+    pub value: Value,
+    pub reason: String,
+}
+
+/// What converting a column to a new type does to its rows
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ConversionReport {
+    pub converted: usize,
+    pub failed: Vec<FailedRow>,
+}
+
+/// Evaluates an expression at one row of a column, with `x` standing for a
+/// value of the column, as conversions and validation rules do. Every other
+/// name resolves as it would in the column's sheet.
+pub(super) struct ValueContext<'a> {
+    pub sheet: SheetContext<'a>,
+    pub value_type: &'a ValueType,
+    pub value: Value,
+}
+
+impl Context for ValueContext<'_> {
+    fn variable(&self, name: &str) -> Option<Value> {
+        match name {
+            "x" => Some(self.value.clone()),
+            _ => self.sheet.variable(name),
+        }
+    }
+
+    fn variable_type(&self, name: &str) -> Option<ValueType> {
+        match name {
+            "x" => Some(self.value_type.clone()),
+            _ => self.sheet.variable_type(name),
+        }
+    }
+
+    fn column(&self, reference: &ColumnRef) -> Option<Arc<dyn ColumnView>> {
+        self.sheet.column(reference)
+    }
+
+    fn row(&self) -> Option<usize> {
+        self.sheet.row()
+    }
+}
+
+/// Converts every row of a column to `value_type` with `conversion`. Rows
+/// that fail are null in the returned values and listed in the report.
+/// The columns the conversion uses must be evaluated already.
+pub(super) fn convert(
+    workbook: &Workbook,
+    sheet: &str,
+    column: &str,
+    value_type: &ValueType,
+    conversion: &Expression,
+) -> ModelResult<(Vec<Value>, ConversionReport)> {
+    let old = workbook
+        .sheet(sheet)
+        .ok_or_else(|| ModelError::new(&format!("No sheet named {:?}", sheet), None, None))?
+        .column(column)
+        .ok_or_else(|| ModelError::unknown_column(column))?;
+    if old.is_computed() {
+        return Err(ModelError::new(
+            "Column is computed, change its formula instead",
+            Some(column),
+            None,
+        ));
+    }
+    // Checks the type can be stored, and the values against it
+    let target = Column::new(column, value_type.clone())?;
+    let context = |row: Option<usize>, old_value: Value| ValueContext {
+        sheet: workbook.context(sheet, row).unwrap(),
+        value_type: old.value_type(),
+        value: old_value,
+    };
+    let result_type = conversion
+        .type_check(&context(None, Value::Null))
+        .map_err(|error| ModelError::expression(&error, Some(column)))?;
+    if !matches!(result_type, ValueType::Null) && result_type.base() != value_type.base() {
+        return Err(ModelError::new(
+            &format!(
+                "Conversion gives {}, the new type is {}",
+                result_type, value_type
+            ),
+            Some(column),
+            None,
+        ));
+    }
+    let mut report = ConversionReport::default();
+    let values = old
+        .iter()
+        .enumerate()
+        .map(|(row, old_value)| {
+            let value = conversion
+                .eval(&context(Some(row), old_value.clone()))
+                .unwrap_or_else(Value::error);
+            let failure = match &value {
+                Value::Error(_, error) => Some(error.to_string().trim_start().to_string()),
+                value => target
+                    .check(row, value)
+                    .err()
+                    .map(|error| error.message().to_string()),
+            };
+            match failure {
+                Some(reason) => {
+                    report.failed.push(FailedRow {
+                        row,
+                        value: old_value,
+                        reason,
+                    });
+                    Value::Null
+                }
+                None => {
+                    report.converted += 1;
+                    value
+                }
+            }
+        })
+        .collect();
+    Ok((values, report))
+}
@@ -0,0 +1,140 @@
+use std::collections::HashMap;
+
+/// The hidden, stable identity of a row.
+///
+/// A row keeps its id however it moves, so anything that has to follow a
+/// row through sorts, inserts and deletes holds its id rather than its
+/// position. Ids are never reused within a sheet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct RowId(u64);
+
+impl RowId {
+    pub fn new(id: u64) -> Self {
+        RowId(id)
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0
+    }
+}
+
+impl std::fmt::Display for RowId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "#{}", self.0)
+    }
+}
+
+/// The ids of a sheet's rows, in row order, and the id the next new row
+/// will get.
+///
+/// Two lists are equal if they hold the same ids in the same order. The next
+/// id only ever grows, even when the rows that used ids up are removed
+/// again, so it is left out.
+#[derive(Debug, Clone, Default)]
+pub struct RowIds {
+    ids: Vec<RowId>,
+    positions: HashMap<RowId, usize>,
+    next: u64,
+}
+
+impl PartialEq for RowIds {
+    fn eq(&self, other: &Self) -> bool {
+        self.ids == other.ids
+    }
+}
+
+impl RowIds {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Ids read back from a file. `next` is raised past every id given, so
+    /// new rows never get an id in use.
+    pub fn from_ids(ids: Vec<RowId>, next: u64) -> Self {
+        let next = ids.iter().map(|id| id.0 + 1).max().unwrap_or(0).max(next);
+        let mut row_ids = RowIds {
+            ids,
+            positions: HashMap::new(),
+            next,
+        };
+        row_ids.reindex(0);
+        row_ids
+    }
+
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    /// The id the next new row will get
+    pub fn next(&self) -> u64 {
+        self.next
+    }
+
+    pub fn get(&self, row: usize) -> Option<RowId> {
+        self.ids.get(row).copied()
+    }
+
+    /// The row an id is at now
+    pub fn position(&self, id: RowId) -> Option<usize> {
+        self.positions.get(&id).copied()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = RowId> + '_ {
+        self.ids.iter().copied()
+    }
+
+    /// Updates the positions of the rows from `from` onwards
+    fn reindex(&mut self, from: usize) {
+        for (row, id) in self.ids.iter().enumerate().skip(from) {
+            self.positions.insert(*id, row);
+        }
+    }
+
+    /// Inserts rows before row `at`, with the ids given or new ones
+    pub(super) fn insert(&mut self, at: usize, count: usize, ids: Option<Vec<RowId>>) {
+        let ids = ids.unwrap_or_else(|| {
+            let start = self.next;
+            self.next += count as u64;
+            (start..self.next).map(RowId).collect()
+        });
+        self.ids.splice(at..at, ids);
+        self.reindex(at);
+    }
+
+    /// Removes `count` rows from `at`, returning their ids
+    pub(super) fn remove(&mut self, at: usize, count: usize) -> Vec<RowId> {
+        let removed: Vec<_> = self.ids.drain(at..at + count).collect();
+        for id in &removed {
+            self.positions.remove(id);
+        }
+        self.reindex(at);
+        removed
+    }
+
+    /// Moves the `count` rows starting at `from` so they start at `to`,
+    /// counted after the move
+    pub(super) fn move_rows(&mut self, from: usize, count: usize, to: usize) {
+        let moved: Vec<_> = self.ids.drain(from..from + count).collect();
+        self.ids.splice(to..to, moved);
+        self.reindex(from.min(to));
+    }
+
+    /// Puts the ids in a new order, row `i` taking the id of row `order[i]`
+    pub(super) fn permute(&mut self, order: &[usize]) {
+        self.ids = order.iter().map(|from| self.ids[*from]).collect();
+        self.reindex(0);
+    }
+
+    /// Grows or shrinks the list to `len` rows, giving added rows new ids
+    pub(super) fn resize(&mut self, len: usize) {
+        if len < self.len() {
+            self.remove(len, self.len() - len);
+        } else {
+            self.insert(self.len(), len - self.len(), None);
+        }
+    }
+}
@@ -0,0 +1,666 @@
+use std::{collections::BTreeMap, sync::Arc};
+
+use super::{Column, Format, ModelError, ModelResult, RowId, RowIds, Validation};
+use crate::expression::Value;
+
+/// A single change to a sheet.
+///
+/// All changes go through `Sheet::apply`, which returns the edit that undoes
+/// them. Columns are named rather than indexed, so an edit stays valid while
+/// other columns move around it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SheetEdit {
+    /// Adds a column at `index` in the schema. It must have one value per row.
+    AddColumn {
+        index: usize,
+        column: Arc<Column>,
+        hidden: bool,
+    },
+    DropColumn {
+        name: String,
+    },
+    /// Swaps a column for another with the same number of rows, keeping its
+    /// place in the schema. This is how columns change type or formula.
+    ReplaceColumn {
+        name: String,
+        column: Arc<Column>,
+    },
+    RenameColumn {
+        from: String,
+        to: String,
+    },
+    /// Moves a column to `index` in the schema
+    MoveColumn {
+        name: String,
+        index: usize,
+    },
+    SetHidden {
+        name: String,
+        hidden: bool,
+    },
+    /// Sets the validation rules of a column, or removes them if
+    /// `validation` is `None`
+    SetValidation {
+        name: String,
+        validation: Option<Validation>,
+    },
+    /// Sets the conditional formats of a column, in the order they apply
+    SetFormats {
+        name: String,
+        formats: Vec<Format>,
+    },
+    /// Inserts `count` rows before row `at`. `values` holds the new values
+    /// of each column by name, and columns left out are filled with nulls.
+    /// Computed columns are always left out, their values are evaluated.
+    /// New rows get new ids, unless `ids` gives them, as when deleted rows
+    /// are put back.
+    InsertRows {
+        at: usize,
+        count: usize,
+        values: BTreeMap<String, Vec<Value>>,
+        ids: Option<Vec<RowId>>,
+    },
+    DeleteRows {
+        at: usize,
+        count: usize,
+    },
+    /// Moves the `count` rows starting at `from` so they start at `to`,
+    /// counted after the move
+    MoveRows {
+        from: usize,
+        count: usize,
+        to: usize,
+    },
+    /// Puts the rows in a new order, row `i` taking the values of row
+    /// `order[i]`. This is how sheets are sorted.
+    PermuteRows {
+        order: Vec<usize>,
+    },
+    SetCell {
+        column: String,
+        row: usize,
+        value: Value,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct SchemaEntry {
+    column: Arc<Column>,
+    hidden: bool,
+}
+
+/// An ordered set of equally long columns, and the ids of their rows
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sheet {
+    name: String,
+    schema: Vec<SchemaEntry>,
+    ids: RowIds,
+}
+
+impl Sheet {
+    pub fn new(name: &str) -> Self {
+        Sheet {
+            name: name.to_string(),
+            schema: Vec::new(),
+            ids: RowIds::new(),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn rename(&mut self, name: &str) {
+        self.name = name.to_string();
+    }
+
+    /// Points the references its columns' expressions make to sheet `from`
+    /// at sheet `to` instead. Columns without any are left shared.
+    pub(super) fn rename_sheet_references(&mut self, from: &str, to: &str) {
+        for entry in &mut self.schema {
+            let refers = entry
+                .column
+                .expressions()
+                .any(|expression| expression.clone().rename_sheet(from, to));
+            if refers {
+                for expression in Arc::make_mut(&mut entry.column).expressions_mut() {
+                    expression.rename_sheet(from, to);
+                }
+            }
+        }
+    }
+
+    pub fn row_count(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn row_ids(&self) -> &RowIds {
+        &self.ids
+    }
+
+    pub fn row_id(&self, row: usize) -> Option<RowId> {
+        self.ids.get(row)
+    }
+
+    /// Replaces the ids of the rows, as when reading a sheet back from a
+    /// file. There must be one per row, unless the sheet has no columns yet.
+    pub(super) fn set_row_ids(&mut self, ids: RowIds) -> ModelResult<()> {
+        if !self.schema.is_empty() && ids.len() != self.row_count() {
+            return Err(ModelError::new(
+                &format!("{} row ids given for {} rows", ids.len(), self.row_count()),
+                None,
+                None,
+            ));
+        }
+        self.ids = ids;
+        Ok(())
+    }
+
+    /// The row with an id, wherever it has moved to
+    pub fn get_row_by_id(&self, id: RowId) -> Option<usize> {
+        self.ids.position(id)
+    }
+
+    pub fn column_count(&self) -> usize {
+        self.schema.len()
+    }
+
+    /// All columns in schema order, hidden or not
+    pub fn columns(&self) -> impl Iterator<Item = &Arc<Column>> {
+        self.schema.iter().map(|entry| &entry.column)
+    }
+
+    pub fn visible_columns(&self) -> impl Iterator<Item = &Arc<Column>> {
+        self.schema
+            .iter()
+            .filter(|entry| !entry.hidden)
+            .map(|entry| &entry.column)
+    }
+
+    pub fn column(&self, name: &str) -> Option<&Arc<Column>> {
+        self.schema
+            .iter()
+            .find(|entry| entry.column.name() == name)
+            .map(|entry| &entry.column)
+    }
+
+    pub fn column_at(&self, index: usize) -> Option<&Arc<Column>> {
+        self.schema.get(index).map(|entry| &entry.column)
+    }
+
+    pub fn column_index(&self, name: &str) -> Option<usize> {
+        self.schema
+            .iter()
+            .position(|entry| entry.column.name() == name)
+    }
+
+    pub fn is_hidden(&self, name: &str) -> Option<bool> {
+        self.column_index(name)
+            .map(|index| self.schema[index].hidden)
+    }
+
+    pub fn get(&self, column: &str, row: usize) -> Option<Value> {
+        self.column(column)?.get(row)
+    }
+
+    pub(super) fn column_mut(&mut self, name: &str) -> Option<&mut Column> {
+        self.schema
+            .iter_mut()
+            .find(|entry| entry.column.name() == name)
+            .map(|entry| Arc::make_mut(&mut entry.column))
+    }
+
+    /// Marks the values of every computed column, and the styles of every
+    /// formatted one, as out of date
+    pub(super) fn invalidate(&mut self) {
+        for entry in &mut self.schema {
+            if entry.column.is_computed() || !entry.column.formats().is_empty() {
+                let column = Arc::make_mut(&mut entry.column);
+                column.invalidate();
+                column.styles_mut().clear();
+            }
+        }
+    }
+
+    fn index_of(&self, name: &str) -> ModelResult<usize> {
+        self.column_index(name)
+            .ok_or_else(|| ModelError::unknown_column(name))
+    }
+
+    fn check_rows(&self, at: usize, count: usize) -> ModelResult<()> {
+        match at.checked_add(count) {
+            Some(end) if end <= self.row_count() => Ok(()),
+            _ => Err(ModelError::new(
+                &format!("Rows out of range, sheet has {} rows", self.row_count()),
+                None,
+                Some(at),
+            )),
+        }
+    }
+
+    fn check_name(&self, name: &str) -> ModelResult<()> {
+        if name.is_empty() {
+            Err(ModelError::new("Column names cannot be empty", None, None))
+        } else if self.column_index(name).is_some() {
+            Err(ModelError::duplicate_column(name))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Applies an edit, returning the edit that reverts it. Failed edits
+    /// leave the sheet unchanged.
+    pub fn apply(&mut self, edit: SheetEdit) -> ModelResult<SheetEdit> {
+        match edit {
+            SheetEdit::AddColumn {
+                index,
+                column,
+                hidden,
+            } => {
+                self.check_name(column.name())?;
+                // The first column sets the number of rows
+                if !self.schema.is_empty() && column.len() != self.row_count() {
+                    return Err(ModelError::length_mismatch(
+                        column.name(),
+                        self.row_count(),
+                        column.len(),
+                    ));
+                }
+                if index > self.schema.len() {
+                    return Err(ModelError::new(
+                        &format!("Index {} is past the end of the schema", index),
+                        Some(column.name()),
+                        None,
+                    ));
+                }
+                let name = column.name().to_string();
+                self.ids.resize(column.len());
+                self.schema.insert(index, SchemaEntry { column, hidden });
+                Ok(SheetEdit::DropColumn { name })
+            }
+            SheetEdit::DropColumn { name } => {
+                let index = self.index_of(&name)?;
+                let entry = self.schema.remove(index);
+                Ok(SheetEdit::AddColumn {
+                    index,
+                    column: entry.column,
+                    hidden: entry.hidden,
+                })
+            }
+            SheetEdit::ReplaceColumn { name, column } => {
+                let index = self.index_of(&name)?;
+                if column.name() != name {
+                    self.check_name(column.name())?;
+                }
+                if column.len() != self.row_count() {
+                    return Err(ModelError::length_mismatch(
+                        column.name(),
+                        self.row_count(),
+                        column.len(),
+                    ));
+                }
+                let name = column.name().to_string();
+                let previous = std::mem::replace(&mut self.schema[index].column, column);
+                Ok(SheetEdit::ReplaceColumn {
+                    name,
+                    column: previous,
+                })
+            }
+            SheetEdit::RenameColumn { from, to } => {
+                let index = self.index_of(&from)?;
+                if from != to {
+                    self.check_name(&to)?;
+                }
+                Arc::make_mut(&mut self.schema[index].column).rename(&to);
+                Ok(SheetEdit::RenameColumn { from: to, to: from })
+            }
+            SheetEdit::MoveColumn { name, index } => {
+                let from = self.index_of(&name)?;
+                if index >= self.schema.len() {
+                    return Err(ModelError::new(
+                        &format!("Index {} is past the end of the schema", index),
+                        Some(&name),
+                        None,
+                    ));
+                }
+                let entry = self.schema.remove(from);
+                self.schema.insert(index, entry);
+                Ok(SheetEdit::MoveColumn { name, index: from })
+            }
+            SheetEdit::SetHidden { name, hidden } => {
+                let index = self.index_of(&name)?;
+                let previous = std::mem::replace(&mut self.schema[index].hidden, hidden);
+                Ok(SheetEdit::SetHidden {
+                    name,
+                    hidden: previous,
+                })
+            }
+            SheetEdit::SetValidation { name, validation } => {
+                let index = self.index_of(&name)?;
+                let previous =
+                    Arc::make_mut(&mut self.schema[index].column).set_validation(validation);
+                Ok(SheetEdit::SetValidation {
+                    name,
+                    validation: previous,
+                })
+            }
+            SheetEdit::SetFormats { name, formats } => {
+                let index = self.index_of(&name)?;
+                let previous = Arc::make_mut(&mut self.schema[index].column).set_formats(formats);
+                Ok(SheetEdit::SetFormats {
+                    name,
+                    formats: previous,
+                })
+            }
+            SheetEdit::InsertRows {
+                at,
+                count,
+                values,
+                ids,
+            } => {
+                if let Some(ids) = &ids {
+                    if ids.len() != count {
+                        return Err(ModelError::new(
+                            &format!("{} row ids given for {} rows", ids.len(), count),
+                            None,
+                            Some(at),
+                        ));
+                    }
+                }
+                self.insert_rows(at, count, values)?;
+                self.ids.insert(at, count, ids);
+                Ok(SheetEdit::DeleteRows { at, count })
+            }
+            SheetEdit::DeleteRows { at, count } => {
+                self.check_rows(at, count)?;
+                let mut values = BTreeMap::new();
+                for entry in &mut self.schema {
+                    let column = Arc::make_mut(&mut entry.column);
+                    let removed = (0..count)
+                        .map(|_| column.remove(at))
+                        .collect::<ModelResult<Vec<_>>>()?;
+                    if !column.is_computed() {
+                        values.insert(column.name().to_string(), removed);
+                    }
+                }
+                let ids = self.ids.remove(at, count);
+                Ok(SheetEdit::InsertRows {
+                    at,
+                    count,
+                    values,
+                    ids: Some(ids),
+                })
+            }
+            SheetEdit::MoveRows { from, count, to } => {
+                self.check_rows(from, count)?;
+                self.check_rows(to, count)?;
+                for entry in &mut self.schema {
+                    Arc::make_mut(&mut entry.column).move_rows(from, count, to)?;
+                }
+                self.ids.move_rows(from, count, to);
+                Ok(SheetEdit::MoveRows {
+                    from: to,
+                    count,
+                    to: from,
+                })
+            }
+            SheetEdit::PermuteRows { order } => {
+                if order.len() != self.row_count() {
+                    return Err(ModelError::new(
+                        &format!(
+                            "Row order lists {} rows, sheet has {}",
+                            order.len(),
+                            self.row_count()
+                        ),
+                        None,
+                        None,
+                    ));
+                }
+                // Checked here rather than by the columns, as a sheet keeps
+                // its rows when its last column is dropped
+                let mut seen = vec![false; order.len()];
+                for row in &order {
+                    if seen.get(*row) != Some(&false) {
+                        return Err(ModelError::new(
+                            "Row order is not a permutation of the rows",
+                            None,
+                            Some(*row),
+                        ));
+                    }
+                    seen[*row] = true;
+                }
+                for entry in &mut self.schema {
+                    Arc::make_mut(&mut entry.column).permute(&order)?;
+                }
+                self.ids.permute(&order);
+                let mut inverse = vec![0; order.len()];
+                for (row, from) in order.into_iter().enumerate() {
+                    inverse[from] = row;
+                }
+                Ok(SheetEdit::PermuteRows { order: inverse })
+            }
+            SheetEdit::SetCell { column, row, value } => {
+                let index = self.index_of(&column)?;
+                let previous = Arc::make_mut(&mut self.schema[index].column).set(row, value)?;
+                Ok(SheetEdit::SetCell {
+                    column,
+                    row,
+                    value: previous,
+                })
+            }
+        }
+    }
+
+    fn insert_rows(
+        &mut self,
+        at: usize,
+        count: usize,
+        mut values: BTreeMap<String, Vec<Value>>,
+    ) -> ModelResult<()> {
+        if at > self.row_count() {
+            return Err(ModelError::new(
+                &format!("Rows out of range, sheet has {} rows", self.row_count()),
+                None,
+                Some(at),
+            ));
+        }
+        if let Some(name) = values.keys().find(|name| self.column_index(name).is_none()) {
+            return Err(ModelError::unknown_column(name));
+        }
+        // Check every value first, so a bad one leaves the sheet unchanged
+        for entry in &self.schema {
+            let column = &entry.column;
+            match values.get(column.name()) {
+                Some(new) if new.len() != count => {
+                    return Err(ModelError::length_mismatch(column.name(), count, new.len()))
+                }
+                Some(new) => {
+                    for (offset, value) in new.iter().enumerate() {
+                        column.check(at + offset, value)?;
+                    }
+                }
+                None if count > 0 => column.check(at, &Value::Null)?,
+                None => {}
+            }
+        }
+        for entry in &mut self.schema {
+            let column = Arc::make_mut(&mut entry.column);
+            let new = values
+                .remove(column.name())
+                .unwrap_or_else(|| vec![Value::Null; count]);
+            for (offset, value) in new.into_iter().enumerate() {
+                column.insert(at + offset, value)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::expression::ValueType;
+
+    fn numbers(name: &str, values: &[f64]) -> Arc<Column> {
+        Arc::new(
+            Column::from_values(
+                name,
+                ValueType::Number.nullable(),
+                values.iter().map(|n| Value::Number(*n)),
+            )
+            .unwrap(),
+        )
+    }
+
+    fn add(sheet: &mut Sheet, column: Arc<Column>) -> ModelResult<SheetEdit> {
+        sheet.apply(SheetEdit::AddColumn {
+            index: sheet.column_count(),
+            column,
+            hidden: false,
+        })
+    }
+
+    #[test]
+    fn schema_edits() {
+        let mut sheet = Sheet::new("Orders");
+        add(&mut sheet, numbers("a", &[1.0, 2.0])).unwrap();
+        add(&mut sheet, numbers("b", &[3.0, 4.0])).unwrap();
+        assert_eq!(sheet.row_count(), 2);
+
+        let error = add(&mut sheet, numbers("c", &[1.0])).unwrap_err();
+        assert_eq!(error.column(), Some("c"));
+        assert!(add(&mut sheet, numbers("a", &[1.0, 2.0])).is_err());
+
+        let before = sheet.clone();
+        let undo = sheet
+            .apply(SheetEdit::RenameColumn {
+                from: "a".to_string(),
+                to: "z".to_string(),
+            })
+            .unwrap();
+        let undo_move = sheet
+            .apply(SheetEdit::MoveColumn {
+                name: "z".to_string(),
+                index: 1,
+            })
+            .unwrap();
+        assert_eq!(sheet.column_at(0).unwrap().name(), "b");
+        sheet
+            .apply(SheetEdit::SetHidden {
+                name: "b".to_string(),
+                hidden: true,
+            })
+            .unwrap();
+        assert_eq!(sheet.visible_columns().count(), 1);
+        sheet
+            .apply(SheetEdit::SetHidden {
+                name: "b".to_string(),
+                hidden: false,
+            })
+            .unwrap();
+        sheet.apply(undo_move).unwrap();
+        sheet.apply(undo).unwrap();
+        assert_eq!(sheet, before);
+
+        let undo = sheet
+            .apply(SheetEdit::DropColumn {
+                name: "a".to_string(),
+            })
+            .unwrap();
+        assert!(sheet.column("a").is_none());
+        sheet.apply(undo).unwrap();
+        assert_eq!(sheet, before);
+    }
+
+    #[test]
+    fn row_edits() {
+        let mut sheet = Sheet::new("Orders");
+        add(&mut sheet, numbers("a", &[1.0, 2.0, 3.0])).unwrap();
+        add(&mut sheet, numbers("b", &[4.0, 5.0, 6.0])).unwrap();
+        let before = sheet.clone();
+
+        let values = BTreeMap::from([("a".to_string(), vec![Value::Number(9.0)])]);
+        let undo = sheet
+            .apply(SheetEdit::InsertRows {
+                at: 1,
+                count: 1,
+                values,
+                ids: None,
+            })
+            .unwrap();
+        assert_eq!(sheet.row_count(), 4);
+        assert_eq!(sheet.get("a", 1), Some(Value::Number(9.0)));
+        assert_eq!(sheet.get("b", 1), Some(Value::Null));
+        sheet.apply(undo).unwrap();
+        assert_eq!(sheet, before);
+
+        let undo = sheet
+            .apply(SheetEdit::MoveRows {
+                from: 0,
+                count: 2,
+                to: 1,
+            })
+            .unwrap();
+        assert_eq!(sheet.get("a", 0), Some(Value::Number(3.0)));
+        assert_eq!(sheet.get("b", 2), Some(Value::Number(5.0)));
+        sheet.apply(undo).unwrap();
+        assert_eq!(sheet, before);
+
+        let undo = sheet
+            .apply(SheetEdit::PermuteRows {
+                order: vec![2, 0, 1],
+            })
+            .unwrap();
+        assert_eq!(sheet.get("a", 0), Some(Value::Number(3.0)));
+        assert_eq!(sheet.get("b", 1), Some(Value::Number(4.0)));
+        assert_eq!(sheet.row_id(0), before.row_id(2));
+        assert_eq!(sheet.get_row_by_id(before.row_id(0).unwrap()), Some(1));
+        sheet.apply(undo).unwrap();
+        assert_eq!(sheet, before);
+        for order in [vec![0, 0, 1], vec![0, 1]] {
+            assert!(sheet.apply(SheetEdit::PermuteRows { order }).is_err());
+        }
+        assert_eq!(sheet, before);
+
+        // Rows outlive the columns, so orders are checked without them too
+        let mut empty = sheet.clone();
+        for name in ["a", "b"] {
+            empty
+                .apply(SheetEdit::DropColumn {
+                    name: name.to_string(),
+                })
+                .unwrap();
+        }
+        let rows = empty.clone();
+        for order in [vec![0, 0, 1], vec![0, 1, 3]] {
+            assert!(empty.apply(SheetEdit::PermuteRows { order }).is_err());
+        }
+        assert_eq!(empty, rows);
+
+        let undo = sheet
+            .apply(SheetEdit::DeleteRows { at: 0, count: 2 })
+            .unwrap();
+        assert_eq!(sheet.get("b", 0), Some(Value::Number(6.0)));
+        sheet.apply(undo).unwrap();
+        assert_eq!(sheet, before);
+
+        let error = sheet
+            .apply(SheetEdit::SetCell {
+                column: "b".to_string(),
+                row: 0,
+                value: Value::Boolean(true),
+            })
+            .unwrap_err();
+        assert_eq!(error.column(), Some("b"));
+        let values = BTreeMap::from([("b".to_string(), vec![Value::String("x".to_string())])]);
+        assert!(sheet
+            .apply(SheetEdit::InsertRows {
+                at: 0,
+                count: 1,
+                values,
+                ids: None,
+            })
+            .is_err());
+        assert_eq!(sheet, before);
+    }
+}
@@ -0,0 +1,48 @@
+use std::collections::HashMap;
+
+/// A deduplicated list of strings, referred to by index.
+///
+/// This mirrors the string table of the file format: each distinct string is
+/// stored once, however many times it is used.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StringTable {
+    strings: Vec<String>,
+    indices: HashMap<String, u32>,
+}
+
+impl StringTable {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// The index of `value`, adding it to the table if it is not there yet
+    pub fn intern(&mut self, value: &str) -> u32 {
+        if let Some(index) = self.indices.get(value) {
+            return *index;
+        }
+        let index = self.strings.len() as u32;
+        self.strings.push(value.to_string());
+        self.indices.insert(value.to_string(), index);
+        index
+    }
+
+    pub fn index_of(&self, value: &str) -> Option<u32> {
+        self.indices.get(value).copied()
+    }
+
+    pub fn get(&self, index: u32) -> Option<&str> {
+        self.strings.get(index as usize).map(String::as_str)
+    }
+
+    pub fn len(&self) -> usize {
+        self.strings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.strings.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &str> {
+        self.strings.iter().map(String::as_str)
+    }
+}
@@ -0,0 +1,101 @@
+use super::{ModelError, WorkbookEdit};
+
+/// A list of edits applied to a workbook all at once, or not at all
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Transaction {
+    label: Option<String>,
+    edits: Vec<WorkbookEdit>,
+}
+
+impl Transaction {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// A transaction named in the history, such as "Paste 200 cells"
+    pub fn labelled(label: &str) -> Self {
+        Transaction {
+            label: Some(label.to_string()),
+            edits: Vec::new(),
+        }
+    }
+
+    pub fn label(&self) -> Option<&str> {
+        self.label.as_deref()
+    }
+
+    pub fn push(&mut self, edit: WorkbookEdit) {
+        self.edits.push(edit);
+    }
+
+    pub fn edits(&self) -> &[WorkbookEdit] {
+        &self.edits
+    }
+
+    pub fn len(&self) -> usize {
+        self.edits.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.edits.is_empty()
+    }
+
+    pub(super) fn into_edits(self) -> Vec<WorkbookEdit> {
+        self.edits
+    }
+}
+
+impl FromIterator<WorkbookEdit> for Transaction {
+    fn from_iter<T: IntoIterator<Item = WorkbookEdit>>(iter: T) -> Self {
+        Transaction {
+            label: None,
+            edits: iter.into_iter().collect(),
+        }
+    }
+}
+
+/// An edit in a transaction that failed
+#[derive(Debug, Clone, PartialEq)]
+pub struct TransactionFailure {
+    /// The position of the edit in the transaction
+    pub index: usize,
+    /// The sheet the edit was to, if it was to one
+    pub sheet: Option<String>,
+    pub error: ModelError,
+}
+
+/// Every edit that failed in a transaction that was rolled back
+#[derive(Debug, Clone, PartialEq)]
+pub struct TransactionError {
+    failures: Vec<TransactionFailure>,
+}
+
+impl TransactionError {
+    pub(super) fn new(failures: Vec<TransactionFailure>) -> Self {
+        TransactionError { failures }
+    }
+
+    pub fn failures(&self) -> &[TransactionFailure] {
+        &self.failures
+    }
+}
+
+impl std::error::Error for TransactionError {}
+
+impl std::fmt::Display for TransactionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Transaction rolled back, {} edit(s) failed",
+            self.failures.len()
+        )?;
+        for failure in &self.failures {
+            write!(f, "\n  #{}", failure.index)?;
+            if let Some(sheet) = &failure.sheet {
+                write!(f, " {}", sheet)?;
+            }
+            write!(f, ": {}", failure.error.to_string().trim_start())?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,339 @@
+use std::{collections::BTreeMap, ops::Range};
+
+use regex::Regex;
+
+use super::{retype::ValueContext, Column, ModelError, ModelResult, RowId, SheetEdit, Workbook};
+use crate::expression::{ErrorKind, Expression, ExpressionError, Value, ValueType};
+
+/// What happens to an edit that enters values breaking a column's rules
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValidationMode {
+    /// The edit fails
+    Reject,
+    /// The values are kept, and show up in the validation report
+    Warn,
+    /// The values are kept out of the column, each replaced by an `#INVALID`
+    /// error saying what it was and which rule it broke
+    Quarantine,
+}
+
+/// A regular expression that has to match a whole string
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    source: String,
+    regex: Regex,
+}
+
+impl Pattern {
+    pub fn new(source: &str) -> ModelResult<Self> {
+        let regex = Regex::new(&format!("^(?:{})$", source)).map_err(|error| {
+            ModelError::new(
+                &format!("Invalid pattern {:?}: {}", source, error),
+                None,
+                None,
+            )
+        })?;
+        Ok(Pattern {
+            source: source.to_string(),
+            regex,
+        })
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    pub fn is_match(&self, text: &str) -> bool {
+        self.regex.is_match(text)
+    }
+}
+
+impl PartialEq for Pattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.source == other.source
+    }
+}
+
+/// A check the values of a column have to pass. Nulls pass every rule.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Rule {
+    /// A Boolean formula over the value, as `x`, and the rest of its row.
+    /// Values it is false for break it, and so do values it fails on. A null
+    /// result passes, as it does for SQL checks.
+    Formula(Expression),
+    /// No two rows hold the same value
+    Unique,
+    /// The value is one of these. Categorical values are compared by label.
+    Allowed(Vec<Value>),
+    /// Strings, or the labels of categorical values, match the pattern
+    Pattern(Pattern),
+}
+
+impl std::fmt::Display for Rule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Rule::Formula(formula) => write!(f, "{}", formula),
+            Rule::Unique => write!(f, "unique"),
+            Rule::Allowed(values) => write!(f, "one of {}", Value::Array(values.clone())),
+            Rule::Pattern(pattern) => write!(f, "matching {:?}", pattern.source()),
+        }
+    }
+}
+
+/// The rules of a column, and what happens to values that break them
+#[derive(Debug, Clone, PartialEq)]
+pub struct Validation {
+    pub rules: Vec<Rule>,
+    pub mode: ValidationMode,
+}
+
+impl Validation {
+    pub fn new(rules: Vec<Rule>, mode: ValidationMode) -> Self {
+        Validation { rules, mode }
+    }
+}
+
+/// A row holding a value that breaks its column's rules, or that was
+/// quarantined for it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Violation {
+    pub column: String,
+    pub row: usize,
+    pub id: RowId,
+    pub value: Value,
+    pub reason: String,
+}
+
+/// Every row of a sheet that breaks the rules of one of its columns, by
+/// column and then by row
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ValidationReport {
+    pub violations: Vec<Violation>,
+}
+
+impl ValidationReport {
+    pub fn is_empty(&self) -> bool {
+        self.violations.is_empty()
+    }
+}
+
+/// Checks that rules fit a column of a sheet: formulas type check as
+/// Booleans, allowed values have the column's type and patterns are only
+/// used on text
+pub(super) fn check(
+    workbook: &Workbook,
+    sheet: &str,
+    column: &Column,
+    validation: &Validation,
+) -> ModelResult<()> {
+    if column.is_computed() {
+        return Err(ModelError::new(
+            "Column is computed, validate the columns it uses instead",
+            Some(column.name()),
+            None,
+        ));
+    }
+    let base = column.value_type().base();
+    let is_text = matches!(base, ValueType::String | ValueType::Categorical(_));
+    for rule in &validation.rules {
+        match rule {
+            Rule::Formula(formula) => {
+                let context = ValueContext {
+                    sheet: workbook.context(sheet, Some(0)).unwrap(),
+                    value_type: column.value_type(),
+                    value: Value::Null,
+                };
+                let result = formula
+                    .type_check(&context)
+                    .map_err(|error| ModelError::expression(&error, Some(column.name())))?;
+                if !matches!(result.base(), ValueType::Boolean | ValueType::Null) {
+                    return Err(ModelError::new(
+                        &format!("Rule {} gives {}, rules must give Boolean", formula, result),
+                        Some(column.name()),
+                        None,
+                    ));
+                }
+            }
+            Rule::Allowed(values) => {
+                for value in values {
+                    let fits = match (base, value) {
+                        (_, Value::Null) => true,
+                        (ValueType::Categorical(categories), Value::String(label)) => {
+                            categories.code(label).is_some()
+                        }
+                        (base, value) => value.value_type() == *base,
+                    };
+                    if !fits {
+                        return Err(ModelError::new(
+                            &format!("Allowed value {} cannot be stored in {}", value, base),
+                            Some(column.name()),
+                            None,
+                        ));
+                    }
+                }
+            }
+            Rule::Pattern(pattern) if !is_text => {
+                return Err(ModelError::new(
+                    &format!("Pattern {:?} needs a String column", pattern.source()),
+                    Some(column.name()),
+                    None,
+                ))
+            }
+            Rule::Unique | Rule::Pattern(_) => {}
+        }
+    }
+    Ok(())
+}
+
+/// The values in `rows` of a column that break its rules. Nulls and errors
+/// are not checked. The columns formulas use must be evaluated already.
+pub(super) fn violations(
+    workbook: &Workbook,
+    sheet: &str,
+    column: &Column,
+    rows: Range<usize>,
+) -> Vec<Violation> {
+    let Some(validation) = column.validation() else {
+        return Vec::new();
+    };
+    let ids = workbook.sheet(sheet).unwrap().row_ids();
+    // Values are compared by how they print, which is exact for every type
+    // a column can hold
+    let counts = if validation.rules.contains(&Rule::Unique) {
+        let mut counts = BTreeMap::new();
+        for value in column.iter().filter(is_checked) {
+            *counts.entry(value.to_string()).or_insert(0) += 1;
+        }
+        counts
+    } else {
+        BTreeMap::new()
+    };
+    let mut violations = Vec::new();
+    for row in rows.start..rows.end.min(column.len()) {
+        let value = column.get(row).unwrap();
+        if !is_checked(&value) {
+            continue;
+        }
+        let reason = validation.rules.iter().find_map(|rule| match rule {
+            Rule::Formula(formula) => {
+                let context = ValueContext {
+                    sheet: workbook.context(sheet, Some(row)).unwrap(),
+                    value_type: column.value_type(),
+                    value: value.clone(),
+                };
+                match formula.eval(&context).map_err(Box::new) {
+                    Ok(Value::Boolean(false)) => Some(format!("{} fails {}", value, formula)),
+                    Ok(Value::Error(_, error)) | Err(error) => Some(format!(
+                        "{} could not be checked with {}: {}",
+                        value,
+                        formula,
+                        error.to_string().trim_start()
+                    )),
+                    Ok(_) => None,
+                }
+            }
+            Rule::Unique => {
+                (counts[&value.to_string()] > 1).then(|| format!("{} is not unique", value))
+            }
+            Rule::Allowed(allowed) => (!allowed.iter().any(|allowed| match (&value, allowed) {
+                (Value::Categorical(category), Value::String(label)) => category.label() == label,
+                (value, allowed) => value == allowed,
+            }))
+            .then(|| format!("{} is not {}", value, rule)),
+            Rule::Pattern(pattern) => {
+                let text = match &value {
+                    Value::String(text) => text.as_str(),
+                    Value::Categorical(category) => category.label(),
+                    _ => return None,
+                };
+                (!pattern.is_match(text)).then(|| format!("{} is not {}", value, rule))
+            }
+        });
+        if let Some(reason) = reason {
+            violations.push(Violation {
+                column: column.name().to_string(),
+                row,
+                id: ids.get(row).unwrap(),
+                value,
+                reason,
+            });
+        }
+    }
+    violations
+}
+
+fn is_checked(value: &Value) -> bool {
+    !value.is_null() && !value.is_error()
+}
+
+/// The rows of a column holding values quarantined by its rules
+pub(super) fn quarantined(workbook: &Workbook, sheet: &str, column: &Column) -> Vec<Violation> {
+    let ids = workbook.sheet(sheet).unwrap().row_ids();
+    column
+        .iter()
+        .enumerate()
+        .filter_map(|(row, value)| match &value {
+            Value::Error(ErrorKind::Invalid, error) => Some(Violation {
+                column: column.name().to_string(),
+                row,
+                id: ids.get(row).unwrap(),
+                reason: error.to_string().trim_start().to_string(),
+                value,
+            }),
+            _ => None,
+        })
+        .collect()
+}
+
+/// The rows of each column a sheet edit enters values into, which are the
+/// ones to check against the column's rules
+pub(super) fn entered(edit: &SheetEdit) -> Vec<(String, Range<usize>)> {
+    match edit {
+        SheetEdit::SetCell { column, row, .. } => vec![(column.clone(), *row..*row + 1)],
+        SheetEdit::InsertRows {
+            at,
+            count,
+            values,
+            ids: None,
+        } => values
+            .keys()
+            .map(|column| (column.clone(), *at..at + count))
+            .collect(),
+        // Rows given ids are being put back, and were checked when entered
+        _ => Vec::new(),
+    }
+}
+
+/// The edit with every value in `violations` replaced by an `#INVALID`
+/// error holding the reason it was quarantined
+pub(super) fn quarantine(edit: SheetEdit, violations: &[Violation]) -> SheetEdit {
+    let invalid = |violation: &Violation| Value::error(ExpressionError::invalid(&violation.reason));
+    match edit {
+        SheetEdit::SetCell { column, row, value } => {
+            let value = violations
+                .iter()
+                .find(|violation| violation.column == column && violation.row == row)
+                .map_or(value, invalid);
+            SheetEdit::SetCell { column, row, value }
+        }
+        SheetEdit::InsertRows {
+            at,
+            count,
+            mut values,
+            ids,
+        } => {
+            for violation in violations {
+                if let Some(column) = values.get_mut(&violation.column) {
+                    column[violation.row - at] = invalid(violation);
+                }
+            }
+            SheetEdit::InsertRows {
+                at,
+                count,
+                values,
+                ids,
+            }
+        }
+        edit => edit,
+    }
+}
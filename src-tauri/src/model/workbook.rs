@@ -0,0 +1,2589 @@
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, BTreeSet},
+    ops::Range,
+    sync::Arc,
+};
+
+use futures::{executor::ThreadPool, future::join_all, task::SpawnExt};
+
+use super::{
+    format::{self, CellStyle, Format, StyleCache},
+    graph::{DependencyGraph, NodeId},
+    recalc::{self, CancelToken, Recalculation, CHUNK_ROWS},
+    retype::{self, ConversionFailure, ConversionReport},
+    transaction::{Transaction, TransactionError, TransactionFailure},
+    validation::{self, Rule, Validation, ValidationMode, ValidationReport},
+    workspace::{EntryDefinition, Workspace},
+    Column, ModelError, ModelResult, RowId, Sheet, SheetEdit,
+};
+use crate::expression::{ColumnRef, ColumnSpec, ColumnView, Context, Expression, Value, ValueType};
+
+/// A single change to a workbook, either to its list of sheets or to one
+/// of the sheets in it
+#[derive(Debug, Clone, PartialEq)]
+pub enum WorkbookEdit {
+    AddSheet {
+        index: usize,
+        sheet: Sheet,
+    },
+    DropSheet {
+        name: String,
+    },
+    RenameSheet {
+        from: String,
+        to: String,
+    },
+    Sheet {
+        name: String,
+        edit: SheetEdit,
+    },
+    /// Makes a column computed by `formula`, adding it to the end of the
+    /// sheet if it does not exist. A `None` formula turns a computed column
+    /// into a static one holding its current values.
+    SetFormula {
+        sheet: String,
+        column: String,
+        formula: Option<Expression>,
+    },
+    /// Converts a static column to `value_type`, with a `conversion`
+    /// formula where `x` is the old value of each row
+    RetypeColumn {
+        sheet: String,
+        column: String,
+        value_type: ValueType,
+        conversion: Expression,
+        on_failure: ConversionFailure,
+    },
+    /// Sets the validation rules of a static column, or removes them if
+    /// `validation` is `None`. Values already in the column are not checked.
+    SetValidation {
+        sheet: String,
+        column: String,
+        validation: Option<Validation>,
+    },
+    /// Sets the conditional formats of a column, replacing any it had
+    SetFormats {
+        sheet: String,
+        column: String,
+        formats: Vec<Format>,
+    },
+    /// Sets a workspace entry, or removes it if `definition` is `None`
+    SetEntry {
+        name: String,
+        definition: Option<EntryDefinition>,
+    },
+}
+
+/// The rows of a column an edit changed
+#[derive(Debug, Clone, PartialEq)]
+enum Rows {
+    /// Only these rows changed. An empty set means the values are the same
+    /// but some were removed or moved, which still changes aggregates.
+    Some(BTreeSet<usize>),
+    /// Every row from this one on
+    From(usize),
+    All,
+}
+
+impl Rows {
+    fn merge(self, other: Rows) -> Rows {
+        match (self, other) {
+            (Rows::All, _) | (_, Rows::All) => Rows::All,
+            (Rows::Some(mut a), Rows::Some(b)) => {
+                a.extend(b);
+                Rows::Some(a)
+            }
+            (Rows::From(a), Rows::From(b)) => Rows::From(a.min(b)),
+            (Rows::From(a), Rows::Some(rows)) | (Rows::Some(rows), Rows::From(a)) => {
+                Rows::From(rows.iter().next().map_or(a, |first| a.min(*first)))
+            }
+        }
+    }
+
+    /// Marks these rows of a computed column as out of date
+    fn invalidate(&self, column: &mut Column) {
+        match self {
+            Rows::Some(rows) => {
+                for row in rows {
+                    column.invalidate_rows(*row..*row + 1);
+                }
+            }
+            Rows::From(row) => column.invalidate_rows(*row..usize::MAX),
+            Rows::All => column.invalidate(),
+        }
+    }
+
+    /// Marks the styles of these rows of a column as out of date
+    fn invalidate_styles(&self, styles: &mut StyleCache) {
+        match self {
+            Rows::Some(rows) => {
+                for row in rows {
+                    styles.invalidate_rows(*row..*row + 1);
+                }
+            }
+            Rows::From(row) => styles.invalidate_rows(*row..usize::MAX),
+            Rows::All => styles.clear(),
+        }
+    }
+}
+
+/// What an edit changed directly, before following dependencies. `None`
+/// stands for edits that may change what references resolve to, after
+/// which everything is out of date.
+#[derive(Debug, Default)]
+struct Changes {
+    columns: BTreeMap<NodeId, Rows>,
+    entries: BTreeSet<String>,
+    /// The first row of each sheet whose rows were inserted, deleted or
+    /// moved. Rows from there on no longer line up with other sheets.
+    shifted: BTreeMap<String, usize>,
+}
+
+/// An ordered set of named sheets, and the workspace they share
+#[derive(Debug, Clone, Default)]
+pub struct Workbook {
+    sheets: Vec<Sheet>,
+    workspace: Workspace,
+    graph: DependencyGraph,
+    generation: u64,
+    /// The token of the last recalculation started
+    running: Option<CancelToken>,
+}
+
+impl Workbook {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn sheets(&self) -> impl Iterator<Item = &Sheet> {
+        self.sheets.iter()
+    }
+
+    pub fn sheet(&self, name: &str) -> Option<&Sheet> {
+        self.sheets.iter().find(|sheet| sheet.name() == name)
+    }
+
+    pub fn workspace(&self) -> &Workspace {
+        &self.workspace
+    }
+
+    /// Which columns and workspace entries use which
+    pub fn graph(&self) -> &DependencyGraph {
+        &self.graph
+    }
+
+    /// The number of edits applied so far, so work started from an older
+    /// version of the workbook can be recognised
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    fn index_of(&self, name: &str) -> ModelResult<usize> {
+        self.sheets
+            .iter()
+            .position(|sheet| sheet.name() == name)
+            .ok_or_else(|| ModelError::new(&format!("No sheet named {:?}", name), None, None))
+    }
+
+    fn check_name(&self, name: &str) -> ModelResult<()> {
+        if name.is_empty() {
+            Err(ModelError::new("Sheet names cannot be empty", None, None))
+        } else if self.sheet(name).is_some() {
+            Err(ModelError::new(
+                &format!("A sheet named {:?} already exists", name),
+                None,
+                None,
+            ))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Applies an edit, returning the edit that reverts it. Failed edits
+    /// leave the workbook unchanged.
+    ///
+    /// Computed values that use what an edit changed are out of date
+    /// afterwards, down to the rows they use. Computed columns are evaluated
+    /// again when read, workspace entries are evaluated straight away.
+    ///
+    /// Edits that leave columns or entries depending on themselves, such as
+    /// renaming a column to a name its own formula uses, are reverted.
+    /// Successful edits cancel any recalculation running in the background.
+    ///
+    /// Values entered into a column with validation rules are checked once
+    /// they are in place, and handled as the column's mode says.
+    pub fn apply(&mut self, edit: WorkbookEdit) -> ModelResult<WorkbookEdit> {
+        let (inverse, entries) = self.apply_unevaluated(edit, true)?;
+        self.evaluate_workspace(&entries);
+        Ok(inverse)
+    }
+
+    /// Applies an edit that puts back an earlier state of the workbook, such
+    /// as an undo, without checking validation rules again
+    pub fn restore(&mut self, edit: WorkbookEdit) -> ModelResult<WorkbookEdit> {
+        let (inverse, entries) = self.apply_unevaluated(edit, false)?;
+        self.evaluate_workspace(&entries);
+        Ok(inverse)
+    }
+
+    /// Applies every edit in a transaction, or none of them.
+    ///
+    /// Each edit is checked as it is applied, as by `apply`. If any fail the
+    /// rest are still tried, so the error reports every failing edit, then
+    /// the ones that succeeded are reverted. The workspace is evaluated once
+    /// at the end rather than after every edit. Returns the edits that revert
+    /// the transaction, in the order they were applied.
+    pub fn commit(
+        &mut self,
+        transaction: Transaction,
+    ) -> Result<Vec<WorkbookEdit>, TransactionError> {
+        let mut inverses = Vec::with_capacity(transaction.len());
+        let mut entries = BTreeSet::new();
+        let mut failures = Vec::new();
+        for (index, edit) in transaction.into_edits().into_iter().enumerate() {
+            let sheet = match &edit {
+                WorkbookEdit::AddSheet { sheet, .. } => Some(sheet.name().to_string()),
+                WorkbookEdit::DropSheet { name } | WorkbookEdit::Sheet { name, .. } => {
+                    Some(name.clone())
+                }
+                WorkbookEdit::RenameSheet { from, .. } => Some(from.clone()),
+                WorkbookEdit::SetFormula { sheet, .. }
+                | WorkbookEdit::RetypeColumn { sheet, .. }
+                | WorkbookEdit::SetValidation { sheet, .. }
+                | WorkbookEdit::SetFormats { sheet, .. } => Some(sheet.clone()),
+                WorkbookEdit::SetEntry { .. } => None,
+            };
+            match self.apply_unevaluated(edit, true) {
+                Ok((inverse, changed)) => {
+                    inverses.push(inverse);
+                    entries.extend(changed);
+                }
+                Err(error) => failures.push(TransactionFailure {
+                    index,
+                    sheet,
+                    error,
+                }),
+            }
+        }
+        if failures.is_empty() {
+            self.evaluate_workspace(&entries);
+            return Ok(inverses);
+        }
+        for inverse in inverses.into_iter().rev() {
+            let (_, changed) = self
+                .apply_unevaluated(inverse, false)
+                .expect("inverse edits always apply");
+            entries.extend(changed);
+        }
+        self.evaluate_workspace(&entries);
+        Err(TransactionError::new(failures))
+    }
+
+    /// Applies an edit without evaluating the workspace, returning the edit
+    /// that reverts it and the workspace entries that are out of date.
+    /// `validate` says whether to check the values it enters.
+    fn apply_unevaluated(
+        &mut self,
+        edit: WorkbookEdit,
+        validate: bool,
+    ) -> ModelResult<(WorkbookEdit, BTreeSet<String>)> {
+        let changes = self.changes(&edit);
+        let entered = match &edit {
+            WorkbookEdit::Sheet { name, edit } if validate => {
+                let cells = validation::entered(edit);
+                (!cells.is_empty()).then(|| (name.clone(), edit.clone(), cells))
+            }
+            _ => None,
+        };
+        let inverse = self.apply_edit(edit)?;
+        let graph = self.build_graph(&self.workspace);
+        if let Err(error) = graph.order() {
+            self.apply_edit(inverse)
+                .expect("inverse edits always apply");
+            return Err(error);
+        }
+        self.graph = graph;
+        self.generation += 1;
+        if let Some(token) = self.running.take() {
+            token.cancel();
+        }
+        let entries = self.invalidate(changes);
+        match entered {
+            Some((sheet, edit, cells)) => {
+                self.enforce_validation(&sheet, edit, &cells, inverse, entries)
+            }
+            None => Ok((inverse, entries)),
+        }
+    }
+
+    /// Checks the values a sheet edit entered against their columns' rules,
+    /// once the edit is applied. Any broken rule of a rejecting column
+    /// reverts the edit. Otherwise, if a quarantining column's rules were
+    /// broken, the edit is reverted and applied again with those values
+    /// replaced.
+    fn enforce_validation(
+        &mut self,
+        sheet: &str,
+        edit: SheetEdit,
+        cells: &[(String, Range<usize>)],
+        inverse: WorkbookEdit,
+        entries: BTreeSet<String>,
+    ) -> ModelResult<(WorkbookEdit, BTreeSet<String>)> {
+        let mut violations = Vec::new();
+        for (column, rows) in cells {
+            let Some(found) = self.sheet(sheet).and_then(|found| found.column(column)) else {
+                continue;
+            };
+            let Some(rules) = found.validation().cloned() else {
+                continue;
+            };
+            self.materialise_rules(sheet, &rules);
+            let found = self.sheet(sheet).unwrap().column(column).unwrap();
+            let broken = validation::violations(self, sheet, found, rows.clone());
+            violations.extend(broken.into_iter().map(|violation| (rules.mode, violation)));
+        }
+        if let Some((_, rejected)) = violations
+            .iter()
+            .find(|(mode, _)| *mode == ValidationMode::Reject)
+        {
+            let error =
+                ModelError::new(&rejected.reason, Some(&rejected.column), Some(rejected.row));
+            self.apply_unevaluated(inverse, false)
+                .expect("inverse edits always apply");
+            return Err(error);
+        }
+        let quarantined = violations
+            .into_iter()
+            .filter(|(mode, _)| *mode == ValidationMode::Quarantine)
+            .map(|(_, violation)| violation)
+            .collect::<Vec<_>>();
+        if quarantined.is_empty() {
+            return Ok((inverse, entries));
+        }
+        let (_, mut reverted) = self
+            .apply_unevaluated(inverse, false)
+            .expect("inverse edits always apply");
+        let edit = WorkbookEdit::Sheet {
+            name: sheet.to_string(),
+            edit: validation::quarantine(edit, &quarantined),
+        };
+        let (inverse, changed) = self.apply_unevaluated(edit, true)?;
+        reverted.extend(changed);
+        reverted.extend(entries);
+        Ok((inverse, reverted))
+    }
+
+    /// What an edit will change, worked out before it is applied
+    fn changes(&self, edit: &WorkbookEdit) -> Option<Changes> {
+        let mut changes = Changes::default();
+        match edit {
+            WorkbookEdit::Sheet { name, edit } => {
+                let sheet = self.sheet(name)?;
+                let (rows, shifted) = match edit {
+                    SheetEdit::SetCell { column, row, .. } => {
+                        changes
+                            .columns
+                            .insert(NodeId::column(name, column), Rows::Some([*row].into()));
+                        return Some(changes);
+                    }
+                    SheetEdit::SetHidden { .. }
+                    | SheetEdit::SetValidation { .. }
+                    | SheetEdit::SetFormats { .. } => return Some(changes),
+                    SheetEdit::InsertRows { at, count, .. } => {
+                        (Rows::Some((*at..at + count).collect()), *at)
+                    }
+                    SheetEdit::DeleteRows { at, .. } => (Rows::Some(BTreeSet::new()), *at),
+                    SheetEdit::MoveRows { from, to, .. } => {
+                        (Rows::Some(BTreeSet::new()), *from.min(to))
+                    }
+                    SheetEdit::PermuteRows { order } => {
+                        let Some(first) = order
+                            .iter()
+                            .enumerate()
+                            .position(|(row, from)| row != *from)
+                        else {
+                            return Some(changes);
+                        };
+                        (Rows::Some(BTreeSet::new()), first)
+                    }
+                    _ => return None,
+                };
+                for column in sheet.columns() {
+                    changes
+                        .columns
+                        .insert(NodeId::column(name, column.name()), rows.clone());
+                }
+                changes.shifted.insert(name.clone(), shifted);
+            }
+            WorkbookEdit::SetFormula { sheet, column, .. } => {
+                changes
+                    .columns
+                    .insert(NodeId::column(sheet, column), Rows::All);
+            }
+            WorkbookEdit::SetEntry { name, .. } => {
+                changes.entries.insert(name.clone());
+            }
+            WorkbookEdit::SetValidation { .. } | WorkbookEdit::SetFormats { .. } => {}
+            _ => return None,
+        }
+        Some(changes)
+    }
+
+    /// Marks out of date every computed row that uses what changed, directly
+    /// or through other computed columns, and returns the workspace entries
+    /// that need evaluating again.
+    ///
+    /// Rows of a column that uses another row by row only depend on the same
+    /// rows of it. A column that uses another whole, in an aggregate, or that
+    /// uses a changed workspace entry is out of date in every row.
+    fn invalidate(&mut self, changes: Option<Changes>) -> BTreeSet<String> {
+        let Some(mut changes) = changes else {
+            for sheet in &mut self.sheets {
+                sheet.invalidate();
+            }
+            return self
+                .workspace
+                .entries()
+                .map(|(name, _)| name.to_string())
+                .collect();
+        };
+        // The graph is checked for cycles whenever it changes
+        for node in self.graph.order().unwrap_or_default() {
+            match &node {
+                NodeId::Column { sheet, column } => {
+                    let Some(formula) = self
+                        .sheet(sheet)
+                        .and_then(|found| found.column(column))
+                        .and_then(|found| found.formula())
+                    else {
+                        continue;
+                    };
+                    let dirty = changes.columns.get(&node).cloned();
+                    let Some(dirty) = self.dirty_rows(sheet, formula, dirty, &changes) else {
+                        continue;
+                    };
+                    let index = self.index_of(sheet).unwrap();
+                    let target = self.sheets[index].column_mut(column).unwrap();
+                    dirty.invalidate(target);
+                    changes.columns.insert(node, dirty);
+                }
+                NodeId::Entry(name) => {
+                    let changed =
+                        self.graph
+                            .precedents(&node)
+                            .iter()
+                            .any(|precedent| match precedent {
+                                NodeId::Column { .. } => changes.columns.contains_key(precedent),
+                                NodeId::Entry(entry) => changes.entries.contains(entry),
+                            });
+                    if changed {
+                        changes.entries.insert(name.clone());
+                    }
+                }
+            }
+        }
+        self.invalidate_styles(&changes);
+        changes.entries
+    }
+
+    /// The rows of an expression on `sheet` that are out of date after
+    /// `changes`, adding to `dirty`, the rows that already are
+    fn dirty_rows(
+        &self,
+        sheet: &str,
+        expression: &Expression,
+        mut dirty: Option<Rows>,
+        changes: &Changes,
+    ) -> Option<Rows> {
+        for (reference, whole) in expression.column_uses() {
+            let Some((input_sheet, input)) = self.resolve(Some(sheet), reference) else {
+                continue;
+            };
+            let input_node = NodeId::column(input_sheet.name(), input.name());
+            let Some(rows) = changes.columns.get(&input_node) else {
+                continue;
+            };
+            let mut rows = if whole { Rows::All } else { rows.clone() };
+            if input_sheet.name() != sheet {
+                if let Some(at) = changes.shifted.get(input_sheet.name()) {
+                    rows = rows.merge(Rows::From(*at));
+                }
+            }
+            dirty = Some(match dirty {
+                Some(dirty) => dirty.merge(rows),
+                None => rows,
+            });
+        }
+        if expression
+            .variables()
+            .iter()
+            .any(|name| changes.entries.contains(*name))
+        {
+            dirty = Some(Rows::All);
+        }
+        dirty
+    }
+
+    /// Marks out of date the styles of every formatted row whose value, or
+    /// anything its formats use, changed. Nothing depends on styles, so this
+    /// runs once every computed column is marked. A change to any row of a
+    /// column with a scale, bar or icon set can move the range its values
+    /// are placed in, which changes every row.
+    fn invalidate_styles(&mut self, changes: &Changes) {
+        for index in 0..self.sheets.len() {
+            let sheet = self.sheets[index].name();
+            let mut marked = Vec::new();
+            for column in self.sheets[index].columns() {
+                if column.formats().is_empty() {
+                    continue;
+                }
+                let mut dirty = changes
+                    .columns
+                    .get(&NodeId::column(sheet, column.name()))
+                    .cloned();
+                if let Some(at) = changes.shifted.get(sheet) {
+                    dirty =
+                        Some(dirty.map_or(Rows::From(*at), |dirty| dirty.merge(Rows::From(*at))));
+                }
+                for format in column.formats() {
+                    dirty = self.dirty_rows(sheet, format.expression(), dirty, changes);
+                }
+                if let Some(dirty) = dirty {
+                    if column.formats().iter().any(Format::is_ranged) {
+                        marked.push((column.name().to_string(), Rows::All));
+                    } else {
+                        marked.push((column.name().to_string(), dirty));
+                    }
+                }
+            }
+            for (column, dirty) in marked {
+                let target = self.sheets[index].column_mut(&column).unwrap();
+                dirty.invalidate_styles(target.styles_mut());
+            }
+        }
+    }
+
+    fn apply_edit(&mut self, edit: WorkbookEdit) -> ModelResult<WorkbookEdit> {
+        match edit {
+            WorkbookEdit::AddSheet { index, sheet } => {
+                self.check_name(sheet.name())?;
+                if index > self.sheets.len() {
+                    return Err(ModelError::new(
+                        &format!("Index {} is past the last sheet", index),
+                        None,
+                        None,
+                    ));
+                }
+                let name = sheet.name().to_string();
+                self.sheets.insert(index, sheet);
+                Ok(WorkbookEdit::DropSheet { name })
+            }
+            WorkbookEdit::DropSheet { name } => {
+                let index = self.index_of(&name)?;
+                let sheet = self.sheets.remove(index);
+                Ok(WorkbookEdit::AddSheet { index, sheet })
+            }
+            WorkbookEdit::RenameSheet { from, to } => {
+                let index = self.index_of(&from)?;
+                if from != to {
+                    self.check_name(&to)?;
+                }
+                self.sheets[index].rename(&to);
+                for expression in self.workspace.expressions_mut() {
+                    expression.rename_sheet(&from, &to);
+                }
+                for sheet in &mut self.sheets {
+                    sheet.rename_sheet_references(&from, &to);
+                }
+                Ok(WorkbookEdit::RenameSheet { from: to, to: from })
+            }
+            WorkbookEdit::Sheet { name, edit } => {
+                let index = self.index_of(&name)?;
+                let inverse = self.sheets[index].apply(edit)?;
+                Ok(WorkbookEdit::Sheet {
+                    name,
+                    edit: inverse,
+                })
+            }
+            WorkbookEdit::SetFormula {
+                sheet,
+                column,
+                formula,
+            } => self.set_formula(&sheet, &column, formula),
+            WorkbookEdit::RetypeColumn {
+                sheet,
+                column,
+                value_type,
+                conversion,
+                on_failure,
+            } => self.retype_column(&sheet, &column, value_type, &conversion, on_failure),
+            WorkbookEdit::SetValidation {
+                sheet,
+                column,
+                validation,
+            } => self.set_validation(&sheet, &column, validation),
+            WorkbookEdit::SetFormats {
+                sheet,
+                column,
+                formats,
+            } => self.set_formats(&sheet, &column, formats),
+            WorkbookEdit::SetEntry { name, definition } => {
+                let previous = self.set_entry(&name, definition)?;
+                Ok(WorkbookEdit::SetEntry {
+                    name,
+                    definition: previous,
+                })
+            }
+        }
+    }
+
+    fn set_formula(
+        &mut self,
+        sheet: &str,
+        column: &str,
+        formula: Option<Expression>,
+    ) -> ModelResult<WorkbookEdit> {
+        let index = self.index_of(sheet)?;
+        let existing = self.sheets[index].column(column).cloned();
+        // Rules check entered values, which computed columns have none of
+        let validated = existing.as_ref().and_then(|existing| existing.validation());
+        if formula.is_some() && validated.is_some() {
+            return Err(ModelError::new(
+                "Column has validation rules, remove them before giving it a formula",
+                Some(column),
+                None,
+            ));
+        }
+        let mut replacement = match (formula, &existing) {
+            (Some(formula), _) => {
+                let mut graph = self.graph.clone();
+                graph.set_precedents(
+                    NodeId::column(sheet, column),
+                    self.precedents_of(Some(sheet), &formula, &self.workspace),
+                );
+                graph.order()?;
+                let context = self.context(sheet, Some(0)).unwrap();
+                let value_type = formula
+                    .type_check(&context)
+                    .map_err(|error| ModelError::expression(&error, Some(column)))?;
+                let rows = self.sheets[index].row_count();
+                Column::computed(column, value_type, formula, rows)?
+            }
+            (None, Some(existing)) if existing.is_computed() => {
+                self.materialise(sheet, column, 0..existing.len())?;
+                self.sheets[index].column(column).unwrap().to_static()
+            }
+            (None, _) => {
+                return Err(ModelError::new(
+                    "Column is not computed",
+                    Some(column),
+                    None,
+                ))
+            }
+        };
+        // Formats stay with the column, whatever computes its values
+        if let Some(existing) = &existing {
+            replacement.set_formats(existing.formats().to_vec());
+        }
+        let edit = match existing {
+            Some(_) => SheetEdit::ReplaceColumn {
+                name: column.to_string(),
+                column: Arc::new(replacement),
+            },
+            None => SheetEdit::AddColumn {
+                index: self.sheets[index].column_count(),
+                column: Arc::new(replacement),
+                hidden: false,
+            },
+        };
+        let inverse = self.sheets[index].apply(edit)?;
+        Ok(WorkbookEdit::Sheet {
+            name: sheet.to_string(),
+            edit: inverse,
+        })
+    }
+
+    /// The columns and workspace entries an expression uses, from `sheet`
+    /// or from the workspace if `sheet` is `None`. References that resolve to
+    /// nothing are left out.
+    fn precedents_of(
+        &self,
+        sheet: Option<&str>,
+        expression: &Expression,
+        workspace: &Workspace,
+    ) -> BTreeSet<NodeId> {
+        let columns = expression
+            .columns()
+            .into_iter()
+            .filter_map(|reference| self.resolve(sheet, reference))
+            .map(|(sheet, column)| NodeId::column(sheet.name(), column.name()));
+        let entries = expression
+            .variables()
+            .into_iter()
+            .filter(|name| workspace.get(name).is_some())
+            .map(NodeId::entry);
+        columns.chain(entries).collect()
+    }
+
+    /// The dependency graph of every column in the workbook and every entry
+    /// in `workspace`
+    fn build_graph(&self, workspace: &Workspace) -> DependencyGraph {
+        let mut graph = DependencyGraph::new();
+        for sheet in &self.sheets {
+            for column in sheet.columns() {
+                let precedents = match column.formula() {
+                    Some(formula) => self.precedents_of(Some(sheet.name()), formula, workspace),
+                    None => BTreeSet::new(),
+                };
+                graph.set_precedents(NodeId::column(sheet.name(), column.name()), precedents);
+            }
+        }
+        for (name, entry) in workspace.entries() {
+            let precedents = match entry.definition() {
+                EntryDefinition::Expression(expression) => {
+                    self.precedents_of(None, expression, workspace)
+                }
+                EntryDefinition::Value(_) => BTreeSet::new(),
+            };
+            graph.set_precedents(NodeId::entry(name), precedents);
+        }
+        graph
+    }
+
+    /// Finds the column a reference points to, from an expression on `sheet`
+    /// or in the workspace if `sheet` is `None`
+    fn resolve(
+        &self,
+        sheet: Option<&str>,
+        reference: &ColumnRef,
+    ) -> Option<(&Sheet, &Arc<Column>)> {
+        let sheet = match (reference.sheet.as_deref(), sheet) {
+            (Some(name), _) | (None, Some(name)) => self.sheet(name)?,
+            (None, None) => {
+                let mut sheets = self.sheets().filter(|sheet| match &reference.column {
+                    ColumnSpec::Name(name) => sheet.column(name).is_some(),
+                    ColumnSpec::Index(_) => true,
+                });
+                match (sheets.next(), sheets.next()) {
+                    (Some(sheet), None) => sheet,
+                    _ => return None,
+                }
+            }
+        };
+        let column = match &reference.column {
+            ColumnSpec::Name(name) => sheet.column(name),
+            ColumnSpec::Index(index) => sheet.column_at(*index),
+        }?;
+        Some((sheet, column))
+    }
+
+    /// The value at a row of a column, evaluating it first if it is computed
+    /// and out of date
+    pub fn value(&mut self, sheet: &str, column: &str, row: usize) -> ModelResult<Value> {
+        self.materialise(sheet, column, row..row + 1)?;
+        self.sheet(sheet)
+            .and_then(|sheet| sheet.column(column))
+            .and_then(|column| column.get(row))
+            .ok_or_else(|| ModelError::new("Row out of range", Some(column), Some(row)))
+    }
+
+    /// The row of a sheet with an id, so a reference held by id can be
+    /// turned back into a position after rows have moved
+    pub fn get_row_by_id(&self, sheet: &str, id: RowId) -> Option<usize> {
+        self.sheet(sheet)?.get_row_by_id(id)
+    }
+
+    /// What converting a column with `conversion` would do, without
+    /// changing anything. Fails if the conversion does not type check.
+    pub fn preview_conversion(
+        &mut self,
+        sheet: &str,
+        column: &str,
+        value_type: &ValueType,
+        conversion: &Expression,
+    ) -> ModelResult<ConversionReport> {
+        self.materialise_uses(Some(sheet), conversion);
+        retype::convert(self, sheet, column, value_type, conversion).map(|(_, report)| report)
+    }
+
+    fn retype_column(
+        &mut self,
+        sheet: &str,
+        column: &str,
+        value_type: ValueType,
+        conversion: &Expression,
+        on_failure: ConversionFailure,
+    ) -> ModelResult<WorkbookEdit> {
+        self.materialise_uses(Some(sheet), conversion);
+        let (values, report) = retype::convert(self, sheet, column, &value_type, conversion)?;
+        if let Some(first) = report.failed.first() {
+            let message = match on_failure {
+                ConversionFailure::Block => Some(format!(
+                    "{} of {} rows failed to convert, the first because: {}",
+                    report.failed.len(),
+                    values.len(),
+                    first.reason
+                )),
+                ConversionFailure::Null if !value_type.is_nullable() => Some(format!(
+                    "{} rows failed to convert, and {} cannot hold the nulls they would become",
+                    report.failed.len(),
+                    value_type
+                )),
+                ConversionFailure::Null => None,
+            };
+            if let Some(message) = message {
+                return Err(ModelError::new(&message, Some(column), Some(first.row)));
+            }
+        }
+        let mut replacement = Column::from_values(column, value_type, values)?;
+        let index = self.index_of(sheet)?;
+        // Rules and formats stay with the column, so have to fit its new type
+        let existing = self.sheets[index].column(column).unwrap();
+        replacement.set_formats(existing.formats().to_vec());
+        replacement.set_validation(existing.validation().cloned());
+        format::check(self, sheet, &replacement, replacement.formats())?;
+        if let Some(validation) = replacement.validation() {
+            validation::check(self, sheet, &replacement, validation)?;
+        }
+        let inverse = self.sheets[index].apply(SheetEdit::ReplaceColumn {
+            name: column.to_string(),
+            column: Arc::new(replacement),
+        })?;
+        Ok(WorkbookEdit::Sheet {
+            name: sheet.to_string(),
+            edit: inverse,
+        })
+    }
+
+    fn set_validation(
+        &mut self,
+        sheet: &str,
+        column: &str,
+        validation: Option<Validation>,
+    ) -> ModelResult<WorkbookEdit> {
+        let index = self.index_of(sheet)?;
+        let target = self.sheets[index]
+            .column(column)
+            .ok_or_else(|| ModelError::unknown_column(column))?;
+        if let Some(validation) = &validation {
+            validation::check(self, sheet, target, validation)?;
+        }
+        let inverse = self.sheets[index].apply(SheetEdit::SetValidation {
+            name: column.to_string(),
+            validation,
+        })?;
+        Ok(WorkbookEdit::Sheet {
+            name: sheet.to_string(),
+            edit: inverse,
+        })
+    }
+
+    fn set_formats(
+        &mut self,
+        sheet: &str,
+        column: &str,
+        formats: Vec<Format>,
+    ) -> ModelResult<WorkbookEdit> {
+        let index = self.index_of(sheet)?;
+        let target = self.sheets[index]
+            .column(column)
+            .ok_or_else(|| ModelError::unknown_column(column))?;
+        format::check(self, sheet, target, &formats)?;
+        let inverse = self.sheets[index].apply(SheetEdit::SetFormats {
+            name: column.to_string(),
+            formats,
+        })?;
+        Ok(WorkbookEdit::Sheet {
+            name: sheet.to_string(),
+            edit: inverse,
+        })
+    }
+
+    /// The styles of the cells in `rows` of every visible column of a sheet
+    /// that has formats, by column in schema order. Rows past the end are
+    /// left out. What the formats use is evaluated first, and the styles are
+    /// kept until an edit changes it, so asking again for the same rows is
+    /// cheap.
+    pub fn styles(
+        &mut self,
+        sheet: &str,
+        rows: Range<usize>,
+    ) -> ModelResult<Vec<(String, Vec<CellStyle>)>> {
+        let index = self.index_of(sheet)?;
+        let rows = rows.start..rows.end.min(self.sheets[index].row_count());
+        let columns = self.sheets[index]
+            .visible_columns()
+            .filter(|column| !column.formats().is_empty())
+            .map(|column| (column.name().to_string(), column.formats().to_vec()))
+            .collect::<Vec<_>>();
+        let mut styles = Vec::with_capacity(columns.len());
+        for (column, formats) in columns {
+            let found = self.sheets[index].column(&column).unwrap();
+            let missing = rows
+                .clone()
+                .filter(|row| found.styles().get(*row).is_none())
+                .collect::<Vec<_>>();
+            if !missing.is_empty() {
+                // Ranges are taken over every row, otherwise only the missing
+                // rows are needed
+                let ranged = formats.iter().any(Format::is_ranged);
+                let needed = if ranged { 0..usize::MAX } else { rows.clone() };
+                self.materialise(sheet, &column, needed)?;
+                for format in &formats {
+                    self.materialise_uses(Some(sheet), format.expression());
+                }
+                let found = self.sheets[index].column(&column).unwrap();
+                let ranges = match found.styles().ranges() {
+                    Some(ranges) => ranges.to_vec(),
+                    None => format::ranges(self, sheet, found),
+                };
+                let computed = missing
+                    .into_iter()
+                    .map(|row| (row, format::style(self, sheet, found, &ranges, row)))
+                    .collect::<Vec<_>>();
+                let target = self.sheets[index].column_mut(&column).unwrap().styles_mut();
+                target.set_ranges(ranges);
+                for (row, style) in computed {
+                    target.store(row, style);
+                }
+            }
+            let found = self.sheets[index].column(&column).unwrap();
+            let column_styles = rows
+                .clone()
+                .map(|row| found.styles().get(row).cloned().unwrap_or_default())
+                .collect();
+            styles.push((column, column_styles));
+        }
+        Ok(styles)
+    }
+
+    /// Every row of a sheet that breaks the rules of its columns, including
+    /// the values quarantined for it, so rules added to a column with data
+    /// in it can be checked against what is already there
+    pub fn validate(&mut self, sheet: &str) -> ModelResult<ValidationReport> {
+        let index = self.index_of(sheet)?;
+        let mut report = ValidationReport::default();
+        let columns = self.sheets[index]
+            .columns()
+            .filter_map(|column| Some((column.name().to_string(), column.validation()?.clone())))
+            .collect::<Vec<_>>();
+        for (column, rules) in columns {
+            self.materialise_rules(sheet, &rules);
+            let found = self.sheets[index].column(&column).unwrap();
+            let mut violations = validation::violations(self, sheet, found, 0..usize::MAX);
+            violations.extend(validation::quarantined(self, sheet, found));
+            violations.sort_by_key(|violation| violation.row);
+            report.violations.extend(violations);
+        }
+        Ok(report)
+    }
+
+    /// Evaluates every row of the columns the formula rules of a column on
+    /// `sheet` use
+    fn materialise_rules(&mut self, sheet: &str, validation: &Validation) {
+        for rule in &validation.rules {
+            if let Rule::Formula(formula) = rule {
+                self.materialise_uses(Some(sheet), formula);
+            }
+        }
+    }
+
+    /// Evaluates every row of the columns an expression uses, from `sheet`
+    /// or the workspace. Failures show up as error values when the
+    /// expression is evaluated.
+    fn materialise_uses(&mut self, sheet: Option<&str>, expression: &Expression) {
+        for reference in expression.columns() {
+            if let Some((found, column)) = self.resolve(sheet, reference) {
+                let (found, column) = (found.name().to_string(), column.name().to_string());
+                let _ = self.materialise(&found, &column, 0..usize::MAX);
+            }
+        }
+    }
+
+    /// The edit that sorts a sheet by one of its columns. Nulls come after
+    /// the values and errors after the nulls, in either direction, and rows
+    /// that compare equal keep their order.
+    pub fn sort_by(
+        &mut self,
+        sheet: &str,
+        column: &str,
+        descending: bool,
+    ) -> ModelResult<WorkbookEdit> {
+        self.materialise(sheet, column, 0..usize::MAX)?;
+        let key = self.sheet(sheet).unwrap().column(column).unwrap();
+        let values = key.iter().collect::<Vec<_>>();
+        let rank = |value: &Value| match value {
+            Value::Null => 1,
+            Value::Error(..) => 2,
+            _ => 0,
+        };
+        let mut order = (0..values.len()).collect::<Vec<_>>();
+        order.sort_by(|a, b| {
+            let (a, b) = (&values[*a], &values[*b]);
+            rank(a).cmp(&rank(b)).then_with(|| {
+                let ordering = match (a, b) {
+                    (Value::Number(a), Value::Number(b)) => a.total_cmp(b),
+                    _ => a.compare(b).flatten().unwrap_or(Ordering::Equal),
+                };
+                if descending {
+                    ordering.reverse()
+                } else {
+                    ordering
+                }
+            })
+        });
+        Ok(WorkbookEdit::Sheet {
+            name: sheet.to_string(),
+            edit: SheetEdit::PermuteRows { order },
+        })
+    }
+
+    /// Evaluates the out of date rows of a computed column within `rows`,
+    /// along with whatever they use from other computed columns. Only those
+    /// rows are evaluated, so a viewport can be filled in without evaluating
+    /// the whole column.
+    pub fn materialise(
+        &mut self,
+        sheet: &str,
+        column: &str,
+        rows: Range<usize>,
+    ) -> ModelResult<()> {
+        let index = self.index_of(sheet)?;
+        let target = self.sheets[index]
+            .column(column)
+            .ok_or_else(|| ModelError::unknown_column(column))?;
+        let Some(formula) = target.formula().cloned() else {
+            return Ok(());
+        };
+        let rows = rows.start.min(target.len())..rows.end.min(target.len());
+        let pending = rows
+            .filter(|row| !target.is_cached(*row))
+            .collect::<Vec<_>>();
+        let (Some(first), Some(last)) = (pending.first(), pending.last()) else {
+            return Ok(());
+        };
+        // The graph has no cycles, so this always finishes
+        let span = *first..*last + 1;
+        for (reference, whole) in formula.column_uses() {
+            let Some((input_sheet, input)) = self.resolve(Some(sheet), reference) else {
+                continue;
+            };
+            let (input_sheet, input) = (input_sheet.name().to_string(), input.name().to_string());
+            let range = if whole { 0..usize::MAX } else { span.clone() };
+            self.materialise(&input_sheet, &input, range)?;
+        }
+        let values = pending
+            .iter()
+            .map(|row| {
+                let context = self.context(sheet, Some(*row)).unwrap();
+                formula.eval(&context).unwrap_or_else(Value::error)
+            })
+            .collect::<Vec<_>>();
+        let target = self.sheets[index].column_mut(column).unwrap();
+        for (row, value) in pending.into_iter().zip(values) {
+            target.store(row, value);
+        }
+        Ok(())
+    }
+
+    /// Sets a workspace entry, after checking that every entry still type
+    /// checks with the change
+    fn set_entry(
+        &mut self,
+        name: &str,
+        definition: Option<EntryDefinition>,
+    ) -> ModelResult<Option<EntryDefinition>> {
+        let mut chars = name.chars();
+        if !chars.next().map_or(false, |c| c.is_ascii_alphabetic())
+            || !chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+            || ["true", "false", "null"].contains(&name)
+        {
+            return Err(ModelError::new(
+                &format!("{:?} is not a valid variable name", name),
+                None,
+                None,
+            ));
+        }
+        if let Some(EntryDefinition::Value(Value::Column(_))) = definition {
+            return Err(ModelError::new(
+                "Workspace entries cannot hold a whole column",
+                None,
+                None,
+            ));
+        }
+        let mut workspace = self.workspace.clone();
+        let previous = workspace.define(name, definition);
+        let order = self.build_graph(&workspace).order()?;
+        for node in order {
+            let NodeId::Entry(entry) = node else {
+                continue;
+            };
+            let value_type = match workspace.get(&entry).map(|entry| entry.definition()) {
+                Some(EntryDefinition::Expression(expression)) => {
+                    let context = SheetContext {
+                        workbook: self,
+                        workspace: &workspace,
+                        sheet: None,
+                        row: None,
+                    };
+                    expression.type_check(&context).map_err(|error| {
+                        ModelError::new(
+                            &format!("In {}: {}", entry, error.to_string().trim_start()),
+                            None,
+                            None,
+                        )
+                    })?
+                }
+                _ => continue,
+            };
+            if let ValueType::Column(_) = value_type {
+                return Err(ModelError::new(
+                    &format!("In {}: Workspace entries cannot hold a whole column", entry),
+                    None,
+                    None,
+                ));
+            }
+            workspace.set_type(&entry, value_type);
+        }
+        self.workspace = workspace;
+        Ok(previous)
+    }
+
+    /// Evaluates the given workspace entries, each after whatever it uses
+    fn evaluate_workspace(&mut self, entries: &BTreeSet<String>) {
+        let order = self.graph.order().unwrap_or_default();
+        for node in order {
+            match node {
+                NodeId::Entry(name) if entries.contains(&name) => self.evaluate_entry(&name),
+                _ => {}
+            }
+        }
+    }
+
+    fn evaluate_entry(&mut self, name: &str) {
+        let expression = match self.workspace.get(name).map(|entry| entry.definition()) {
+            Some(EntryDefinition::Value(value)) => {
+                self.workspace.set_value(name, value.clone());
+                return;
+            }
+            Some(EntryDefinition::Expression(expression)) => expression.clone(),
+            None => return,
+        };
+        self.materialise_uses(None, &expression);
+        let value = expression
+            .eval(&self.workspace_context())
+            .unwrap_or_else(Value::error);
+        self.workspace.set_value(name, value);
+    }
+
+    /// Evaluates every computed column in full and every workspace entry,
+    /// each after whatever it uses
+    pub fn recalculate(&mut self) -> ModelResult<()> {
+        for sheet in &mut self.sheets {
+            sheet.invalidate();
+        }
+        for node in self.graph.order()? {
+            match node {
+                NodeId::Column { sheet, column } => {
+                    self.materialise(&sheet, &column, 0..usize::MAX)?
+                }
+                NodeId::Entry(name) => self.evaluate_entry(&name),
+            }
+        }
+        Ok(())
+    }
+
+    /// Starts evaluating every out of date computed row on `pool`, working
+    /// on a snapshot of the workbook.
+    ///
+    /// Computed columns are evaluated in levels, each level only using the
+    /// columns in the levels before it. The columns in a level, and chunks of
+    /// rows within each column, are evaluated at the same time, and the
+    /// results are stored in a fixed order once the whole level is done.
+    /// Every value only depends on earlier levels, so the results are the
+    /// same as evaluating one row at a time.
+    pub fn start_recalculation(&mut self, pool: &ThreadPool) -> Recalculation {
+        if let Some(token) = self.running.take() {
+            token.cancel();
+        }
+        let token = CancelToken::new();
+        self.running = Some(token.clone());
+        let future = Self::recalculate_snapshot(self.clone(), pool.clone(), token.clone());
+        let handle = pool
+            .spawn_with_handle(future)
+            .expect("thread pools accept new tasks while running");
+        Recalculation::new(self.generation, token, handle)
+    }
+
+    /// Waits for a recalculation and keeps its results, unless it was
+    /// cancelled or the workbook has been edited since it started. Returns
+    /// whether the results were kept.
+    pub fn finish_recalculation(&mut self, recalculation: Recalculation) -> bool {
+        let generation = recalculation.generation();
+        match recalculation.wait() {
+            Some(snapshot) if generation == self.generation => {
+                self.sheets = snapshot.sheets;
+                self.running = None;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Evaluates every out of date computed row on `pool`, and waits for it
+    pub fn recalculate_on(&mut self, pool: &ThreadPool) -> bool {
+        let recalculation = self.start_recalculation(pool);
+        self.finish_recalculation(recalculation)
+    }
+
+    /// The computed columns grouped into levels, each only using computed
+    /// columns from earlier levels
+    fn levels(&self) -> Vec<Vec<(String, String)>> {
+        let mut level_of = BTreeMap::new();
+        let mut levels: Vec<Vec<(String, String)>> = Vec::new();
+        for node in self.graph.order().unwrap_or_default() {
+            let NodeId::Column { sheet, column } = &node else {
+                continue;
+            };
+            let computed = self
+                .sheet(sheet)
+                .and_then(|found| found.column(column))
+                .map_or(false, |found| found.is_computed());
+            if !computed {
+                continue;
+            }
+            let level = self
+                .graph
+                .precedents(&node)
+                .iter()
+                .filter_map(|precedent| level_of.get(precedent))
+                .map(|level| level + 1)
+                .max()
+                .unwrap_or(0);
+            if levels.len() <= level {
+                levels.resize(level + 1, Vec::new());
+            }
+            levels[level].push((sheet.clone(), column.clone()));
+            level_of.insert(node, level);
+        }
+        levels
+    }
+
+    async fn recalculate_snapshot(
+        workbook: Workbook,
+        pool: ThreadPool,
+        token: CancelToken,
+    ) -> Option<Workbook> {
+        let levels = workbook.levels();
+        let mut workbook = Arc::new(workbook);
+        for level in levels {
+            let mut jobs = Vec::new();
+            let mut targets = Vec::new();
+            for (sheet, column) in level {
+                let target = workbook.sheet(&sheet)?.column(&column)?;
+                let formula = Arc::new(target.formula()?.clone());
+                let pending = (0..target.len())
+                    .filter(|row| !target.is_cached(*row))
+                    .collect::<Vec<_>>();
+                for rows in pending.chunks(CHUNK_ROWS) {
+                    let (workbook, formula, token) =
+                        (workbook.clone(), formula.clone(), token.clone());
+                    let (name, chunk) = (sheet.clone(), rows.to_vec());
+                    let job = pool
+                        .spawn_with_handle(async move {
+                            recalc::evaluate_rows(&workbook, &name, &formula, &chunk, &token)
+                        })
+                        .expect("thread pools accept new tasks while running");
+                    jobs.push(job);
+                    targets.push((sheet.clone(), column.clone(), rows.to_vec()));
+                }
+            }
+            let results = join_all(jobs).await;
+            let snapshot = Arc::make_mut(&mut workbook);
+            for ((sheet, column, rows), values) in targets.into_iter().zip(results) {
+                let index = snapshot.index_of(&sheet).ok()?;
+                let target = snapshot.sheets[index].column_mut(&column)?;
+                for (row, value) in rows.into_iter().zip(values?) {
+                    target.store(row, value);
+                }
+            }
+            if token.is_cancelled() {
+                return None;
+            }
+        }
+        Some(Arc::try_unwrap(workbook).unwrap_or_else(|shared| (*shared).clone()))
+    }
+
+    /// A context for evaluating expressions that belong to `sheet`,
+    /// optionally at one of its rows
+    pub fn context(&self, sheet: &str, row: Option<usize>) -> Option<SheetContext<'_>> {
+        Some(SheetContext {
+            workbook: self,
+            workspace: &self.workspace,
+            sheet: Some(self.sheet(sheet)?),
+            row,
+        })
+    }
+
+    /// A context for evaluating workspace entries, which belong to no sheet
+    pub fn workspace_context(&self) -> SheetContext<'_> {
+        SheetContext {
+            workbook: self,
+            workspace: &self.workspace,
+            sheet: None,
+            row: None,
+        }
+    }
+}
+
+/// Resolves the names in an expression belonging to a workbook. Variables
+/// are workspace entries.
+///
+/// Column references without a sheet name refer to the expression's sheet.
+/// Expressions in the workspace have no sheet, so a column name without a
+/// sheet refers to the only sheet with a column of that name.
+pub struct SheetContext<'a> {
+    workbook: &'a Workbook,
+    workspace: &'a Workspace,
+    sheet: Option<&'a Sheet>,
+    row: Option<usize>,
+}
+
+impl Context for SheetContext<'_> {
+    fn variable(&self, name: &str) -> Option<Value> {
+        self.workspace.get(name).map(|entry| entry.value().clone())
+    }
+
+    fn variable_type(&self, name: &str) -> Option<ValueType> {
+        self.workspace
+            .get(name)
+            .map(|entry| entry.value_type().clone())
+    }
+
+    fn column(&self, reference: &ColumnRef) -> Option<Arc<dyn ColumnView>> {
+        self.workbook
+            .resolve(self.sheet.map(Sheet::name), reference)
+            .map(|(_, column)| column.clone() as Arc<dyn ColumnView>)
+    }
+
+    fn row(&self) -> Option<usize> {
+        self.row
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::expression::{ErrorKind, Expression};
+    use crate::model::{CellStyle, Colour, Pattern};
+
+    fn workbook() -> Workbook {
+        let mut workbook = Workbook::new();
+        for (name, column, values) in [
+            ("Orders", "amount", vec![10, 20, 30]),
+            ("Rates", "rate", vec![2]),
+        ] {
+            let mut sheet = Sheet::new(name);
+            let column = Column::from_values(
+                column,
+                ValueType::Integer,
+                values.into_iter().map(Value::Integer),
+            )
+            .unwrap();
+            sheet
+                .apply(SheetEdit::AddColumn {
+                    index: 0,
+                    column: Arc::new(column),
+                    hidden: false,
+                })
+                .unwrap();
+            workbook
+                .apply(WorkbookEdit::AddSheet { index: 0, sheet })
+                .unwrap();
+        }
+        workbook
+    }
+
+    #[test]
+    fn cross_sheet_references() {
+        let workbook = workbook();
+        let check = |source: &str, row: Option<usize>| {
+            let context = workbook.context("Orders", row).unwrap();
+            let expression = Expression::parse(source).unwrap();
+            expression
+                .type_check(&context)
+                .map(|value_type| (value_type, expression.eval(&context).unwrap()))
+        };
+
+        assert_eq!(
+            check("=sum(:'amount')", None).unwrap(),
+            (ValueType::Integer.nullable(), Value::Integer(60))
+        );
+        assert_eq!(
+            check("=:'amount' * :'Rates'!'rate'", Some(0)).unwrap(),
+            (ValueType::Integer.nullable(), Value::Integer(20))
+        );
+        // Rates only has one row
+        assert_eq!(check("=:'Rates'!0", Some(1)).unwrap().1, Value::Null);
+        assert_eq!(
+            check("=:0 + 1", Some(2)).unwrap(),
+            (ValueType::Integer, Value::Integer(31))
+        );
+        assert!(check("=:'Orders'!'price'", Some(0)).is_err());
+        assert!(check("=:'Nowhere'!'amount'", Some(0)).is_err());
+        assert!(check("=:'amount' + 'x'", Some(0)).is_err());
+        assert!(check("=:'amount'", None).unwrap().1.is_error());
+    }
+
+    fn set(workbook: &mut Workbook, name: &str, source: &str) -> ModelResult<WorkbookEdit> {
+        workbook.apply(WorkbookEdit::SetEntry {
+            name: name.to_string(),
+            definition: Some(EntryDefinition::Expression(
+                Expression::parse(source).unwrap(),
+            )),
+        })
+    }
+
+    #[test]
+    fn workspace() {
+        let mut workbook = workbook();
+        set(&mut workbook, "total", "=sum(:'amount') * markup").unwrap_err();
+        workbook
+            .apply(WorkbookEdit::SetEntry {
+                name: "markup".to_string(),
+                definition: Some(EntryDefinition::Value(Value::Integer(2))),
+            })
+            .unwrap();
+        set(&mut workbook, "total", "=sum(:'amount') * markup").unwrap();
+        let entry = workbook.workspace().get("total").unwrap();
+        assert_eq!(entry.value_type(), &ValueType::Integer.nullable());
+        assert_eq!(entry.value(), &Value::Integer(120));
+
+        // Entries are usable in sheet expressions and follow their inputs
+        let context = workbook.context("Orders", Some(0)).unwrap();
+        let expression = Expression::parse("=:'amount' * markup").unwrap();
+        assert_eq!(expression.eval(&context).unwrap(), Value::Integer(20));
+        workbook
+            .apply(WorkbookEdit::Sheet {
+                name: "Orders".to_string(),
+                edit: SheetEdit::SetCell {
+                    column: "amount".to_string(),
+                    row: 0,
+                    value: Value::Integer(40),
+                },
+            })
+            .unwrap();
+        assert_eq!(
+            workbook.workspace().get("total").unwrap().value(),
+            &Value::Integer(180)
+        );
+
+        // Changes that would break another entry are rejected
+        let error = workbook
+            .apply(WorkbookEdit::SetEntry {
+                name: "markup".to_string(),
+                definition: Some(EntryDefinition::Value(Value::String("x".to_string()))),
+            })
+            .unwrap_err();
+        assert!(error.message().starts_with("In total"));
+        assert!(workbook
+            .apply(WorkbookEdit::SetEntry {
+                name: "markup".to_string(),
+                definition: None,
+            })
+            .is_err());
+        assert!(set(&mut workbook, "markup", "=total + 1").is_err());
+        assert!(set(&mut workbook, "2x", "=1").is_err());
+
+        let undo = set(&mut workbook, "total", "=0").unwrap();
+        workbook.apply(undo).unwrap();
+        assert_eq!(
+            workbook.workspace().get("total").unwrap().value(),
+            &Value::Integer(180)
+        );
+    }
+
+    fn set_formula(
+        workbook: &mut Workbook,
+        column: &str,
+        source: Option<&str>,
+    ) -> ModelResult<WorkbookEdit> {
+        workbook.apply(WorkbookEdit::SetFormula {
+            sheet: "Orders".to_string(),
+            column: column.to_string(),
+            formula: source.map(|source| Expression::parse(source).unwrap()),
+        })
+    }
+
+    #[test]
+    fn computed_columns() {
+        let mut workbook = workbook();
+        set_formula(&mut workbook, "double", Some("=:'amount' * 2")).unwrap();
+        set_formula(&mut workbook, "quad", Some("=:'double' * 2")).unwrap();
+        let cached = |workbook: &Workbook, column: &str| {
+            let column = workbook.sheet("Orders").unwrap().column(column).unwrap();
+            (0..column.len())
+                .map(|row| column.is_cached(row))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            workbook
+                .sheet("Orders")
+                .unwrap()
+                .column("quad")
+                .unwrap()
+                .value_type(),
+            &ValueType::Integer
+        );
+
+        // Only the rows asked for are evaluated
+        assert_eq!(
+            workbook.value("Orders", "quad", 2).unwrap(),
+            Value::Integer(120)
+        );
+        assert_eq!(cached(&workbook, "double"), [false, false, true]);
+        set(&mut workbook, "total", "=sum(:'double')").unwrap();
+        assert_eq!(
+            workbook.workspace().get("total").unwrap().value(),
+            &Value::Integer(120)
+        );
+        assert_eq!(cached(&workbook, "double"), [true, true, true]);
+
+        // Changing an input makes the rows that use it out of date
+        workbook
+            .apply(WorkbookEdit::Sheet {
+                name: "Orders".to_string(),
+                edit: SheetEdit::SetCell {
+                    column: "amount".to_string(),
+                    row: 0,
+                    value: Value::Integer(40),
+                },
+            })
+            .unwrap();
+        assert_eq!(cached(&workbook, "quad"), [false, false, true]);
+        assert_eq!(
+            workbook.value("Orders", "quad", 0).unwrap(),
+            Value::Integer(160)
+        );
+        assert!(workbook
+            .apply(WorkbookEdit::Sheet {
+                name: "Orders".to_string(),
+                edit: SheetEdit::SetCell {
+                    column: "quad".to_string(),
+                    row: 0,
+                    value: Value::Integer(1),
+                },
+            })
+            .is_err());
+
+        let error = set_formula(&mut workbook, "double", Some("=:'quad' + 1")).unwrap_err();
+        assert_eq!(
+            error.message(),
+            "Dependency cycle: Orders!double -> Orders!quad -> Orders!double"
+        );
+
+        // Paste as values, and back
+        let undo = set_formula(&mut workbook, "double", None).unwrap();
+        let double = workbook.sheet("Orders").unwrap().column("double").unwrap();
+        assert!(!double.is_computed());
+        assert_eq!(
+            double.iter().collect::<Vec<_>>(),
+            [80, 40, 60].map(Value::Integer)
+        );
+        workbook.apply(undo).unwrap();
+        assert!(workbook
+            .sheet("Orders")
+            .unwrap()
+            .column("double")
+            .unwrap()
+            .is_computed());
+        assert!(set_formula(&mut workbook, "amount", None).is_err());
+
+        // New rows are evaluated like any other
+        workbook
+            .apply(WorkbookEdit::Sheet {
+                name: "Orders".to_string(),
+                edit: SheetEdit::InsertRows {
+                    at: 0,
+                    count: 1,
+                    values: [("amount".to_string(), vec![Value::Integer(1)])].into(),
+                    ids: None,
+                },
+            })
+            .unwrap();
+        assert_eq!(
+            workbook.value("Orders", "quad", 0).unwrap(),
+            Value::Integer(4)
+        );
+    }
+
+    #[test]
+    fn dependencies() {
+        let mut workbook = workbook();
+        workbook
+            .apply(WorkbookEdit::SetEntry {
+                name: "markup".to_string(),
+                definition: Some(EntryDefinition::Value(Value::Integer(2))),
+            })
+            .unwrap();
+        set_formula(&mut workbook, "price", Some("=:'amount' * markup")).unwrap();
+        set(
+            &mut workbook,
+            "total",
+            "=sum(:'price') * sum(:'Rates'!'rate')",
+        )
+        .unwrap();
+
+        let graph = workbook.graph();
+        let price = NodeId::column("Orders", "price");
+        let names =
+            |nodes: BTreeSet<NodeId>| nodes.iter().map(NodeId::to_string).collect::<Vec<_>>();
+        assert_eq!(names(graph.precedents(&price)), ["Orders!amount", "markup"]);
+        assert_eq!(names(graph.dependents(&price)), ["total"]);
+        assert_eq!(
+            names(graph.all_precedents(&NodeId::entry("total"))),
+            ["Orders!amount", "Orders!price", "Rates!rate", "markup"]
+        );
+        assert_eq!(
+            names(graph.all_dependents(&NodeId::entry("markup"))),
+            ["Orders!price", "total"]
+        );
+        assert_eq!(
+            workbook.workspace().get("total").unwrap().value(),
+            &Value::Integer(240)
+        );
+
+        // Cycles through entries are found too, and renames that would make
+        // one are rejected
+        let error = set(&mut workbook, "markup", "=total").unwrap_err();
+        assert_eq!(
+            error.message(),
+            "Dependency cycle: Orders!price -> markup -> total -> Orders!price"
+        );
+        set_formula(&mut workbook, "bonus", Some("=:'extra'")).unwrap_err();
+        set_formula(&mut workbook, "bonus", Some("=:'price'")).unwrap();
+        assert!(workbook
+            .apply(WorkbookEdit::Sheet {
+                name: "Orders".to_string(),
+                edit: SheetEdit::RenameColumn {
+                    from: "bonus".to_string(),
+                    to: "price".to_string(),
+                },
+            })
+            .is_err());
+
+        workbook.recalculate().unwrap();
+        let bonus = workbook.sheet("Orders").unwrap().column("bonus").unwrap();
+        assert!((0..3).all(|row| bonus.is_cached(row)));
+        assert_eq!(bonus.get(2), Some(Value::Integer(60)));
+    }
+
+    #[test]
+    fn incremental() {
+        let mut workbook = workbook();
+        set_formula(&mut workbook, "double", Some("=:'amount' * 2")).unwrap();
+        set_formula(&mut workbook, "total", Some("=sum(:'amount')")).unwrap();
+        set_formula(
+            &mut workbook,
+            "scaled",
+            Some("=:'amount' * :'Rates'!'rate'"),
+        )
+        .unwrap();
+        set(&mut workbook, "n", "=count(:'amount')").unwrap();
+        workbook.recalculate().unwrap();
+        let cached = |workbook: &Workbook, column: &str| {
+            let column = workbook.sheet("Orders").unwrap().column(column).unwrap();
+            (0..column.len())
+                .map(|row| column.is_cached(row))
+                .collect::<Vec<_>>()
+        };
+        let edit = |workbook: &mut Workbook, sheet: &str, edit: SheetEdit| {
+            workbook
+                .apply(WorkbookEdit::Sheet {
+                    name: sheet.to_string(),
+                    edit,
+                })
+                .unwrap()
+        };
+
+        // Row by row uses only lose the changed row, aggregates lose them all
+        edit(
+            &mut workbook,
+            "Orders",
+            SheetEdit::SetCell {
+                column: "amount".to_string(),
+                row: 1,
+                value: Value::Integer(25),
+            },
+        );
+        assert_eq!(cached(&workbook, "double"), [true, false, true]);
+        assert_eq!(cached(&workbook, "scaled"), [true, false, true]);
+        assert_eq!(cached(&workbook, "total"), [false, false, false]);
+        assert_eq!(
+            workbook.value("Orders", "total", 2).unwrap(),
+            Value::Integer(65)
+        );
+
+        // Inserted rows are new, and rows on other sheets no longer line up
+        workbook.recalculate().unwrap();
+        edit(
+            &mut workbook,
+            "Orders",
+            SheetEdit::InsertRows {
+                at: 1,
+                count: 1,
+                values: [("amount".to_string(), vec![Value::Integer(5)])].into(),
+                ids: None,
+            },
+        );
+        assert_eq!(cached(&workbook, "double"), [true, false, true, true]);
+        assert_eq!(
+            workbook.workspace().get("n").unwrap().value(),
+            &Value::Integer(4)
+        );
+        workbook.recalculate().unwrap();
+        edit(
+            &mut workbook,
+            "Rates",
+            SheetEdit::InsertRows {
+                at: 1,
+                count: 1,
+                values: [("rate".to_string(), vec![Value::Integer(3)])].into(),
+                ids: None,
+            },
+        );
+        assert_eq!(cached(&workbook, "double"), [true, true, true, true]);
+        assert_eq!(cached(&workbook, "scaled"), [true, false, false, false]);
+        assert_eq!(
+            workbook.value("Orders", "scaled", 1).unwrap(),
+            Value::Integer(15)
+        );
+
+        // Edits that change no values keep everything
+        workbook.recalculate().unwrap();
+        edit(
+            &mut workbook,
+            "Orders",
+            SheetEdit::SetHidden {
+                name: "amount".to_string(),
+                hidden: true,
+            },
+        );
+        assert_eq!(cached(&workbook, "total"), [true, true, true, true]);
+    }
+
+    #[test]
+    fn parallel_recalculation() {
+        let mut workbook = Workbook::new();
+        let rows = CHUNK_ROWS * 2 + 10;
+        let mut sheet = Sheet::new("Data");
+        let column = Column::from_values(
+            "x",
+            ValueType::Integer,
+            (0..rows as i64).map(Value::Integer),
+        )
+        .unwrap();
+        sheet
+            .apply(SheetEdit::AddColumn {
+                index: 0,
+                column: Arc::new(column),
+                hidden: false,
+            })
+            .unwrap();
+        workbook
+            .apply(WorkbookEdit::AddSheet { index: 0, sheet })
+            .unwrap();
+        for (column, source) in [
+            ("double", "=:'x' * 2"),
+            ("square", "=:'x' * :'x'"),
+            ("total", "=sum(:'double') + :'square'"),
+            ("ratio", "=to_number(:'total') / to_number(:'x')"),
+        ] {
+            workbook
+                .apply(WorkbookEdit::SetFormula {
+                    sheet: "Data".to_string(),
+                    column: column.to_string(),
+                    formula: Some(Expression::parse(source).unwrap()),
+                })
+                .unwrap();
+        }
+        let values = |workbook: &Workbook| {
+            workbook
+                .sheet("Data")
+                .unwrap()
+                .columns()
+                .map(|column| column.iter().collect::<Vec<_>>())
+                .collect::<Vec<_>>()
+        };
+
+        let pool = ThreadPool::new().unwrap();
+        let mut serial = workbook.clone();
+        serial.recalculate().unwrap();
+        assert!(workbook.recalculate_on(&pool));
+        assert_eq!(values(&workbook), values(&serial));
+        assert!(workbook
+            .sheet("Data")
+            .unwrap()
+            .column("ratio")
+            .unwrap()
+            .get(0)
+            .unwrap()
+            .is_error());
+
+        // A newer edit cancels the recalculation and its results are dropped
+        let generation = workbook.generation();
+        let recalculation = workbook.start_recalculation(&pool);
+        workbook
+            .apply(WorkbookEdit::Sheet {
+                name: "Data".to_string(),
+                edit: SheetEdit::SetCell {
+                    column: "x".to_string(),
+                    row: 1,
+                    value: Value::Integer(-1),
+                },
+            })
+            .unwrap();
+        assert!(recalculation.is_cancelled());
+        assert_eq!(workbook.generation(), generation + 1);
+        assert!(!workbook.finish_recalculation(recalculation));
+        assert!(!workbook
+            .sheet("Data")
+            .unwrap()
+            .column("double")
+            .unwrap()
+            .is_cached(1));
+        assert!(workbook.recalculate_on(&pool));
+        assert_eq!(
+            workbook.value("Data", "double", 1).unwrap(),
+            Value::Integer(-2)
+        );
+    }
+
+    #[test]
+    fn transactions() {
+        let mut workbook = workbook();
+        set(&mut workbook, "total", "=sum(:'amount')").unwrap();
+        let cell = |row: usize, value: Value| WorkbookEdit::Sheet {
+            name: "Orders".to_string(),
+            edit: SheetEdit::SetCell {
+                column: "amount".to_string(),
+                row,
+                value,
+            },
+        };
+
+        // Every failing cell is reported and nothing is kept
+        let transaction = Transaction::from_iter([
+            cell(0, Value::Integer(1)),
+            cell(1, Value::String("x".to_string())),
+            cell(2, Value::Integer(3)),
+            cell(5, Value::Integer(4)),
+        ]);
+        let error = workbook.commit(transaction).unwrap_err();
+        let failures = error
+            .failures()
+            .iter()
+            .map(|failure| (failure.index, failure.error.row()))
+            .collect::<Vec<_>>();
+        assert_eq!(failures, [(1, Some(1)), (3, Some(5))]);
+        assert!(error.to_string().contains("#1 Orders: [\"amount\":1]"));
+        assert_eq!(
+            workbook
+                .sheet("Orders")
+                .unwrap()
+                .column("amount")
+                .unwrap()
+                .iter()
+                .collect::<Vec<_>>(),
+            [10, 20, 30].map(Value::Integer)
+        );
+        assert_eq!(
+            workbook.workspace().get("total").unwrap().value(),
+            &Value::Integer(60)
+        );
+
+        // Entries redefined and then rolled back are evaluated again
+        let mut transaction = Transaction::new();
+        transaction.push(WorkbookEdit::SetEntry {
+            name: "total".to_string(),
+            definition: Some(EntryDefinition::Value(Value::Integer(0))),
+        });
+        transaction.push(cell(0, Value::Null));
+        assert!(workbook.commit(transaction).is_err());
+        assert_eq!(
+            workbook.workspace().get("total").unwrap().value(),
+            &Value::Integer(60)
+        );
+
+        let undo = workbook
+            .commit(Transaction::from_iter([
+                cell(0, Value::Integer(1)),
+                cell(1, Value::Integer(2)),
+            ]))
+            .unwrap();
+        assert_eq!(
+            workbook.workspace().get("total").unwrap().value(),
+            &Value::Integer(33)
+        );
+        workbook.commit(undo.into_iter().rev().collect()).unwrap();
+        assert_eq!(
+            workbook.workspace().get("total").unwrap().value(),
+            &Value::Integer(60)
+        );
+    }
+
+    #[test]
+    fn row_offsets() {
+        let mut workbook = workbook();
+        let values = |workbook: &mut Workbook, column: &str| {
+            (0..3)
+                .map(|row| workbook.value("Orders", column, row).unwrap())
+                .collect::<Vec<_>>()
+        };
+        set_formula(&mut workbook, "delta", Some("=:'amount' - :'amount'[-1]")).unwrap();
+        set_formula(&mut workbook, "total", Some("=sum(:'amount'[..+1])")).unwrap();
+        set_formula(
+            &mut workbook,
+            "first",
+            Some("=:'amount'[0] + sum(:'amount'[1..2])"),
+        )
+        .unwrap();
+        set_formula(&mut workbook, "next", Some("=:'amount'[+1]")).unwrap();
+        assert_eq!(
+            values(&mut workbook, "delta"),
+            [Value::Null, Value::Integer(10), Value::Integer(10)]
+        );
+        assert_eq!(
+            values(&mut workbook, "total"),
+            [10, 30, 60].map(Value::Integer)
+        );
+        assert_eq!(
+            values(&mut workbook, "first"),
+            [30, 30, 30].map(Value::Integer)
+        );
+        assert_eq!(
+            values(&mut workbook, "next"),
+            [Value::Integer(20), Value::Integer(30), Value::Null]
+        );
+
+        // Rows picked in brackets depend on more than their own row
+        workbook
+            .apply(WorkbookEdit::Sheet {
+                name: "Orders".to_string(),
+                edit: SheetEdit::SetCell {
+                    column: "amount".to_string(),
+                    row: 0,
+                    value: Value::Integer(0),
+                },
+            })
+            .unwrap();
+        assert_eq!(
+            values(&mut workbook, "delta"),
+            [Value::Null, Value::Integer(20), Value::Integer(10)]
+        );
+        assert_eq!(
+            values(&mut workbook, "total"),
+            [0, 20, 50].map(Value::Integer)
+        );
+
+        // Slices are columns, so they cannot be a column's values
+        assert!(set_formula(&mut workbook, "slice", Some("=:'amount'[0..2]")).is_err());
+        assert!(set_formula(&mut workbook, "bad", Some("=:'amount'[0..2] + 1")).is_err());
+    }
+
+    #[test]
+    fn row_ids() {
+        let mut workbook = workbook();
+        let ids: Vec<_> = workbook.sheet("Orders").unwrap().row_ids().iter().collect();
+        let row = |workbook: &Workbook, id| workbook.get_row_by_id("Orders", id);
+
+        // Ids follow their rows through sorts, inserts and deletes
+        let sort = workbook.sort_by("Orders", "amount", true).unwrap();
+        let unsort = workbook.apply(sort).unwrap();
+        assert_eq!(row(&workbook, ids[0]), Some(2));
+        assert_eq!(row(&workbook, ids[2]), Some(0));
+        workbook.apply(unsort).unwrap();
+        let insert = WorkbookEdit::Sheet {
+            name: "Orders".to_string(),
+            edit: SheetEdit::InsertRows {
+                at: 0,
+                count: 1,
+                values: [("amount".to_string(), vec![Value::Integer(0)])].into(),
+                ids: None,
+            },
+        };
+        let delete = workbook.apply(insert).unwrap();
+        let new = workbook.sheet("Orders").unwrap().row_id(0).unwrap();
+        assert!(!ids.contains(&new));
+        assert_eq!(row(&workbook, ids[0]), Some(1));
+        workbook.apply(delete).unwrap();
+        assert_eq!(row(&workbook, new), None);
+
+        let delete = WorkbookEdit::Sheet {
+            name: "Orders".to_string(),
+            edit: SheetEdit::DeleteRows { at: 0, count: 1 },
+        };
+        let undo = workbook.apply(delete).unwrap();
+        assert_eq!(row(&workbook, ids[0]), None);
+        assert_eq!(row(&workbook, ids[1]), Some(0));
+        workbook.apply(undo).unwrap();
+        assert_eq!(row(&workbook, ids[0]), Some(0));
+    }
+
+    #[test]
+    fn retype_column() {
+        let mut workbook = workbook();
+        let mut sheet = Sheet::new("Codes");
+        let column = Column::from_values(
+            "code",
+            ValueType::String.nullable(),
+            [
+                Value::String(" 1.5 ".to_string()),
+                Value::String("2".to_string()),
+                Value::String("abc".to_string()),
+                Value::Null,
+            ],
+        )
+        .unwrap();
+        sheet
+            .apply(SheetEdit::AddColumn {
+                index: 0,
+                column: Arc::new(column),
+                hidden: false,
+            })
+            .unwrap();
+        workbook
+            .apply(WorkbookEdit::AddSheet { index: 0, sheet })
+            .unwrap();
+        let conversion = Expression::parse("=to_number(trim(x))").unwrap();
+        let retype = |value_type: ValueType, on_failure| WorkbookEdit::RetypeColumn {
+            sheet: "Codes".to_string(),
+            column: "code".to_string(),
+            value_type,
+            conversion: conversion.clone(),
+            on_failure,
+        };
+
+        let report = workbook
+            .preview_conversion("Codes", "code", &ValueType::Number.nullable(), &conversion)
+            .unwrap();
+        assert_eq!(report.converted, 3);
+        assert_eq!(
+            report
+                .failed
+                .iter()
+                .map(|failed| (failed.row, failed.value.clone()))
+                .collect::<Vec<_>>(),
+            [(2, Value::String("abc".to_string()))]
+        );
+        let error = workbook
+            .apply(retype(
+                ValueType::Number.nullable(),
+                ConversionFailure::Block,
+            ))
+            .unwrap_err();
+        assert_eq!(error.row(), Some(2));
+        assert!(workbook
+            .apply(retype(ValueType::Number, ConversionFailure::Null))
+            .is_err());
+        assert_eq!(
+            workbook
+                .sheet("Codes")
+                .unwrap()
+                .column("code")
+                .unwrap()
+                .value_type(),
+            &ValueType::String.nullable()
+        );
+
+        let undo = workbook
+            .apply(retype(
+                ValueType::Number.nullable(),
+                ConversionFailure::Null,
+            ))
+            .unwrap();
+        let code = workbook.sheet("Codes").unwrap().column("code").unwrap();
+        assert_eq!(
+            code.iter().collect::<Vec<_>>(),
+            [
+                Value::Number(1.5),
+                Value::Number(2.0),
+                Value::Null,
+                Value::Null
+            ]
+        );
+        workbook.apply(undo).unwrap();
+        assert_eq!(
+            workbook.sheet("Codes").unwrap().get("code", 0),
+            Some(Value::String(" 1.5 ".to_string()))
+        );
+
+        // Rules and formats are kept, and have to fit the new type
+        let set_rules = |rules: Vec<Rule>| WorkbookEdit::SetValidation {
+            sheet: "Codes".to_string(),
+            column: "code".to_string(),
+            validation: Some(Validation::new(rules, ValidationMode::Reject)),
+        };
+        let formats = vec![Format::Highlight {
+            condition: Expression::parse("=true").unwrap(),
+            style: CellStyle::default(),
+        }];
+        workbook
+            .apply(WorkbookEdit::SetFormats {
+                sheet: "Codes".to_string(),
+                column: "code".to_string(),
+                formats: formats.clone(),
+            })
+            .unwrap();
+        workbook
+            .apply(set_rules(vec![Rule::Pattern(Pattern::new(".*").unwrap())]))
+            .unwrap();
+        let error = workbook
+            .apply(retype(
+                ValueType::Number.nullable(),
+                ConversionFailure::Null,
+            ))
+            .unwrap_err();
+        assert_eq!(error.message(), r#"Pattern ".*" needs a String column"#);
+        workbook.apply(set_rules(vec![Rule::Unique])).unwrap();
+        let undo = workbook
+            .apply(retype(
+                ValueType::Number.nullable(),
+                ConversionFailure::Null,
+            ))
+            .unwrap();
+        let code = workbook.sheet("Codes").unwrap().column("code").unwrap();
+        assert_eq!(code.formats(), formats);
+        assert_eq!(
+            code.validation(),
+            Some(&Validation::new(vec![Rule::Unique], ValidationMode::Reject))
+        );
+        workbook.restore(undo).unwrap();
+
+        // Conversions have to give the new type
+        let error = workbook
+            .preview_conversion(
+                "Codes",
+                "code",
+                &ValueType::Number,
+                &Expression::parse("=trim(x)").unwrap(),
+            )
+            .unwrap_err();
+        assert!(error.message().starts_with("Conversion gives String?"));
+    }
+
+    #[test]
+    fn validation() {
+        let mut workbook = workbook();
+        let set_rules = |rules: Vec<Rule>, mode| WorkbookEdit::SetValidation {
+            sheet: "Orders".to_string(),
+            column: "amount".to_string(),
+            validation: Some(Validation::new(rules, mode)),
+        };
+        let rules = || {
+            vec![
+                Rule::Formula(Expression::parse("=x >= 0 & x < 1000").unwrap()),
+                Rule::Unique,
+            ]
+        };
+        let set_cell = |row: usize, value: i64| WorkbookEdit::Sheet {
+            name: "Orders".to_string(),
+            edit: SheetEdit::SetCell {
+                column: "amount".to_string(),
+                row,
+                value: Value::Integer(value),
+            },
+        };
+        let insert = |values: Vec<i64>| WorkbookEdit::Sheet {
+            name: "Orders".to_string(),
+            edit: SheetEdit::InsertRows {
+                at: 3,
+                count: values.len(),
+                values: BTreeMap::from([(
+                    "amount".to_string(),
+                    values.into_iter().map(Value::Integer).collect(),
+                )]),
+                ids: None,
+            },
+        };
+        let amounts = |workbook: &Workbook| {
+            workbook
+                .sheet("Orders")
+                .unwrap()
+                .column("amount")
+                .unwrap()
+                .iter()
+                .collect::<Vec<_>>()
+        };
+
+        workbook
+            .apply(set_rules(rules(), ValidationMode::Reject))
+            .unwrap();
+        let error = workbook.apply(set_cell(0, -5)).unwrap_err();
+        assert_eq!(error.row(), Some(0));
+        assert_eq!(error.message(), "-5 fails =x >= 0 & x < 1000");
+        assert_eq!(
+            workbook.apply(set_cell(0, 20)).unwrap_err().message(),
+            "20 is not unique"
+        );
+        assert!(workbook.apply(insert(vec![40, -1])).is_err());
+        assert_eq!(workbook.sheet("Orders").unwrap().row_count(), 3);
+        // Sorting moves the rows but keeps the rules
+        let sort = workbook.sort_by("Orders", "amount", true).unwrap();
+        let sorted = workbook.apply(sort).unwrap();
+        assert_eq!(
+            workbook.apply(set_cell(0, -5)).unwrap_err().message(),
+            "-5 fails =x >= 0 & x < 1000"
+        );
+        workbook.apply(sorted).unwrap();
+        let undo = workbook.apply(set_cell(0, 15)).unwrap();
+
+        // Existing values are not checked until asked for
+        workbook
+            .apply(set_rules(rules(), ValidationMode::Warn))
+            .unwrap();
+        workbook.apply(set_cell(1, 15)).unwrap();
+        let report = workbook.validate("Orders").unwrap();
+        assert_eq!(
+            report
+                .violations
+                .iter()
+                .map(|violation| (violation.row, violation.reason.as_str()))
+                .collect::<Vec<_>>(),
+            [(0, "15 is not unique"), (1, "15 is not unique")]
+        );
+        assert_eq!(
+            report.violations[1].id,
+            workbook.sheet("Orders").unwrap().row_id(1).unwrap()
+        );
+        workbook.apply(set_cell(1, 20)).unwrap();
+        // Undoing puts back values without checking them again
+        workbook
+            .apply(set_rules(rules(), ValidationMode::Reject))
+            .unwrap();
+        workbook.apply(set_cell(0, 20)).unwrap_err();
+        workbook.restore(undo).unwrap();
+
+        workbook
+            .apply(set_rules(rules(), ValidationMode::Quarantine))
+            .unwrap();
+        let undo = workbook.apply(insert(vec![40, 2000])).unwrap();
+        let values = amounts(&workbook);
+        assert_eq!(values[3], Value::Integer(40));
+        assert!(matches!(values[4], Value::Error(ErrorKind::Invalid, _)));
+        let report = workbook.validate("Orders").unwrap();
+        assert_eq!(report.violations.len(), 1);
+        assert_eq!(report.violations[0].row, 4);
+        assert_eq!(report.violations[0].reason, "2000 fails =x >= 0 & x < 1000");
+        workbook.apply(undo).unwrap();
+        assert!(workbook.validate("Orders").unwrap().is_empty());
+
+        // Rules have to fit the column
+        for rule in [
+            Rule::Formula(Expression::parse("=x + 1").unwrap()),
+            Rule::Allowed(vec![Value::String("10".to_string())]),
+            Rule::Pattern(Pattern::new("[0-9]+").unwrap()),
+        ] {
+            assert!(workbook
+                .apply(set_rules(vec![rule], ValidationMode::Reject))
+                .is_err());
+        }
+        let set_formula = |workbook: &mut Workbook| {
+            workbook.apply(WorkbookEdit::SetFormula {
+                sheet: "Orders".to_string(),
+                column: "amount".to_string(),
+                formula: Some(Expression::parse("=5").unwrap()),
+            })
+        };
+        assert_eq!(
+            set_formula(&mut workbook).unwrap_err().message(),
+            "Column has validation rules, remove them before giving it a formula"
+        );
+        let undo = workbook
+            .apply(WorkbookEdit::SetValidation {
+                sheet: "Orders".to_string(),
+                column: "amount".to_string(),
+                validation: None,
+            })
+            .unwrap();
+        let computed = set_formula(&mut workbook).unwrap();
+        workbook.apply(computed).unwrap();
+        workbook.apply(undo).unwrap();
+        assert!(Pattern::new("(").is_err());
+
+        let mut sheet = Sheet::new("Regions");
+        let column = Column::from_values(
+            "region",
+            ValueType::String,
+            [Value::String("EU".to_string())],
+        )
+        .unwrap();
+        sheet
+            .apply(SheetEdit::AddColumn {
+                index: 0,
+                column: Arc::new(column),
+                hidden: false,
+            })
+            .unwrap();
+        workbook
+            .apply(WorkbookEdit::AddSheet { index: 0, sheet })
+            .unwrap();
+        workbook
+            .apply(WorkbookEdit::SetValidation {
+                sheet: "Regions".to_string(),
+                column: "region".to_string(),
+                validation: Some(Validation::new(
+                    vec![
+                        Rule::Pattern(Pattern::new("[A-Z]{2}").unwrap()),
+                        Rule::Allowed(vec![
+                            Value::String("EU".to_string()),
+                            Value::String("UK".to_string()),
+                        ]),
+                    ],
+                    ValidationMode::Reject,
+                )),
+            })
+            .unwrap();
+        let set_region = |region: &str| WorkbookEdit::Sheet {
+            name: "Regions".to_string(),
+            edit: SheetEdit::SetCell {
+                column: "region".to_string(),
+                row: 0,
+                value: Value::String(region.to_string()),
+            },
+        };
+        assert_eq!(
+            workbook.apply(set_region("eu")).unwrap_err().message(),
+            r#""eu" is not matching "[A-Z]{2}""#
+        );
+        assert_eq!(
+            workbook.apply(set_region("US")).unwrap_err().message(),
+            r#""US" is not one of ["EU", "UK"]"#
+        );
+        workbook.apply(set_region("UK")).unwrap();
+    }
+
+    #[test]
+    fn conditional_formats() {
+        let mut workbook = workbook();
+        let (black, grey, white) = (
+            Colour::new(0, 0, 0),
+            Colour::new(128, 128, 128),
+            Colour::new(255, 255, 255),
+        );
+        let formats = |value: &str, condition: &str| WorkbookEdit::SetFormats {
+            sheet: "Orders".to_string(),
+            column: "amount".to_string(),
+            formats: vec![
+                Format::ColourScale {
+                    value: Expression::parse(value).unwrap(),
+                    low: black,
+                    mid: None,
+                    high: white,
+                },
+                Format::Highlight {
+                    condition: Expression::parse(condition).unwrap(),
+                    style: CellStyle {
+                        bold: true,
+                        ..Default::default()
+                    },
+                },
+            ],
+        };
+        let set_cell = |sheet: &str, column: &str, row: usize, value: i64| WorkbookEdit::Sheet {
+            name: sheet.to_string(),
+            edit: SheetEdit::SetCell {
+                column: column.to_string(),
+                row,
+                value: Value::Integer(value),
+            },
+        };
+        let styles = |workbook: &mut Workbook| {
+            let mut styles = workbook.styles("Orders", 0..10).unwrap();
+            assert_eq!(styles.len(), 1);
+            assert_eq!(styles[0].0, "amount");
+            styles
+                .pop()
+                .unwrap()
+                .1
+                .into_iter()
+                .map(|style| (style.background.unwrap(), style.bold))
+                .collect::<Vec<_>>()
+        };
+        let is_cached = |workbook: &Workbook, row: usize| {
+            let sheet = workbook.sheet("Orders").unwrap();
+            sheet.column("amount").unwrap().styles().get(row).is_some()
+        };
+
+        assert!(workbook
+            .apply(formats("=\"high\"", "=true"))
+            .unwrap_err()
+            .message()
+            .contains("needs a number"));
+        assert!(workbook.apply(formats("=x", "=x")).is_err());
+        let undo = workbook
+            .apply(formats("=x", "=x > sum(:'Rates'!'rate') * 10"))
+            .unwrap();
+        assert_eq!(
+            styles(&mut workbook),
+            vec![(black, false), (grey, false), (white, true)]
+        );
+        assert!(is_cached(&workbook, 2));
+
+        // Styles follow the columns their formats use
+        workbook.apply(set_cell("Rates", "rate", 0, 1)).unwrap();
+        assert!(!is_cached(&workbook, 0));
+        assert_eq!(
+            styles(&mut workbook),
+            vec![(black, false), (grey, true), (white, true)]
+        );
+
+        // A new value moves the range of the scale for every row
+        workbook.apply(set_cell("Orders", "amount", 0, 40)).unwrap();
+        assert!(!is_cached(&workbook, 2));
+        assert_eq!(
+            styles(&mut workbook),
+            vec![(white, true), (black, true), (grey, true)]
+        );
+        assert_eq!(workbook.styles("Orders", 1..2).unwrap()[0].1.len(), 1);
+
+        // Sorting keeps the formats, and the styles move with the rows
+        let sort = workbook.sort_by("Orders", "amount", false).unwrap();
+        workbook.apply(sort).unwrap();
+        assert!(!is_cached(&workbook, 0));
+        assert_eq!(
+            styles(&mut workbook),
+            vec![(black, true), (grey, true), (white, true)]
+        );
+
+        workbook.restore(undo).unwrap();
+        assert!(workbook.styles("Orders", 0..10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn rename_sheet() {
+        let mut workbook = workbook();
+        let mut expression = Expression::parse("=sum(:'Rates'!'rate') * -:'amount'").unwrap();
+        let undo = workbook
+            .apply(WorkbookEdit::RenameSheet {
+                from: "Rates".to_string(),
+                to: "FX rates".to_string(),
+            })
+            .unwrap();
+        assert!(expression.rename_sheet("Rates", "FX rates"));
+        assert_eq!(
+            expression.to_string(),
+            "=sum(:'FX rates'!'rate') * -:'amount'"
+        );
+        let context = workbook.context("Orders", Some(0)).unwrap();
+        assert_eq!(expression.eval(&context).unwrap(), Value::Integer(-20));
+
+        assert!(workbook
+            .apply(WorkbookEdit::RenameSheet {
+                from: "Orders".to_string(),
+                to: "FX rates".to_string(),
+            })
+            .is_err());
+        workbook.apply(undo).unwrap();
+        assert!(workbook.sheet("Rates").is_some());
+
+        set(&mut workbook, "rate", "=sum(:'Rates'!'rate')").unwrap();
+        workbook
+            .apply(WorkbookEdit::RenameSheet {
+                from: "Rates".to_string(),
+                to: "FX".to_string(),
+            })
+            .unwrap();
+        match workbook.workspace().get("rate").unwrap().definition() {
+            EntryDefinition::Expression(expression) => {
+                assert_eq!(expression.to_string(), "=sum(:'FX'!'rate')")
+            }
+            other => panic!("unexpected definition {:?}", other),
+        }
+        assert_eq!(
+            workbook.workspace().get("rate").unwrap().value(),
+            &Value::Integer(2)
+        );
+
+        // So do the expressions columns keep
+        workbook
+            .apply(WorkbookEdit::SetFormula {
+                sheet: "Orders".to_string(),
+                column: "converted".to_string(),
+                formula: Some(Expression::parse("=:'amount' * sum(:'FX'!'rate')").unwrap()),
+            })
+            .unwrap();
+        let rule = Rule::Formula(Expression::parse("=x <= sum(:'FX'!'rate') * 100").unwrap());
+        workbook
+            .apply(WorkbookEdit::SetValidation {
+                sheet: "Orders".to_string(),
+                column: "amount".to_string(),
+                validation: Some(Validation::new(vec![rule], ValidationMode::Reject)),
+            })
+            .unwrap();
+        workbook
+            .apply(WorkbookEdit::SetFormats {
+                sheet: "Orders".to_string(),
+                column: "amount".to_string(),
+                formats: vec![
+                    Format::DataBar {
+                        value: Expression::parse("=x / sum(:'FX'!'rate')").unwrap(),
+                        colour: Colour::new(0, 0, 255),
+                    },
+                    Format::Highlight {
+                        condition: Expression::parse("=x > sum(:'FX'!'rate') * 5").unwrap(),
+                        style: CellStyle::default(),
+                    },
+                ],
+            })
+            .unwrap();
+        let kept = |workbook: &Workbook| {
+            let orders = workbook.sheet("Orders").unwrap();
+            ["converted", "amount"]
+                .into_iter()
+                .flat_map(|column| orders.column(column).unwrap().expressions())
+                .map(|expression| expression.to_string())
+                .collect::<Vec<_>>()
+        };
+        let undo = workbook
+            .apply(WorkbookEdit::RenameSheet {
+                from: "FX".to_string(),
+                to: "Rates".to_string(),
+            })
+            .unwrap();
+        assert_eq!(
+            kept(&workbook),
+            [
+                "=:'amount' * sum(:'Rates'!'rate')",
+                "=x <= sum(:'Rates'!'rate') * 100",
+                "=x / sum(:'Rates'!'rate')",
+                "=x > sum(:'Rates'!'rate') * 5"
+            ]
+        );
+        assert_eq!(
+            workbook.value("Orders", "converted", 1).unwrap(),
+            Value::Integer(40)
+        );
+        let set_amount = |value: i64| WorkbookEdit::Sheet {
+            name: "Orders".to_string(),
+            edit: SheetEdit::SetCell {
+                column: "amount".to_string(),
+                row: 0,
+                value: Value::Integer(value),
+            },
+        };
+        assert!(workbook.apply(set_amount(500)).is_err());
+        workbook.apply(set_amount(200)).unwrap();
+        workbook.apply(undo).unwrap();
+        assert_eq!(
+            kept(&workbook),
+            [
+                "=:'amount' * sum(:'FX'!'rate')",
+                "=x <= sum(:'FX'!'rate') * 100",
+                "=x / sum(:'FX'!'rate')",
+                "=x > sum(:'FX'!'rate') * 5"
+            ]
+        );
+    }
+}
@@ -0,0 +1,112 @@
+use std::collections::BTreeMap;
+
+use crate::expression::{Expression, Value, ValueType};
+
+/// What a workspace entry is set to
+#[derive(Debug, Clone, PartialEq)]
+pub enum EntryDefinition {
+    Value(Value),
+    Expression(Expression),
+}
+
+/// A named value in the workspace, with the type and value it currently
+/// evaluates to
+#[derive(Debug, Clone, PartialEq)]
+pub struct WorkspaceEntry {
+    definition: EntryDefinition,
+    value_type: ValueType,
+    value: Value,
+}
+
+impl WorkspaceEntry {
+    pub fn definition(&self) -> &EntryDefinition {
+        &self.definition
+    }
+
+    pub fn value_type(&self) -> &ValueType {
+        &self.value_type
+    }
+
+    pub fn value(&self) -> &Value {
+        &self.value
+    }
+}
+
+/// Named values shared by every sheet in a workbook, such as tax and
+/// exchange rates. Entries are available to any expression as variables.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Workspace {
+    entries: BTreeMap<String, WorkspaceEntry>,
+}
+
+impl Workspace {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&WorkspaceEntry> {
+        self.entries.get(name)
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = (&str, &WorkspaceEntry)> {
+        self.entries
+            .iter()
+            .map(|(name, entry)| (name.as_str(), entry))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Replaces an entry without checking it, returning the old definition.
+    /// The entry's type and value are only set once it is checked.
+    pub(super) fn define(
+        &mut self,
+        name: &str,
+        definition: Option<EntryDefinition>,
+    ) -> Option<EntryDefinition> {
+        let previous = match definition {
+            Some(definition) => {
+                let value_type = match &definition {
+                    EntryDefinition::Value(value) => value.value_type(),
+                    EntryDefinition::Expression(_) => ValueType::Null,
+                };
+                self.entries.insert(
+                    name.to_string(),
+                    WorkspaceEntry {
+                        definition,
+                        value_type,
+                        value: Value::Null,
+                    },
+                )
+            }
+            None => self.entries.remove(name),
+        };
+        previous.map(|entry| entry.definition)
+    }
+
+    pub(super) fn set_type(&mut self, name: &str, value_type: ValueType) {
+        if let Some(entry) = self.entries.get_mut(name) {
+            entry.value_type = value_type;
+        }
+    }
+
+    pub(super) fn set_value(&mut self, name: &str, value: Value) {
+        if let Some(entry) = self.entries.get_mut(name) {
+            entry.value = value;
+        }
+    }
+
+    pub(super) fn expressions_mut(&mut self) -> impl Iterator<Item = &mut Expression> {
+        self.entries
+            .values_mut()
+            .filter_map(|entry| match &mut entry.definition {
+                EntryDefinition::Expression(expression) => Some(expression),
+                EntryDefinition::Value(_) => None,
+            })
+    }
+}